@@ -0,0 +1,176 @@
+use crate::profile::Emote;
+use color_eyre::eyre::{bail, Result, WrapErr};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Map of emote name to the name of the folder it goes in, e.g.:
+///
+/// ```json
+/// {
+///   "small9cm": "Reactions",
+///   "yay": "Reactions",
+///   "gm": "Greetings"
+/// }
+/// ```
+///
+/// Emotes that aren't listed go in the [`crate::profile::DEFAULT_CATEGORY`] folder.
+pub type Categories = HashMap<String, String>;
+
+pub fn read_categories(path: &Path) -> Result<Categories> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read category file {:?}", path))?;
+
+    let categories = serde_json::from_str::<Categories>(&contents)
+        .with_context(|| format!("Failed to parse category file {:?}", path))?;
+    if let Some((name, _)) = categories.iter().find(|(_, c)| c.trim().is_empty()) {
+        bail!("Category for {:?} in {:?} is empty", name, path);
+    }
+
+    Ok(categories)
+}
+
+/// Sets the category of the matching emotes (by case-insensitive name). Returns the names in the
+/// file that didn't match an emote.
+pub fn apply_categories(emotes: &mut [Emote], categories: &Categories) -> Vec<String> {
+    let categories = categories
+        .iter()
+        .map(|(name, category)| (name.to_lowercase(), (name, category.trim())))
+        .collect::<HashMap<_, _>>();
+
+    for emote in emotes.iter_mut() {
+        if let Some((_, category)) = categories.get(&emote.name.to_lowercase()) {
+            emote.category = Some((*category).to_owned());
+        }
+    }
+
+    let mut unmatched = categories
+        .iter()
+        .filter(|(lower_name, _)| {
+            !emotes
+                .iter()
+                .any(|emote| emote.name.to_lowercase() == **lower_name)
+        })
+        .map(|(_, (name, _))| (*name).clone())
+        .collect::<Vec<_>>();
+    unmatched.sort();
+    unmatched
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::tests::{emote_images, options, placeholder_images};
+    use crate::profile::{
+        DeviceModel, NavLayout, Position, ProfileOptions, ProfilesWithImages, Settings,
+    };
+
+    #[test]
+    fn emotes_in_category_folders() -> Result<()> {
+        let mut emotes = ["small9cm", "yay", "gm", "wave", "pomu"]
+            .iter()
+            .map(|name| Emote {
+                name: (*name).to_owned(),
+                url: format!("http://example.com/{}.png", name),
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+
+        let categories = serde_json::from_str::<Categories>(
+            r#"{
+                "Small9cm": "Reactions",
+                "gm": "Greetings",
+                "yay": "Reactions",
+                "wave": " Greetings ",
+                "missing": "Reactions"
+            }"#,
+        )?;
+        let unmatched = apply_categories(&mut emotes, &categories);
+        assert_eq!(unmatched, vec!["missing"]);
+
+        let images = placeholder_images(emotes);
+        let options = ProfileOptions {
+            categorize: true,
+            ..options(DeviceModel::Standard, NavLayout::Minimal)
+        };
+        let profiles = ProfilesWithImages::from_images(images, &options);
+
+        // The root page links to each folder, in the order that the categories first appear
+        let (root_uuid, root) = &profiles.manifests[0];
+        assert_eq!(*root_uuid, options.root_profile_uuid);
+        let mut folders = root
+            .actions
+            .iter()
+            .map(|(pos, action)| match action.settings {
+                Settings::OpenChild { profile_uuid } => {
+                    (*pos, action.states[0].title.as_str(), profile_uuid)
+                }
+                _ => panic!("unexpected action on the root page: {}", action.name),
+            })
+            .collect::<Vec<_>>();
+        folders.sort_by_key(|(pos, _, _)| (pos.y, pos.x));
+        let titles = folders
+            .iter()
+            .map(|(pos, title, _)| (*pos, *title))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            titles,
+            vec![
+                (Position::new(0, 0), "Reactions"),
+                (Position::new(1, 0), "Greetings"),
+                (Position::new(2, 0), "Other"),
+            ]
+        );
+
+        let names = profiles
+            .categories
+            .iter()
+            .map(|c| c.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["Reactions", "Greetings", "Other"]);
+
+        for (category, (_, _, folder_uuid)) in profiles.categories.iter().zip(&folders) {
+            assert_eq!(category.pages, 1);
+            assert_eq!(profiles.parent_page(category.first_page), Some(0));
+
+            let (uuid, manifest) = &profiles.manifests[category.first_page];
+            assert_eq!(uuid, folder_uuid);
+            assert!(matches!(
+                manifest.actions[&Position::new(0, 0)].settings,
+                Settings::BackToParent {}
+            ));
+
+            let mut emotes = manifest
+                .actions
+                .values()
+                .filter_map(|action| Some(action.emote.as_ref()?.name.as_str()))
+                .collect::<Vec<_>>();
+            emotes.sort();
+            let expected = match category.name.as_str() {
+                "Reactions" => vec!["small9cm", "yay"],
+                "Greetings" => vec!["gm", "wave"],
+                _ => vec!["pomu"],
+            };
+            assert_eq!(emotes, expected);
+        }
+        assert!(profiles.dropped_categories.is_empty());
+
+        // Categories that don't fit on the root page are reported
+        let mut images = emote_images(8);
+        for (i, image) in images.iter_mut().enumerate() {
+            image.emote.category = Some(format!("Category {}", i));
+        }
+        let mini = ProfileOptions {
+            model: DeviceModel::Mini,
+            ..options
+        };
+        let profiles = ProfilesWithImages::from_images(images, &mini);
+        assert_eq!(profiles.categories.len(), 6);
+        assert_eq!(
+            profiles.dropped_categories,
+            vec!["Category 6", "Category 7"]
+        );
+
+        Ok(())
+    }
+}
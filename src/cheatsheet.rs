@@ -0,0 +1,66 @@
+use crate::profile::{ProfilesWithImages, Settings};
+
+/// Renders a Markdown table for each page of emotes, listing the emote and pasted text on each key.
+/// Pages without emotes (such as an index page) are skipped.
+pub fn render(profiles: &ProfilesWithImages, name: &str) -> String {
+    let mut out = format!("# {}\n", name);
+    let mut page = 0;
+
+    for (_, manifest) in profiles.manifests.iter() {
+        let mut rows = manifest
+            .actions
+            .iter()
+            .filter_map(|(pos, action)| match (&action.settings, &action.emote) {
+                (Settings::Text { pasted_text, .. }, Some(emote)) => {
+                    Some((pos, &emote.name, pasted_text))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        rows.sort_by_key(|(pos, _, _)| (pos.y, pos.x));
+
+        if rows.is_empty() {
+            continue;
+        }
+
+        page += 1;
+        out.push_str(&format!(
+            "\n## Page {}\n\n| Key | Emote | Code |\n| --- | --- | --- |\n",
+            page
+        ));
+
+        for (pos, name, pasted_text) in rows {
+            out.push_str(&format!("| {} | {} | `{}` |\n", pos, name, pasted_text));
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::tests::{emote_images, options};
+    use crate::profile::{ActionOptions, DeviceModel, NameCase, NavLayout, ProfileOptions};
+
+    #[test]
+    fn lists_every_emote() {
+        let options = ProfileOptions {
+            action: ActionOptions {
+                prefix: "pomu".into(),
+                name_case: NameCase::CapitalizeFirst,
+                ..Default::default()
+            },
+            ..options(DeviceModel::Standard, NavLayout::Minimal)
+        };
+
+        let profiles = ProfilesWithImages::from_images(emote_images(14), &options);
+        let cheatsheet = render(&profiles, "Emotes");
+
+        let rows = cheatsheet.lines().filter(|line| line.contains("`:_pomu"));
+        assert_eq!(rows.count(), 14);
+        assert!(cheatsheet.contains("## Page 2"));
+        assert!(cheatsheet.contains("| 1,0 | emote0 | `:_pomuEmote0:` |"));
+        assert!(cheatsheet.contains("| 2,0 | emote13 | `:_pomuEmote13:` |"));
+    }
+}
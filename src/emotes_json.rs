@@ -0,0 +1,164 @@
+use crate::fetch::check_http_url;
+use crate::profile::Emote;
+use color_eyre::eyre::{bail, Result, WrapErr};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+/// Reads a list of emotes from a JSON file, as an alternative to a YouTube memberships page, e.g.:
+///
+/// ```json
+/// [{ "name": "small9cm", "url": "https://example.com/small9cm.png", "tier": "Member" }]
+/// ```
+///
+/// `tier` and `id` are optional. Labels, pasted text and the like are set with the overrides file.
+pub fn read_emotes_json(path: &Path) -> Result<Vec<Emote>> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read emotes file {:?}", path))?;
+
+    let emotes = serde_json::from_str::<Vec<JsonEmote>>(&contents)
+        .with_context(|| format!("Failed to parse emotes file {:?}", path))?
+        .into_iter()
+        .map(Emote::from)
+        .collect::<Vec<_>>();
+
+    for (index, emote) in emotes.iter().enumerate() {
+        if emote.name.trim().is_empty() || emote.url.trim().is_empty() {
+            bail!(
+                "Emote at index {} in {:?} must have a non-empty name and url",
+                index,
+                path
+            );
+        }
+        check_http_url(&emote.url)
+            .with_context(|| format!("Invalid url for emote {} in {:?}", emote.name, path))?;
+    }
+
+    Ok(emotes)
+}
+
+/// An emote in the emotes file, which only has the fields of the documented schema
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonEmote {
+    name: String,
+    url: String,
+    #[serde(default)]
+    tier: Option<String>,
+    #[serde(default)]
+    id: Option<String>,
+}
+
+impl From<JsonEmote> for Emote {
+    fn from(emote: JsonEmote) -> Self {
+        Self {
+            name: emote.name,
+            url: emote.url,
+            tier: emote.tier,
+            id: emote.id,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetch::StubFetcher;
+    use crate::processing::tests::fixture_png;
+    use crate::profile::tests::options;
+    use crate::profile::{DeviceModel, NavLayout, ProfilesWithImages, Settings};
+
+    #[tokio::test]
+    async fn emotes_json_round_trip() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("emotes.json");
+
+        let input = r#"[
+            { "name": "Kappa", "url": "https://example.com/kappa.png", "tier": "Tier 1" },
+            { "name": "monkaS", "url": "https://example.com/monkas.png" }
+        ]"#;
+        fs::write(&path, input)?;
+
+        let emotes = read_emotes_json(&path)?;
+        assert_eq!(emotes.len(), 2);
+        assert_eq!(emotes[0].tier.as_deref(), Some("Tier 1"));
+        assert_eq!(emotes[1].tier, None);
+
+        let serialized = serde_json::to_value(&emotes)?;
+        assert_eq!(
+            serialized,
+            serde_json::from_str::<serde_json::Value>(input)?
+        );
+
+        let fetcher = StubFetcher::new(
+            emotes
+                .iter()
+                .map(|emote| (emote.url.as_str(), fixture_png(72, 72))),
+        );
+        let options = options(DeviceModel::Standard, NavLayout::Minimal);
+        let profiles = ProfilesWithImages::new(&fetcher, emotes, &options).await?;
+
+        let mut pasted_texts = profiles.manifests[0]
+            .1
+            .actions
+            .values()
+            .filter_map(|action| match &action.settings {
+                Settings::Text { pasted_text, .. } => Some(pasted_text.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        pasted_texts.sort_unstable();
+        assert_eq!(pasted_texts, vec![":_Kappa:", ":_monkaS:"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_emotes_json() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("emotes.json");
+
+        fs::write(
+            &path,
+            r#"[{ "name": "", "url": "https://example.com/a.png" }]"#,
+        )?;
+        assert!(read_emotes_json(&path).is_err());
+
+        // Local files can't be pulled into the profile
+        fs::write(&path, r#"[{ "name": "a", "url": "file:///etc/passwd" }]"#)?;
+        let error = read_emotes_json(&path).unwrap_err();
+        assert!(
+            matches!(
+                error.downcast_ref::<crate::error::Error>(),
+                Some(crate::error::Error::UnsupportedUrl { .. })
+            ),
+            "{:?}",
+            error
+        );
+
+        // Unknown fields are rejected, so that typos don't go unnoticed
+        fs::write(
+            &path,
+            r#"[{ "name": "a", "url": "https://example.com/a.png", "bogus": 1 }]"#,
+        )?;
+        let error = format!("{:#}", read_emotes_json(&path).unwrap_err());
+        assert!(error.contains("unknown field `bogus`"), "{}", error);
+
+        // Fields that the overrides file sets aren't part of the schema
+        fs::write(
+            &path,
+            r#"[{ "name": "a", "url": "https://example.com/a.png", "title_color": "red" }]"#,
+        )?;
+        let error = format!("{:#}", read_emotes_json(&path).unwrap_err());
+        assert!(error.contains("unknown field `title_color`"), "{}", error);
+
+        fs::write(
+            &path,
+            r#"[{ "name": "a", "url": "https://example.com/a.png", "id": "abc" }]"#,
+        )?;
+        assert_eq!(read_emotes_json(&path)?[0].id.as_deref(), Some("abc"));
+
+        Ok(())
+    }
+}
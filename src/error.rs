@@ -0,0 +1,129 @@
+use reqwest::StatusCode;
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// Failures in the main steps of generating a profile, for callers that need to tell them apart.
+/// Everything else is reported with `eyre`.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// The HTML doesn't contain `ytInitialData`, or it isn't in the expected form
+    #[error("{message}")]
+    HtmlParseFailed {
+        message: &'static str,
+        #[source]
+        source: Option<serde_json::Error>,
+    },
+
+    /// The memberships page was parsed, but it doesn't list any emotes
+    #[error("{reason}")]
+    NoEmotesFound { reason: NoEmotesReason },
+
+    /// A page or image couldn't be downloaded
+    #[error("{}", download_message(.url, .status, .snippet))]
+    DownloadFailed {
+        url: String,
+        /// Status of the response, if one was received
+        status: Option<StatusCode>,
+        /// Start of the response body, if it was text
+        snippet: Option<String>,
+        #[source]
+        source: Option<reqwest::Error>,
+    },
+
+    /// A download was redirected more times than allowed by `--max-redirects`, e.g. because of a
+    /// redirect loop
+    #[error("URL {url} was redirected more than {limit} times. Use --max-redirects to allow more")]
+    TooManyRedirects { url: String, limit: usize },
+
+    /// An image URL that isn't `http` or `https`. Other schemes, such as `file://`, could read
+    /// local files into the generated profile, so only `--images-dir` can use them.
+    #[error("URL {url} isn't an http or https URL")]
+    UnsupportedUrl { url: String },
+
+    /// With `--offline`, an image that isn't in the cache
+    #[error("Image from URL {url} isn't in the cache, and can't be downloaded with --offline")]
+    NotCached { url: String },
+
+    /// An image from `--images-dir` couldn't be read
+    #[error("Failed to read image file {path:?}")]
+    ImageFileUnreadable {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Unknown device model {0}")]
+    InvalidDeviceModel(String),
+
+    #[error(
+        "Output directory {path:?} is not writable. Check that you have permission to write to \
+        it, and that it isn't locked by another program (e.g., try closing the Stream Deck \
+        application)."
+    )]
+    OutputPathUnavailable {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+impl Error {
+    pub fn html_parse(message: &'static str) -> Self {
+        Self::HtmlParseFailed {
+            message,
+            source: None,
+        }
+    }
+
+    /// Download failure for a request that didn't get a response, or whose body couldn't be read
+    pub fn download(url: &str, source: reqwest::Error) -> Self {
+        let redirect_limit = std::error::Error::source(&source)
+            .and_then(|e| e.downcast_ref::<RedirectLimitExceeded>());
+        if let Some(RedirectLimitExceeded(limit)) = redirect_limit {
+            return Self::TooManyRedirects {
+                url: url.to_owned(),
+                limit: *limit,
+            };
+        }
+
+        Self::DownloadFailed {
+            url: url.to_owned(),
+            status: None,
+            snippet: None,
+            source: Some(source),
+        }
+    }
+}
+
+/// Returned by the HTTP client's redirect policy, so that `Error::download` can tell it apart from
+/// other failures
+#[derive(Debug, Error)]
+#[error("more than {0} redirects")]
+pub struct RedirectLimitExceeded(pub usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Error)]
+pub enum NoEmotesReason {
+    #[error(
+        "the page appears to have been saved while logged out. \
+        Save the memberships page again while logged in as a member of the channel"
+    )]
+    LoggedOut,
+    #[error(
+        "the page doesn't contain any membership perks. \
+        Save the memberships page again while logged in as a member of the channel"
+    )]
+    NoPerks,
+    #[error("failed to find emotes in JSON")]
+    NotInPerks,
+}
+
+fn download_message(url: &str, status: &Option<StatusCode>, snippet: &Option<String>) -> String {
+    match (status, snippet) {
+        (Some(status), Some(snippet)) => format!(
+            "Received non-success code {} from URL {}: {}",
+            status, url, snippet
+        ),
+        (Some(status), None) => format!("Received non-success code {} from URL {}", status, url),
+        (None, _) => format!("Failed to download from URL {}", url),
+    }
+}
@@ -0,0 +1,104 @@
+use crate::profile::Emote;
+use color_eyre::eyre::{bail, Result, WrapErr};
+use reqwest::Url;
+use std::fs;
+use std::path::Path;
+
+/// Extensions of the image files that are used as emotes
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif", "webp"];
+
+/// Reads a list of emotes from the image files in a directory, as an alternative to a YouTube
+/// memberships page. Each emote is named after its file (e.g., `small9cm.png` is `small9cm`), and
+/// its URL is a `file://` URL, which [`crate::fetch::FileFetcher`] reads instead of downloading.
+/// Emotes are sorted by file name, and other files are ignored.
+pub fn read_images_dir(dir: &Path) -> Result<Vec<Emote>> {
+    let dir =
+        fs::canonicalize(dir).with_context(|| format!("Failed to read directory {:?}", dir))?;
+    let entries =
+        fs::read_dir(&dir).with_context(|| format!("Failed to read directory {:?}", dir))?;
+
+    let mut paths = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let is_image = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.to_lowercase().as_str()));
+        if path.is_file() && is_image {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let emotes = paths
+        .into_iter()
+        .filter_map(|path| {
+            let name = path.file_stem()?.to_str()?.to_owned();
+            let url = Url::from_file_path(&path).ok()?;
+            Some(Emote {
+                name,
+                url: url.into(),
+                ..Default::default()
+            })
+        })
+        .collect::<Vec<_>>();
+
+    if emotes.is_empty() {
+        bail!(
+            "No images found in {:?}. Expected files ending in one of: {}",
+            dir,
+            IMAGE_EXTENSIONS.join(", ")
+        );
+    }
+
+    Ok(emotes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetch::{FileFetcher, StubFetcher};
+    use crate::processing::image_dimensions;
+    use crate::processing::tests::fixture_png;
+    use crate::profile::tests::options;
+    use crate::profile::{DeviceModel, NavLayout, Position, ProfilesWithImages};
+
+    #[tokio::test]
+    async fn emotes_from_images() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("small9cm.png"), fixture_png(32, 16))?;
+        fs::write(dir.path().join("ANGRY.PNG"), fixture_png(16, 32))?;
+        fs::write(dir.path().join("notes.txt"), "not an emote")?;
+        fs::create_dir(dir.path().join("nested.png"))?;
+
+        let emotes = read_images_dir(dir.path())?;
+        let names = emotes.iter().map(|e| e.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["ANGRY", "small9cm"]);
+        assert!(emotes[0].url.starts_with("file://"), "{}", emotes[0].url);
+
+        // The images are read from the files, without going through the inner fetcher
+        let fetcher = FileFetcher {
+            inner: StubFetcher::default(),
+        };
+        let options = options(DeviceModel::Standard, NavLayout::Minimal);
+        let profiles = ProfilesWithImages::new(&fetcher, emotes, &options).await?;
+        assert!(fetcher.inner.requested().is_empty());
+
+        // Each image has a different shape, to tell them apart once they're resized
+        let actions = &profiles.manifests[0].1.actions;
+        let dimensions = |x| image_dimensions(actions[&Position::new(x, 0)].image.as_deref()?);
+        assert_eq!(dimensions(1), Some((36, 72)));
+        assert_eq!(dimensions(2), Some((72, 36)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn empty_images_dir() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("notes.txt"), "not an emote")?;
+        let error = read_images_dir(dir.path()).unwrap_err();
+        assert!(error.to_string().contains("No images found"), "{}", error);
+        Ok(())
+    }
+}
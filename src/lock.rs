@@ -0,0 +1,100 @@
+use color_eyre::eyre::{bail, Result, WrapErr};
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tracing::warn;
+
+/// Name of the lock file, in the output directory
+const LOCK_FILE: &str = ".streamdeck-emotes.lock";
+
+/// Locks older than this are assumed to be left over from a run that crashed
+const STALE_AFTER: Duration = Duration::from_secs(10 * 60);
+
+/// Advisory lock on an output directory, so that two runs don't write profiles to it at the same
+/// time. The lock file is removed when this is dropped.
+#[derive(Debug)]
+pub struct OutputLock {
+    path: PathBuf,
+}
+
+impl OutputLock {
+    /// Creates the lock file, and the directory if it doesn't exist yet
+    pub fn acquire(dir: &Path) -> Result<Self> {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create directory {:?}", dir))?;
+        let path = dir.join(LOCK_FILE);
+
+        match Self::create(&path) {
+            Err(e) if e.kind() == ErrorKind::AlreadyExists && is_stale(&path) => {
+                warn!(?path, "Removing stale lock file");
+                fs::remove_file(&path)
+                    .with_context(|| format!("Failed to remove stale lock file {:?}", path))?;
+                Self::create(&path)
+            }
+            result => result,
+        }
+        .or_else(|e| {
+            if e.kind() == ErrorKind::AlreadyExists {
+                bail!(
+                    "Another instance is already writing to {:?}. If it isn't running, \
+                    delete the lock file {:?}",
+                    dir,
+                    path
+                )
+            }
+            Err(e).with_context(|| format!("Failed to create lock file {:?}", path))
+        })
+    }
+
+    fn create(path: &Path) -> std::io::Result<Self> {
+        let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+        writeln!(file, "{}", std::process::id())?;
+        Ok(Self {
+            path: path.to_owned(),
+        })
+    }
+}
+
+impl Drop for OutputLock {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!(path = ?self.path, error = %e, "Failed to remove lock file");
+        }
+    }
+}
+
+fn is_stale(path: &Path) -> bool {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_some_and(|age| age > STALE_AFTER)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn second_lock_fails() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+
+        let lock = OutputLock::acquire(dir.path())?;
+        let error = OutputLock::acquire(dir.path()).unwrap_err();
+        assert!(error.to_string().contains("Another instance"), "{}", error);
+
+        drop(lock);
+        assert!(!dir.path().join(LOCK_FILE).exists());
+        let lock = OutputLock::acquire(dir.path())?;
+
+        // Locks that were never cleaned up are replaced once they're old enough
+        std::mem::forget(lock);
+        let file = fs::File::options()
+            .write(true)
+            .open(dir.path().join(LOCK_FILE))?;
+        file.set_modified(SystemTime::now() - STALE_AFTER * 2)?;
+        OutputLock::acquire(dir.path())?;
+
+        Ok(())
+    }
+}
@@ -1,298 +1,4307 @@
+mod categories;
+mod cheatsheet;
+mod emotes_json;
+mod error;
+mod fetch;
+mod images_dir;
+mod lock;
+mod overrides;
+#[cfg(feature = "interactive")]
+mod picker;
+mod processing;
 mod profile;
+mod report;
+mod snapshot;
+mod stage;
+mod strings;
+#[cfg(feature = "watch")]
+mod watch;
 mod youtube;
 
-use crate::profile::{DeviceModel, ProfilesWithImages};
-use color_eyre::eyre::{bail, Result, WrapErr};
+use crate::fetch::ImageFetcher;
+use crate::processing::{
+    BadgeCorner, BadgeSize, BadgeStyle, EncodedFormat, ImageOptions, KeyImageFormat, KeyPadding,
+    ResizeFilter,
+};
+use crate::profile::{
+    Action, ActionOptions, DeviceModel, Emote, EmoteImage, EmoteSort, ExtraAction, LabelSize,
+    NameCase, NavLayout, NavStyle, Position, ProfileManifest, ProfileOptions, ProfilesWithImages,
+    TierColor,
+};
+use crate::report::OutputFormat;
+use bytes::Bytes;
+use color_eyre::eyre::{bail, ContextCompat, Result, WrapErr};
 use fs_extra::dir::CopyOptions;
+use reqwest::Url;
 use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap};
+use std::ffi::{OsStr, OsString};
 use std::fs;
-use std::io::Read;
-use std::path::PathBuf;
+use std::io::{BufRead, IsTerminal, Read, Write};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::str::FromStr;
+use std::sync::Arc;
+use structopt::clap::Shell;
 use structopt::StructOpt;
-use tracing::{info, warn};
+use tracing::{debug, info, warn, Level};
 use uuid::Uuid;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     color_eyre::install()?;
-    tracing_subscriber::fmt::fmt().init();
+
+    if std::env::args().nth(1).as_deref() == Some("completions") {
+        let Completions::Completions { shell } = Completions::from_args();
+        return write_completions(shell, &mut std::io::stdout());
+    }
 
     let mut args = Args::from_args();
-    if let Some(prefix) = args.prefix.strip_prefix('_') {
-        warn!(%prefix, "Ignoring leading underscore in prefix");
-        args.prefix = prefix.to_owned();
-    }
-
-    // Find output path based on platform
-    let root_path = if let Some(ref path) = args.out {
-        path.clone()
-    } else if let Some(home) = dirs::home_dir() {
-        if cfg!(target_os = "macos") {
-            home.join("Library")
-                .join("Application Support")
-                .join("com.elgato.StreamDeck")
-                .join("ProfilesV2")
-                .to_path_buf()
-        } else if cfg!(target_os = "windows") {
-            home.join("AppData")
-                .join("Roaming")
-                .join("Elgato")
-                .join("StreamDeck")
-                .join("ProfilesV2")
-                .to_path_buf()
-        } else {
-            bail!("No output path specified")
-        }
-    } else {
-        bail!("Could not find home directory")
-    };
 
-    // Parse HTML file to get list of emotes
-    let html = if args.html_file.to_str() == Some("-") {
-        let mut buf = String::new();
-        std::io::stdin().read_to_string(&mut buf)?;
-        buf
-    } else {
-        fs::read_to_string(&args.html_file)
-            .with_context(|| format!("Failed to read file {:?}", &args.html_file))?
-    };
+    // Logs go to stderr, so that stdout can be used for output (e.g., `--out -`)
+    tracing_subscriber::fmt::fmt()
+        .with_writer(std::io::stderr)
+        .with_max_level(if args.quiet { Level::WARN } else { Level::INFO })
+        .init();
 
-    // Reorder emotes, prioritizing ones specified in `prioritize`
-    let mut emotes = youtube::parse_emotes(&html)?;
-    let emotes_count = emotes.len();
-    emotes.sort_by_cached_key(|emote| {
-        let lower_name = emote.name.to_ascii_lowercase();
+    #[cfg(feature = "watch")]
+    if args.watch {
+        return watch(args).await;
+    }
 
-        if let Some(pos) = args
-            .prioritize
-            .iter()
-            .position(|name| name.to_ascii_lowercase() == lower_name)
-        {
-            return pos;
+    run(&mut args).await
+}
+
+/// Regenerates the profile each time the input files change, for `--watch`. Failed runs are
+/// logged, so that the input can be fixed without restarting.
+#[cfg(feature = "watch")]
+async fn watch(mut args: Args) -> Result<()> {
+    let paths = watched_paths(&args)?;
+    let mut watcher = watch::FileWatcher::new(&paths)?;
+
+    loop {
+        match run(&mut args).await {
+            // Later runs overwrite the profile from the first one
+            Ok(()) => args.force = true,
+            Err(e) => warn!(error = ?e, "Failed to generate profile"),
         }
+        watcher.changed().await?;
+        info!("Input changed, regenerating profile");
+    }
+}
 
-        if let Some(pos) = args
-            .deprioritize
-            .iter()
-            .position(|name| name.to_ascii_lowercase() == lower_name)
-        {
-            return pos + emotes_count + 1;
+/// Input files that `--watch` regenerates the profile for
+#[cfg(feature = "watch")]
+fn watched_paths(args: &Args) -> Result<Vec<PathBuf>> {
+    if args.html_file.as_deref() == Some(Path::new("-")) {
+        bail!("--watch can't be used with --html-file -, since stdin can't be watched");
+    }
+    if args.out.as_deref() == Some(Path::new("-")) {
+        bail!("--watch can't be used with --out -");
+    }
+
+    let paths = [
+        &args.html_file,
+        &args.emotes_json,
+        &args.overrides,
+        &args.category_file,
+        &args.prioritize_file,
+        &args.deprioritize_file,
+    ]
+    .iter()
+    .filter_map(|path| path.as_ref().cloned())
+    .collect::<Vec<_>>();
+
+    if paths.is_empty() {
+        bail!("--watch requires an input file, such as --html-file");
+    }
+    Ok(paths)
+}
+
+/// Generates the profile as specified by the arguments
+async fn run(args: &mut Args) -> Result<()> {
+    let prefix = profile::normalize_prefix(&args.prefix);
+    if prefix != args.prefix {
+        if args.strict {
+            bail!(
+                "Prefix {:?} should be given without decoration, e.g. {:?}",
+                args.prefix,
+                prefix
+            );
         }
+        warn!(given = %args.prefix, %prefix, "Normalized emote prefix");
+        args.prefix = prefix;
+    }
 
-        emotes_count
-    });
+    let cookies = match &args.cookies {
+        Some(path) => Some(Arc::new(fetch::read_cookies(path)?)),
+        None => None,
+    };
+    let client = fetch::build_client(args.proxy.as_ref(), cookies, args.max_redirects)?;
 
-    // Generate profiles
-    let profiles = ProfilesWithImages::new(
-        args.profile_uuid
-            .unwrap_or_else(|| profile::uuid_v5(&args.name, 0)),
-        args.model,
-        args.device_uuid,
-        args.name,
-        emotes,
-        &args.prefix,
-        args.include_labels,
-    )
-    .await?;
+    let existing_device = match &args.from_existing {
+        Some(path) => Some(read_existing_device(path)?),
+        None => None,
+    };
 
-    // Write profiles to filesystem
-    let mut root_profiles_path = root_path.clone();
-    let mut current_path = root_path;
-    let mut depth = 0;
+    // The codes and suggestions don't depend on the device
+    let devices = if args.print_codes || args.suggest_model || args.validate_urls {
+        Vec::new()
+    } else {
+        resolve_devices(
+            &args.device,
+            &args.model,
+            &args.device_uuid,
+            existing_device,
+        )?
+    };
+    if let Some(position) = args.start_position {
+        for (model, _) in &devices {
+            let (width, height) = model.size();
+            if position.x >= width || position.y >= height {
+                bail!(
+                    "--start-position {} is outside of the {}x{} grid of the {} model",
+                    position,
+                    width,
+                    height,
+                    model
+                );
+            }
+        }
+    }
 
-    let copy_options = CopyOptions {
-        overwrite: true,
-        copy_inside: true,
-        ..Default::default()
+    if devices.len() > 1 && (args.profile_uuid.is_some() || args.parent_profile_uuid.is_some()) {
+        bail!("--profile-uuid and --parent-profile-uuid can only be used with a single --model");
+    }
+
+    let root_path = match &args.out {
+        Some(path) => path.clone(),
+        None => profiles_dir(std::env::var_os(PROFILES_DIR_VAR), args.profiles_version)?,
     };
 
-    for (uuid, manifest) in profiles.manifests {
-        let sd_profile_dir = format!("{}.sdProfile", uuid.to_string().to_uppercase());
+    if args.check && args.out.as_deref() == Some(Path::new("-")) {
+        bail!("--check can't be used with --out -, since there are no existing files to compare");
+    }
 
-        if depth == 0 {
-            root_profiles_path = current_path.join(&sd_profile_dir).join("Profiles");
+    let writes_profile = !args.plan_only
+        && !args.print_codes
+        && !args.suggest_model
+        && !args.validate_urls
+        && !args.check;
+    if args.out.as_deref() != Some(Path::new("-")) && writes_profile {
+        check_writable(&root_path)?;
+    }
+
+    let (name, mut emotes) = if let Some(path) = &args.emotes_json {
+        let name = args
+            .name
+            .clone()
+            .context("--name must be specified when using --emotes-json")?;
+        (name, emotes_json::read_emotes_json(path)?)
+    } else if let Some(dir) = &args.images_dir {
+        let name = args
+            .name
+            .clone()
+            .context("--name must be specified when using --images-dir")?;
+        (name, images_dir::read_images_dir(dir)?)
+    } else {
+        let html = if let Some(url) = &args.channel_url {
+            fetch::fetch_page(&client, url).await?
         } else {
-            // Nested profiles have an additional `Profiles` directory
-            current_path.push("Profiles");
-        }
+            read_html_file(args)?
+        };
+        read_memberships_page(args, &html)?
+    };
 
-        current_path.push(&sd_profile_dir);
-        info!(path = ?current_path, "Creating profile directory");
+    if let Some(path) = &args.overrides {
+        let overrides = overrides::read_overrides(path)?;
+        let unmatched = overrides::apply_overrides(&mut emotes, &overrides);
+        if !unmatched.is_empty() {
+            if args.strict {
+                bail!("Overrides don't match any emote: {:?}", unmatched);
+            }
+            warn!(names = ?unmatched, "Overrides don't match any emote");
+        }
+    }
 
-        // After the initial profile installation, the Stream Deck application un-nests the
-        // directories. The app seems to ignore changes that we make to the un-nested profiles, so
-        // we have to move the directories back to the nested structure to make changes.
-        if depth >= 2 {
-            let src = root_profiles_path.join(&sd_profile_dir);
-            if let Err(e) = fs_extra::dir::move_dir(&src, &current_path, &copy_options) {
-                if !matches!(e.kind, fs_extra::error::ErrorKind::NotFound) {
-                    warn!(error = %e, "Failed to move existing nested profile");
-                }
-            } else {
-                info!(?src, dest = ?current_path, "Moved existing nested profile");
+    if let Some(path) = &args.category_file {
+        let categories = categories::read_categories(path)?;
+        let unmatched = categories::apply_categories(&mut emotes, &categories);
+        if !unmatched.is_empty() {
+            if args.strict {
+                bail!("Categories don't match any emote: {:?}", unmatched);
             }
+            warn!(names = ?unmatched, "Categories don't match any emote");
         }
+    }
 
-        fs::create_dir_all(&current_path)
-            .with_context(|| format!("Failed to create path {:?}", &current_path))?;
+    // The snapshot has every emote, before any are filtered out
+    let all_emotes = args.save_snapshot.as_ref().map(|_| emotes.clone());
 
-        let manifest_path = current_path.join("manifest.json");
-        let mut json = serde_json::to_value(&manifest)?;
+    if let Some(path) = &args.since {
+        let snapshot = snapshot::read_snapshot(path)?;
+        let new_names = snapshot::new_emote_names(&snapshot, &emotes);
+        info!(count = new_names.len(), names = ?new_names, "New emotes since the snapshot");
 
-        if !args.no_merge {
-            if let Err(e) = merge_manifests_if_exists(&mut json, &manifest_path) {
-                warn!(error = %e, path = ?manifest_path, "Failed to merge existing manifest file");
+        if args.only_new {
+            if new_names.is_empty() {
+                bail!("There are no new emotes since the snapshot {:?}", path);
             }
+            emotes.retain(|emote| new_names.contains(&emote.name));
         }
+    }
 
-        fs::write(&manifest_path, serde_json::to_vec(&json)?)
-            .with_context(|| format!("Failed to write file {:?}", &manifest_path))?;
+    let names = emotes.iter().map(|emote| emote.name.as_str());
+    if let Some(suggested) = profile::suggest_prefix(&args.prefix, names) {
+        warn!(
+            prefix = %args.prefix,
+            %suggested,
+            "None of the emote names start with the prefix, but most start with another one. \
+            Double-check that --prefix is correct"
+        );
+    }
 
-        for (position, action) in manifest.actions.iter() {
-            let img_path = current_path
-                .join(format!("{},{}", position.x, position.y))
-                .join("CustomImages");
+    emotes = select_emotes(args, emotes)?;
 
-            fs::create_dir_all(&img_path)
-                .with_context(|| format!("Failed to create path {:?}", &img_path))?;
+    if args.print_codes {
+        let codes = emote_codes(args, &emotes);
+        match args.format {
+            OutputFormat::Text => {
+                for code in codes {
+                    println!("{}", code);
+                }
+            }
+            OutputFormat::Json => {
+                let emotes = emotes
+                    .iter()
+                    .zip(codes)
+                    .map(|(emote, code)| report::EmoteCode {
+                        name: emote.name.clone(),
+                        code,
+                    })
+                    .collect();
+                report::print_json(&report::CodesReport::new(emotes))?;
+            }
+        }
+        return Ok(());
+    }
 
-            let img_file_path = img_path.join("state0.png");
-            if let Some(bytes) = &action.image {
-                fs::write(&img_file_path, bytes)
-                    .with_context(|| format!("Failed to write image {:?}", &img_file_path))?;
+    if args.suggest_model {
+        let suggestions = model_suggestions(args, &name, emotes.len());
+        match args.format {
+            OutputFormat::Text => {
+                for suggestion in &suggestions {
+                    println!("{}", describe_suggestion(suggestion));
+                }
+            }
+            OutputFormat::Json => {
+                report::print_json(&report::SuggestionsReport::new(emotes.len(), suggestions))?
             }
         }
+        return Ok(());
+    }
 
-        depth += 1;
+    if args.plan_only {
+        let mut plans = Vec::new();
+        for (model, device_uuid) in devices.iter() {
+            let options = profile_options(args, &name, model.clone(), device_uuid.clone(), false);
+            let plan = profile::plan_layout(emotes.len(), &options);
+            match args.format {
+                OutputFormat::Text => println!("{}", describe_plan(&plan, emotes.len(), model)),
+                OutputFormat::Json => {
+                    plans.push(report::DevicePlan::new(model, emotes.len(), &plan))
+                }
+            }
+        }
+        if args.format == OutputFormat::Json {
+            report::print_json(&report::PlanReport::new(plans))?;
+        }
+        return Ok(());
     }
 
-    if args.restart {
-        restart_stream_deck().context("Failed to restart Stream Deck application")?;
+    if args.validate_urls {
+        let failures = fetch::validate_urls(&client, emotes.iter().map(|e| e.url.as_str())).await;
+        for (url, error) in &failures {
+            if let Some(emote) = emotes.iter().find(|e| e.url == *url) {
+                println!("{}: {}", emote.name, error);
+            }
+        }
+        if !failures.is_empty() {
+            bail!(
+                "{} of {} emote URLs failed validation",
+                failures.len(),
+                emotes.len()
+            );
+        }
+        info!(count = emotes.len(), "All emote URLs are reachable");
+        return Ok(());
     }
 
-    Ok(())
-}
+    let cache = match &args.cache_dir {
+        Some(dir) => Some(fetch::ImageCache::new(dir)?),
+        None => None,
+    };
+    if let (true, Some(cache)) = (args.offline, &cache) {
+        emotes = cached_emotes(emotes, cache)?;
+    }
+    let fetcher = fetch::CachedFetcher {
+        inner: if args.offline { None } else { Some(client) },
+        cache,
+    };
+    // Only `--images-dir` reads local files. Other sources must use http(s) URLs.
+    let fetcher: Box<dyn ImageFetcher> = if args.images_dir.is_some() {
+        Box::new(fetch::FileFetcher { inner: fetcher })
+    } else {
+        Box::new(fetcher)
+    };
 
-fn merge_manifests_if_exists(new_manifest: &mut Value, existing_path: &PathBuf) -> Result<()> {
-    let string = match fs::read_to_string(existing_path) {
-        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
-        Err(e) => return Err(e).context("Could not read existing manifest file"),
-        Ok(s) => s,
+    // Write to a temporary directory if the output is streamed to stdout as a tar archive
+    let staging_dir = if args.out.as_deref() == Some(Path::new("-")) {
+        Some(tempfile::tempdir().context("Failed to create temporary directory")?)
+    } else {
+        None
+    };
+    // With `--check`, write to a copy of the existing files, to compare with them afterwards
+    let check_dir = if args.check {
+        let dir = tempfile::tempdir().context("Failed to create temporary directory")?;
+        stage::copy_contents(&root_path, dir.path())
+            .context("Failed to copy the existing profiles to compare with")?;
+        Some(dir)
+    } else {
+        None
     };
 
-    let old_manifest = serde_json::from_str::<Value>(&string).context("Invalid JSON")?;
+    // Only the application's own profiles directory is affected by it running
+    if args.out.is_none() && !args.restart && !args.check && stream_deck_running() {
+        // `--restart` only works on macOS
+        let suggestion = if cfg!(target_os = "macos") {
+            "Pass --restart to restart it automatically"
+        } else {
+            "Restart it after the profile is written"
+        };
+        warn!(
+            "The Stream Deck application is running, so it may not pick up the changes until it's \
+            restarted. {}",
+            suggestion
+        );
+    }
 
-    let old_actions = if let Some(actions) = old_manifest
-        .pointer("/Actions")
-        .and_then(|json| json.as_object())
-    {
-        actions
+    // The temporary directories are new, so only the real output directory needs to be locked
+    let lock = if staging_dir.is_none() && check_dir.is_none() {
+        Some(lock::OutputLock::acquire(&root_path)?)
     } else {
-        bail!("Existing manifest file has invalid `Actions` field");
+        None
     };
 
-    let new_actions = if let Some(actions) = new_manifest
-        .pointer_mut("/Actions")
-        .and_then(|json| json.as_object_mut())
-    {
-        actions
-    } else {
-        bail!("New manifest file has invalid `Actions` field");
+    // With `--stage-dir`, write to a copy of the output directory, and move it into place at the end
+    let stage_dir = match &args.stage_dir {
+        Some(_) if staging_dir.is_some() => {
+            bail!("--stage-dir can't be used when writing to stdout with `--out -`")
+        }
+        Some(path) => Some(stage::StageDir::create(&root_path, path)?),
+        None => None,
+    };
+    let stage_path = stage_dir.as_ref().map(|stage| stage.path.clone());
+    let out_path = staging_dir
+        .as_ref()
+        .or(check_dir.as_ref())
+        .map(|dir| dir.path().to_owned())
+        .or_else(|| stage_path.clone())
+        .unwrap_or_else(|| root_path.clone());
+    // Where a path in the output ends up, once the stage directory is moved into place
+    let live_path = |path: &Path| match &stage_path {
+        Some(stage_path) => root_path.join(path.strip_prefix(stage_path).unwrap_or(path)),
+        None => path.to_owned(),
     };
 
-    for (pos, action) in old_actions.into_iter() {
-        if !new_actions.contains_key(pos) {
-            new_actions.insert(pos.to_owned(), action.clone());
+    // Generate profiles, one tree per device
+    let multiple_devices = devices.len() > 1;
+    let mut cheatsheets = Vec::new();
+    let mut written_files = Vec::new();
+    let mut open_path = out_path.clone();
+    let mut downloaded_bytes = 0;
+    let mut summaries = Vec::new();
+    let mut failed_emotes = Vec::<report::FailedEmote>::new();
+
+    for (model, device_uuid) in devices {
+        let options = profile_options(args, &name, model, device_uuid, multiple_devices);
+
+        // Flat layouts use the same page directory names for every device
+        let device_path = if multiple_devices && args.layout == OutputLayout::Flat {
+            out_path.join(options.model.to_string())
+        } else {
+            out_path.clone()
+        };
+
+        let profiles =
+            load_profiles(args, &fetcher, emotes.clone(), &options, &device_path).await?;
+        downloaded_bytes += profiles.downloaded_bytes;
+        // Each device downloads the same images, so a failure is only listed once
+        for failure in &profiles.failed_downloads {
+            if !failed_emotes.iter().any(|f| f.url == failure.emote.url) {
+                failed_emotes.push(report::FailedEmote {
+                    name: failure.emote.name.clone(),
+                    url: failure.emote.url.clone(),
+                    error: failure.error.clone(),
+                });
+            }
+        }
+
+        if !profiles.duplicate_pasted_texts.is_empty() && args.strict {
+            bail!(
+                "Multiple emotes have the same pasted text: {:?}",
+                profiles.duplicate_pasted_texts
+            );
+        }
+        if !profiles.dropped_categories.is_empty() && args.strict {
+            bail!(
+                "Too many categories to fit on the root page of the {} model, leaving out {:?}",
+                options.model,
+                profiles.dropped_categories
+            );
+        }
+
+        if args.cheatsheet.is_some() {
+            let title = if multiple_devices {
+                format!("{} ({})", options.name, options.model)
+            } else {
+                options.name.clone()
+            };
+            cheatsheets.push(cheatsheet::render(&profiles, &title));
+        }
+
+        let is_single_root = !multiple_devices
+            && args.layout == OutputLayout::Nested
+            && profiles.parent_profile_uuid.is_none();
+        if let (true, Some((uuid, _))) = (is_single_root, profiles.manifests.first()) {
+            open_path = out_path.join(sd_profile_dir(*uuid));
         }
+
+        let destination = if staging_dir.is_some() {
+            "stdout".to_owned()
+        } else {
+            let targets = overwrite_targets(&profiles, &device_path, args.layout);
+            let profile_path = match args.layout {
+                OutputLayout::Nested => targets.into_iter().next(),
+                OutputLayout::Flat => None,
+            };
+            format!(
+                "{}",
+                live_path(profile_path.as_ref().unwrap_or(&device_path)).display()
+            )
+        };
+        let emote_count = profiles
+            .manifests
+            .iter()
+            .flat_map(|(_, manifest)| manifest.actions.values())
+            .filter(|action| action.emote.is_some())
+            .count();
+        summaries.push(summary(
+            &options.name,
+            profiles.manifests.len(),
+            emote_count,
+            &destination,
+        ));
+
+        written_files.extend(write_profiles(profiles, device_path, args)?);
     }
 
-    Ok(())
-}
+    if let Some(path) = &args.failure_report {
+        if !failed_emotes.is_empty() {
+            warn!(
+                count = failed_emotes.len(),
+                report = ?path,
+                "Some emote images failed to download. Pass the report to --retry-failed to try \
+                again"
+            );
+        }
+        report::FailureReport::new(failed_emotes).write(path)?;
+    }
 
-fn restart_stream_deck() -> Result<()> {
-    if !cfg!(target_os = "macos") {
-        warn!(
-            "The --restart flag is currently only supported on macOS. \
-            See https://github.com/walfie/streamdeck-youtube-emotes/issues/1"
-        );
+    let missing = missing_images(&written_files, &args.extra_action)?;
+    if !missing.is_empty() {
+        if args.strict {
+            bail!(
+                "Images referenced by the profile are missing or empty: {:?}",
+                missing
+            );
+        }
+        warn!(paths = ?missing, "Images referenced by the profile are missing or empty");
+    }
+
+    if let Some(dir) = &check_dir {
+        check_up_to_date(&root_path, dir.path())?;
+        eprintln!("✓ Profiles in {} are up to date", root_path.display());
         return Ok(());
     }
 
-    info!("Restarting Stream Deck application");
+    if let Some(path) = &args.checksums {
+        write_checksums(&out_path, &written_files, path)?;
+    }
 
-    let stop_result = Command::new("pkill").arg("Stream Deck").status();
+    // Moving the staged profiles into place writes to the output directory too
+    if let Some(stage) = stage_dir {
+        stage.sync(&root_path, &written_files)?;
+    }
+    drop(lock);
 
-    if let Err(e) = stop_result {
-        warn!(error = %e, "Failed to stop Stream Deck");
+    if multiple_devices {
+        info!(
+            total_bytes = downloaded_bytes,
+            "Downloaded images for all devices"
+        );
     }
 
-    let start_result = Command::new("open")
-        .arg("/Applications/Stream Deck.app")
-        .status();
+    if let Some(path) = &args.cheatsheet {
+        fs::write(path, cheatsheets.join("\n"))
+            .with_context(|| format!("Failed to write cheat sheet {:?}", path))?;
+        info!(?path, "Wrote cheat sheet");
+    }
 
-    if let Err(e) = start_result {
-        warn!(error = %e, "Failed to start Stream Deck");
+    if let Some(dir) = &staging_dir {
+        write_tar(dir.path(), std::io::stdout().lock())?;
+    } else if args.open {
+        open_in_file_manager(&live_path(&open_path));
+    }
+
+    if args.restart && confirm_restart(args, prompt_stdin)? {
+        restart_stream_deck(args.strict).context("Failed to restart Stream Deck application")?;
+    }
+
+    if let (Some(path), Some(emotes)) = (&args.save_snapshot, &all_emotes) {
+        snapshot::write_snapshot(path, emotes)?;
+        info!(?path, "Wrote emote snapshot");
+    }
+
+    for summary in summaries {
+        eprintln!("{}", summary);
     }
 
     Ok(())
 }
 
-#[derive(StructOpt)]
-pub struct Args {
-    /// Path to an HTML file containing the memberships page for a channel.
-    /// E.g., Download the following page in a browser while logged in:
-    /// https://www.youtube.com/channel/UCP4nMSTdwU1KqYWu3UH5DHQ/memberships
-    ///
-    /// Use - to read from stdin.
-    #[structopt(parse(from_os_str), long)]
-    pub html_file: PathBuf,
+/// Reads the saved memberships page from `--html-file`, or stdin if it's `-`
+fn read_html_file(args: &Args) -> Result<String> {
+    let html_file = args.html_file.as_ref().context(
+        "One of --html-file, --channel-url, --emotes-json, or --images-dir must be specified",
+    )?;
 
-    /// The emote prefix (also known as "family name"). For example, if the channel has an emote
-    /// `:_pomuSmall9cm:`, the emote prefix would be `pomu`. For some channels, there is no prefix,
-    /// so this option can be omitted.
-    #[structopt(default_value = "", long)]
-    pub prefix: String,
+    if html_file.to_str() == Some("-") {
+        let mut buf = String::new();
+        std::io::stdin().read_to_string(&mut buf)?;
+        Ok(buf)
+    } else {
+        fs::read_to_string(html_file)
+            .with_context(|| format!("Failed to read file {:?}", html_file))
+    }
+}
 
-    /// Name of the Stream Deck profile. Note that if the `profile-uuid` argument is unspecified, this name will
-    /// be used to determine the name of the output profile directory.
-    #[structopt(long)]
-    pub name: String,
+/// Reads the list of emotes and the channel name from the HTML of a memberships page
+fn read_memberships_page(args: &Args, html: &str) -> Result<(String, Vec<Emote>)> {
+    if let Some(path) = &args.dump_ytinitialdata {
+        dump_initial_data(html, path)?;
+    }
 
-    /// Device UUID for the Stream Deck
-    #[structopt(default_value = "", long)]
-    pub device_uuid: String,
+    let json = youtube::parse_initial_data(html)?;
 
-    /// Override the UUID for the profile
-    #[structopt(long)]
-    pub profile_uuid: Option<Uuid>,
+    let name = profile_name(args, &json)?;
+    let emotes = youtube::parse_emotes(&json, args.thumbnail_select)?;
 
-    /// Whether to include the name of the emote on each key
-    #[structopt(long)]
-    pub include_labels: bool,
+    if youtube::has_continuation(&json) {
+        if args.strict {
+            bail!("The page has more content that isn't included in the HTML file");
+        }
+        warn!(
+            "The page has more content that isn't included in the HTML file, \
+            so some emotes may be missing"
+        );
+    }
 
-    /// Overwrite existing manifest files instead of merging them.
-    #[structopt(long)]
-    pub no_merge: bool,
+    Ok((name, emotes))
+}
 
-    /// Output path to save the profile to. If unspecified, profiles will be saved to the default
-    /// Stream Deck profile location (depending on platform).
-    #[structopt(long)]
-    pub out: Option<PathBuf>,
+/// Name of the profile: `--name` if set, otherwise the channel name from the page. With
+/// `--name-from-html`, the channel name is used even if `--name` is set.
+fn profile_name(args: &Args, json: &Value) -> Result<String> {
+    if let (Some(name), false) = (&args.name, args.name_from_html) {
+        return Ok(name.clone());
+    }
 
-    /// List of emotes that should appear first, before all others (case-insensitive)
-    #[structopt(long)]
-    pub prioritize: Vec<String>,
+    match youtube::parse_channel_name(json) {
+        Some(name) => {
+            info!(%name, "Using channel name as profile name");
+            Ok(name)
+        }
+        None if args.name_from_html => {
+            bail!(
+                "Could not find the channel name in the HTML file, as required by --name-from-html"
+            )
+        }
+        None => bail!("Could not find the channel name in the HTML file, please specify --name"),
+    }
+}
 
-    /// List of emotes that should appear last, after all others (case-insensitive)
-    #[structopt(long)]
-    pub deprioritize: Vec<String>,
+/// Writes the `ytInitialData` JSON from the HTML to a file, so that it can be inspected or shared
+/// without the rest of the page. It's pretty-printed if it's valid JSON, and written as-is
+/// otherwise.
+fn dump_initial_data(html: &str, path: &Path) -> Result<()> {
+    let json_str = youtube::find_initial_data(html)?;
+    let contents = match serde_json::from_str::<Value>(json_str) {
+        Ok(json) => serde_json::to_string_pretty(&json)?,
+        Err(_) => json_str.to_owned(),
+    };
 
-    /// The Stream Deck model to generate the profile for
-    #[structopt(long, possible_values = &["standard", "xl", "mini"])]
-    pub model: DeviceModel,
+    fs::write(path, contents).with_context(|| format!("Failed to write file {:?}", path))?;
+    info!(?path, "Wrote ytInitialData");
+    Ok(())
+}
 
-    /// Restart the Stream Deck application after creating the profile
-    #[structopt(long)]
-    pub restart: bool,
+/// Pairs each `--model` with the `--device-uuid` at the same position, or uses the pairs given
+/// with `--device`. Without either, the device from `--from-existing` is used.
+fn resolve_devices(
+    device_specs: &[DeviceSpec],
+    models: &[DeviceModel],
+    device_uuids: &[String],
+    existing_device: Option<(DeviceModel, String)>,
+) -> Result<Vec<(DeviceModel, String)>> {
+    let devices = if device_specs.is_empty() {
+        pair_devices(models, device_uuids, existing_device)?
+    } else {
+        device_specs
+            .iter()
+            .map(|spec| (spec.model.clone(), spec.uuid.clone().unwrap_or_default()))
+            .collect()
+    };
+
+    // A single profile can still be assigned to a device in the Stream Deck application, but with
+    // several it's easy to mix them up
+    if devices.len() > 1 {
+        for (model, _) in devices
+            .iter()
+            .filter(|(_, device_uuid)| device_uuid.is_empty())
+        {
+            warn!(
+                %model,
+                "No device UUID for this model, so its profile won't be bound to a specific device"
+            );
+        }
+    }
+
+    Ok(devices)
+}
+
+fn pair_devices(
+    models: &[DeviceModel],
+    device_uuids: &[String],
+    existing_device: Option<(DeviceModel, String)>,
+) -> Result<Vec<(DeviceModel, String)>> {
+    if device_uuids.len() > models.len().max(1) {
+        bail!("Each --device-uuid must be paired with a --model");
+    }
+
+    if models.is_empty() {
+        return match existing_device {
+            Some((model, device_uuid)) => {
+                let device_uuid = device_uuids.first().cloned().unwrap_or(device_uuid);
+                Ok(vec![(model, device_uuid)])
+            }
+            None => bail!("Either --model, --device, or --from-existing must be specified"),
+        };
+    }
+
+    let devices = models
+        .iter()
+        .enumerate()
+        .map(|(index, model)| {
+            let device_uuid = match (device_uuids.get(index), &existing_device) {
+                (Some(device_uuid), _) => device_uuid.clone(),
+                (None, Some((existing_model, device_uuid))) if existing_model == model => {
+                    device_uuid.clone()
+                }
+                (None, _) => String::new(),
+            };
+            (model.clone(), device_uuid)
+        })
+        .collect();
+
+    Ok(devices)
+}
+
+/// Options for generating the profile for one device. When generating for multiple devices, the
+/// model is included in the UUIDs so that each device gets its own profiles.
+fn profile_options(
+    args: &Args,
+    name: &str,
+    model: DeviceModel,
+    device_uuid: String,
+    multiple_devices: bool,
+) -> ProfileOptions {
+    let uuid_seed = if multiple_devices {
+        format!("{}_{}", name, model)
+    } else {
+        name.to_owned()
+    };
+
+    ProfileOptions {
+        root_profile_uuid: args
+            .profile_uuid
+            .unwrap_or_else(|| profile::uuid_v5(&uuid_seed, 0)),
+        parent_profile_uuid: args.parent_profile_uuid,
+        image: ImageOptions {
+            resize: model.key_size(),
+            resize_filter: args.resize_filter,
+            sharpen: args.sharpen,
+            toggle_dim: args.toggle_dim,
+            animated: args.animated,
+            format: args.image_format,
+            padding: args.key_padding,
+            badge: None,
+        },
+        model,
+        device_uuid,
+        name: name.to_owned(),
+        uuid_seed,
+        action: action_options(args),
+        nav_layout: args.nav_layout,
+        nav_style: args.nav_style,
+        tier_colors: args.tier_colors.clone(),
+        tier_badges: if args.tier_badges {
+            Some(BadgeStyle {
+                corner: args.badge_corner,
+                size: args.badge_size,
+            })
+        } else {
+            None
+        },
+        sort: args.sort,
+        page_name_template: args.page_name_template.clone(),
+        compact: args.compact,
+        dedupe_by_image: args.dedupe_by_image,
+        extra_actions: args.extra_action.clone(),
+        save_originals: args.save_originals.clone(),
+        min_source_resolution: args.min_source_resolution,
+        center_last_page: args.center_last_page,
+        start_position: args.start_position,
+        categorize: args.category_file.is_some(),
+        no_navigation: args.no_navigation,
+        no_animated: args.no_animated,
+        skip_failed_downloads: args.failure_report.is_some(),
+    }
+}
+
+fn action_options(args: &Args) -> ActionOptions {
+    ActionOptions {
+        prefix: args.prefix.clone(),
+        underscore: !args.no_underscore,
+        name_case: name_case(args),
+        include_label: args.include_labels,
+        label_size: args.label_size.clone(),
+        label_max_chars: args.label_max_chars.map(NonZeroUsize::get),
+        label_wrap: args.label_wrap,
+        text_template: args.text_template.clone(),
+    }
+}
+
+fn name_case(args: &Args) -> NameCase {
+    args.name_case
+        .unwrap_or_else(|| NameCase::default_for_prefix(&args.prefix))
+}
+
+/// Text that each emote's key would paste, for `--print-codes`
+fn emote_codes(args: &Args, emotes: &[Emote]) -> Vec<String> {
+    let options = action_options(args);
+    emotes.iter().map(|emote| emote.code(&options)).collect()
+}
+
+/// Environment variable for the profiles directory, used if `--out` is unspecified
+const PROFILES_DIR_VAR: &str = "STREAMDECK_PROFILES_DIR";
+
+/// Version of the Stream Deck application's profiles directory. Older versions of the application
+/// use `ProfilesV1`. The manifests are written the same way for both, since no differences in the
+/// parts of the format used here are known.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ProfilesVersion {
+    V1,
+    #[default]
+    V2,
+}
+
+impl FromStr for ProfilesVersion {
+    type Err = color_eyre::eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_ref() {
+            "v1" | "1" => Ok(Self::V1),
+            "v2" | "2" => Ok(Self::V2),
+            other => bail!("Unknown profiles version {}", other),
+        }
+    }
+}
+
+impl ProfilesVersion {
+    fn dir_name(&self) -> &'static str {
+        match self {
+            Self::V1 => "ProfilesV1",
+            Self::V2 => "ProfilesV2",
+        }
+    }
+}
+
+/// Finds the directory to write profiles to when `--out` is unspecified: the value of
+/// [`PROFILES_DIR_VAR`] if set, otherwise the Stream Deck application's directory on this platform
+fn profiles_dir(env_dir: Option<OsString>, version: ProfilesVersion) -> Result<PathBuf> {
+    if let Some(dir) = env_dir.filter(|dir| !dir.is_empty()) {
+        return Ok(PathBuf::from(dir));
+    }
+
+    let home = dirs::home_dir().context("Could not find home directory")?;
+    app_profiles_dir(std::env::consts::OS, &home, version).with_context(|| {
+        format!(
+            "No output path specified. Use --out or set {}",
+            PROFILES_DIR_VAR
+        )
+    })
+}
+
+/// The Stream Deck application's profiles directory on the given OS (as in
+/// [`std::env::consts::OS`]), if the application is available there
+fn app_profiles_dir(os: &str, home: &Path, version: ProfilesVersion) -> Option<PathBuf> {
+    let app_dir = match os {
+        "macos" => home
+            .join("Library")
+            .join("Application Support")
+            .join("com.elgato.StreamDeck"),
+        "windows" => home
+            .join("AppData")
+            .join("Roaming")
+            .join("Elgato")
+            .join("StreamDeck"),
+        _ => return None,
+    };
+    Some(app_dir.join(version.dir_name()))
+}
+
+/// One-line confirmation shown after a successful run
+fn summary(name: &str, pages: usize, emotes: usize, destination: &str) -> String {
+    format!(
+        "✓ Wrote profile '{}' ({}, {}) to {}",
+        name,
+        plural(pages, "page"),
+        plural(emotes, "emote"),
+        destination
+    )
+}
+
+/// Describes a layout plan for `--plan-only`, e.g.
+/// `standard: 3 pages for 30 emotes, up to 12 per page (6 on the last page), 3 keys reserved for
+/// navigation`
+fn describe_plan(plan: &profile::LayoutPlan, emotes: usize, model: &DeviceModel) -> String {
+    let mut description = format!(
+        "{}: {} for {}",
+        model,
+        plural(plan.pages(), "page"),
+        plural(emotes, "emote")
+    );
+
+    if plan.nav_style == NavStyle::Index {
+        description.push_str(" (including an index page)");
+    }
+
+    if let (Some(first), Some(last)) = (plan.emotes_per_page.first(), plan.emotes_per_page.last()) {
+        description.push_str(&format!(
+            ", up to {} per page ({} on the last page)",
+            first, last
+        ));
+    }
+
+    description.push_str(&format!(
+        ", {} reserved for navigation",
+        plural(plan.reserved.len(), "key")
+    ));
+    description
+}
+
+/// Works out how many emotes each device model holds on one screen, and how many pages it would
+/// need for `emote_count` emotes with the other options given, starting with the models that need
+/// the fewest pages
+fn model_suggestions(args: &Args, name: &str, emote_count: usize) -> Vec<report::ModelSuggestion> {
+    let mut suggestions = [
+        DeviceModel::Mini,
+        DeviceModel::Neo,
+        DeviceModel::Standard,
+        DeviceModel::XL,
+    ]
+    .iter()
+    .map(|model| {
+        let (width, height) = model.size();
+        let keys = width as usize * height as usize;
+        let options = profile_options(args, name, model.clone(), "".into(), false);
+        report::ModelSuggestion {
+            model: model.to_string(),
+            keys,
+            pages: profile::plan_layout(emote_count, &options).pages(),
+            fits_on_one_screen: emote_count <= keys,
+        }
+    })
+    .collect::<Vec<_>>();
+    suggestions.sort_by_key(|suggestion| (suggestion.pages, suggestion.keys));
+    suggestions
+}
+
+/// One line of `--suggest-model` output, e.g. `standard: 15 keys, 3 pages`
+fn describe_suggestion(suggestion: &report::ModelSuggestion) -> String {
+    let mut line = format!(
+        "{}: {}, {}",
+        suggestion.model,
+        plural(suggestion.keys, "key"),
+        plural(suggestion.pages, "page")
+    );
+    if suggestion.fits_on_one_screen {
+        line.push_str(" (fits on one screen with --compact)");
+    }
+    line
+}
+
+/// Formats a count with a noun, e.g. `1 page` or `2 pages`
+fn plural(count: usize, noun: &str) -> String {
+    if count == 1 {
+        format!("{} {}", count, noun)
+    } else {
+        format!("{} {}s", count, noun)
+    }
+}
+
+/// Fails early if files can't be created in the output directory, before downloading anything.
+/// Directories that don't exist yet are created when the profile is written, so this probes the
+/// nearest one that does, without creating anything.
+fn check_writable(path: &Path) -> Result<(), error::Error> {
+    let existing = path
+        .ancestors()
+        .find(|dir| dir.exists())
+        .unwrap_or_else(|| Path::new("."));
+    tempfile::tempfile_in(existing).map_err(|source| error::Error::OutputPathUnavailable {
+        path: path.to_owned(),
+        source,
+    })?;
+
+    Ok(())
+}
+
+/// Reads the device model and UUID from an existing profile manifest
+fn read_existing_device(path: &Path) -> Result<(DeviceModel, String)> {
+    let string =
+        fs::read_to_string(path).with_context(|| format!("Failed to read file {:?}", path))?;
+    let manifest = serde_json::from_str::<Value>(&string).context("Invalid JSON")?;
+
+    let model_id = manifest
+        .pointer("/DeviceModel")
+        .and_then(|v| v.as_str())
+        .with_context(|| format!("Manifest {:?} has no `DeviceModel` field", path))?;
+    let model = DeviceModel::from_model_id(model_id)
+        .with_context(|| format!("Unrecognized device model {:?} in {:?}", model_id, path))?;
+
+    let device_uuid = manifest
+        .pointer("/DeviceUUID")
+        .and_then(|v| v.as_str())
+        .unwrap_or_default()
+        .to_owned();
+
+    info!(?path, %model_id, %device_uuid, "Using device from existing manifest");
+    Ok((model, device_uuid))
+}
+
+/// Fails if too few emotes were found, which usually means the wrong page was saved
+fn check_min_emotes(count: usize, min_emotes: usize) -> Result<()> {
+    if count < min_emotes {
+        bail!(
+            "Found {} emote(s), but at least {} are required (see --min-emotes). \
+            The page may have been saved before it finished loading, or it may be for the wrong \
+            channel. Try saving the memberships page again.",
+            count,
+            min_emotes
+        );
+    }
+
+    Ok(())
+}
+
+/// Filters and orders the emotes with `--tier`, `--prioritize`, `--interactive`, and `--limit`,
+/// then checks that at least `--min-emotes` are left
+fn select_emotes(args: &Args, mut emotes: Vec<Emote>) -> Result<Vec<Emote>> {
+    if !args.tier.is_empty() {
+        let (unknown, available) = filter_tiers(&mut emotes, &args.tier);
+        if !unknown.is_empty() {
+            warn!(tiers = ?unknown, ?available, "Unknown membership tiers");
+        }
+        if emotes.is_empty() {
+            bail!("None of the emotes are in the tiers {:?}", args.tier);
+        }
+    }
+
+    let prioritize = with_names_from_file(&args.prioritize, args.prioritize_file.as_deref())?;
+    let deprioritize = with_names_from_file(&args.deprioritize, args.deprioritize_file.as_deref())?;
+    sort_emotes(&mut emotes, &prioritize, &deprioritize);
+
+    #[cfg(feature = "interactive")]
+    if args.interactive {
+        emotes = picker::pick(emotes)?;
+    }
+
+    if let Some(limit) = args.limit {
+        limit_emotes(&mut emotes, limit);
+    }
+
+    check_min_emotes(emotes.len(), args.min_emotes)?;
+    Ok(emotes)
+}
+
+/// Appends the emote names listed in the file (one per line) to the names given inline, so that
+/// the inline names come first. Blank lines and lines starting with `#` are skipped.
+fn with_names_from_file(inline: &[String], path: Option<&Path>) -> Result<Vec<String>> {
+    let mut names = inline.to_vec();
+
+    if let Some(path) = path {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read emote list {:?}", path))?;
+        let lines = contents
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+        names.extend(lines.map(|line| line.to_owned()));
+    }
+
+    Ok(names)
+}
+
+/// Reorders emotes so that the ones in `prioritize` come first and the ones in `deprioritize` come
+/// last, in the given order (case-insensitive). Other emotes keep their original order.
+fn sort_emotes(emotes: &mut [Emote], prioritize: &[String], deprioritize: &[String]) {
+    let emotes_count = emotes.len();
+    emotes.sort_by_cached_key(|emote| {
+        let lower_name = emote.name.to_ascii_lowercase();
+
+        if let Some(pos) = prioritize
+            .iter()
+            .position(|name| name.to_ascii_lowercase() == lower_name)
+        {
+            return pos;
+        }
+
+        if let Some(pos) = deprioritize
+            .iter()
+            .position(|name| name.to_ascii_lowercase() == lower_name)
+        {
+            return pos + emotes_count + 1;
+        }
+
+        emotes_count
+    });
+}
+
+/// Keeps only the emotes whose images are in the cache, for `--offline`
+fn cached_emotes(emotes: Vec<Emote>, cache: &fetch::ImageCache) -> Result<Vec<Emote>> {
+    let (cached, missing): (Vec<_>, Vec<_>) = emotes
+        .into_iter()
+        .partition(|emote| cache.contains(&emote.url));
+    info!(
+        cached = cached.len(),
+        missing = missing.len(),
+        "Using cached images"
+    );
+
+    if !missing.is_empty() {
+        let names = missing.iter().map(|emote| &emote.name).collect::<Vec<_>>();
+        warn!(?names, "Skipping emotes whose images aren't in the cache");
+    }
+    if cached.is_empty() {
+        bail!("None of the emote images are in the cache. Run without --offline first");
+    }
+
+    Ok(cached)
+}
+
+/// Keeps only the emotes in the given tiers (case-insensitive). Returns the tiers that didn't match
+/// any emote, along with the tiers that are available.
+fn filter_tiers(emotes: &mut Vec<Emote>, tiers: &[String]) -> (Vec<String>, Vec<String>) {
+    let mut available = Vec::<String>::new();
+    for tier in emotes.iter().filter_map(|emote| emote.tier.as_ref()) {
+        if !available.contains(tier) {
+            available.push(tier.clone());
+        }
+    }
+
+    let unknown = tiers
+        .iter()
+        .filter(|tier| !available.iter().any(|a| a.eq_ignore_ascii_case(tier)))
+        .cloned()
+        .collect();
+
+    emotes.retain(|emote| match &emote.tier {
+        Some(tier) => tiers.iter().any(|t| t.eq_ignore_ascii_case(tier)),
+        None => false,
+    });
+
+    (unknown, available)
+}
+
+/// Keeps only the first `limit` emotes
+fn limit_emotes(emotes: &mut Vec<Emote>, limit: usize) {
+    if emotes.len() > limit {
+        let dropped = emotes.split_off(limit);
+        let names = dropped.iter().map(|e| e.name.as_str()).collect::<Vec<_>>();
+        info!(
+            limit,
+            dropped = dropped.len(),
+            ?names,
+            "Dropped emotes over the limit"
+        );
+    }
+}
+
+/// Writes the generated profiles to the filesystem, nesting each page inside the previous one
+/// (or side by side, for the flat layout). Returns the paths of all written files.
+fn write_profiles(
+    profiles: ProfilesWithImages,
+    root_path: PathBuf,
+    args: &Args,
+) -> Result<Vec<PathBuf>> {
+    // Regenerating only the manifests, or adding the retried emotes, always modifies an existing
+    // profile
+    let modifies_existing = args.manifest_only || args.retry_failed.is_some();
+    if !args.force && !modifies_existing && !args.replace && !args.check {
+        for target in overwrite_targets(&profiles, &root_path, args.layout) {
+            check_not_overwriting(&target)?;
+        }
+    }
+
+    if args.replace {
+        remove_stale_pages(&profiles, &root_path, args.layout)?;
+    }
+
+    let mut written_files = Vec::new();
+    let mut root_profiles_path = root_path.clone();
+    let mut current_path = root_path.clone();
+
+    // When nesting inside an existing profile, that profile is the top of the hierarchy
+    let mut depth_offset = 0;
+    if let (Some(parent_uuid), OutputLayout::Nested) = (profiles.parent_profile_uuid, args.layout) {
+        current_path.push(sd_profile_dir(parent_uuid));
+        root_profiles_path = current_path.join("Profiles");
+        depth_offset = 1;
+
+        if let Some((root_uuid, root)) = profiles.manifests.first() {
+            let parent_manifest_path = current_path.join("manifest.json");
+            link_child_profile(&parent_manifest_path, *root_uuid, root, args.pretty_json)?;
+            written_files.push(parent_manifest_path);
+        }
+    }
+
+    let copy_options = CopyOptions {
+        overwrite: true,
+        copy_inside: true,
+        ..Default::default()
+    };
+
+    let parent_pages = (0..profiles.manifests.len())
+        .map(|index| profiles.parent_page(index))
+        .collect::<Vec<_>>();
+    let mut page_paths = Vec::<(PathBuf, usize)>::new();
+
+    for (index, (uuid, manifest)) in profiles.manifests.into_iter().enumerate() {
+        if args.layout == OutputLayout::Flat {
+            let page_path = root_path.join(format!("page{}", index + 1));
+            info!(path = ?page_path, "Creating page directory");
+            write_page(&manifest, &page_path, args, &mut written_files)?;
+            continue;
+        }
+
+        let sd_profile_dir = sd_profile_dir(uuid);
+
+        // Nested profiles have an additional `Profiles` directory
+        let (current_path, depth) = match parent_pages[index] {
+            Some(parent) => {
+                let (parent_path, parent_depth) = &page_paths[parent];
+                let path = parent_path.join("Profiles").join(&sd_profile_dir);
+                (path, parent_depth + 1)
+            }
+            None if depth_offset > 0 => {
+                let path = current_path.join("Profiles").join(&sd_profile_dir);
+                (path, depth_offset)
+            }
+            None => (current_path.join(&sd_profile_dir), 0),
+        };
+
+        if depth == 0 {
+            root_profiles_path = current_path.join("Profiles");
+        }
+
+        info!(path = ?current_path, "Creating profile directory");
+
+        // After the initial profile installation, the Stream Deck application un-nests the
+        // directories. The app seems to ignore changes that we make to the un-nested profiles, so
+        // we have to move the directories back to the nested structure to make changes.
+        if depth >= 2 {
+            let src = root_profiles_path.join(&sd_profile_dir);
+            if let Err(e) = fs_extra::dir::move_dir(&src, &current_path, &copy_options) {
+                if !matches!(e.kind, fs_extra::error::ErrorKind::NotFound) {
+                    if args.strict {
+                        return Err(e).context("Failed to move existing nested profile");
+                    }
+                    warn!(error = %e, "Failed to move existing nested profile");
+                }
+            } else {
+                info!(?src, dest = ?current_path, "Moved existing nested profile");
+            }
+        }
+
+        write_page(&manifest, &current_path, args, &mut written_files)?;
+        page_paths.push((current_path, depth));
+    }
+
+    Ok(written_files)
+}
+
+/// Downloads the emote images and lays them out into pages, or with `--manifest-only`, reuses the
+/// images of the existing profile at `device_path` instead. With `--retry-failed`, only the images
+/// that failed last time are downloaded.
+async fn load_profiles(
+    args: &Args,
+    fetcher: &impl ImageFetcher,
+    emotes: Vec<Emote>,
+    options: &ProfileOptions,
+    device_path: &Path,
+) -> Result<ProfilesWithImages> {
+    if args.manifest_only {
+        reuse_existing_images(emotes, options, device_path, args.layout)
+    } else if let Some(report_path) = &args.retry_failed {
+        let report = report::FailureReport::read(report_path)?;
+        retry_failed_downloads(fetcher, emotes, &report, options, device_path, args.layout).await
+    } else {
+        ProfilesWithImages::new(fetcher, emotes, options).await
+    }
+}
+
+/// Lays out the emotes using the images already written for them, matching each emote to its old
+/// key by pasted text. Fails if any emote doesn't have an image yet.
+fn reuse_existing_images(
+    emotes: Vec<Emote>,
+    options: &ProfileOptions,
+    device_path: &Path,
+    layout: OutputLayout,
+) -> Result<ProfilesWithImages> {
+    let (images, missing) = existing_images(emotes, options, device_path, layout)?;
+    if !missing.is_empty() {
+        let names = missing.iter().map(|emote| &emote.name).collect::<Vec<_>>();
+        bail!(
+            "The existing profile doesn't have images for some emotes, run without \
+            --manifest-only to download them: {:?}",
+            names
+        );
+    }
+
+    info!(count = images.len(), "Reusing existing images");
+    Ok(ProfilesWithImages::from_images(images, options))
+}
+
+/// Downloads the images of the emotes listed in the `--retry-failed` report, and lays them out
+/// along with the images already written for the other emotes. Emotes that aren't in the report or
+/// the existing profile were left out on purpose (e.g. by `--dedupe-by-image`), so they stay out.
+async fn retry_failed_downloads(
+    fetcher: &impl ImageFetcher,
+    emotes: Vec<Emote>,
+    report: &report::FailureReport,
+    options: &ProfileOptions,
+    device_path: &Path,
+    layout: OutputLayout,
+) -> Result<ProfilesWithImages> {
+    let (retried, kept): (Vec<_>, Vec<_>) = emotes
+        .iter()
+        .cloned()
+        .partition(|emote| report.failed.iter().any(|failed| failed.url == emote.url));
+    info!(
+        count = retried.len(),
+        "Retrying emotes that failed to download"
+    );
+
+    let (mut images, missing) = existing_images(kept, options, device_path, layout)?;
+    if !missing.is_empty() {
+        let names = missing.iter().map(|emote| &emote.name).collect::<Vec<_>>();
+        info!(
+            ?names,
+            "Leaving out emotes that aren't in the existing profile"
+        );
+    }
+    info!(count = images.len(), "Reusing existing images");
+
+    let downloaded = profile::download_images(fetcher, retried, options).await?;
+    images.extend(downloaded.images);
+    // Back in the order of the page, so that the emotes that were kept stay where they were
+    images.sort_by_key(|image| {
+        emotes
+            .iter()
+            .position(|emote| emote.name == image.emote.name && emote.url == image.emote.url)
+    });
+
+    Ok(ProfilesWithImages {
+        downloaded_bytes: downloaded.downloaded_bytes,
+        failed_downloads: downloaded.failed,
+        ..ProfilesWithImages::from_images(images, options)
+    })
+}
+
+/// Finds the images already written for the emotes in the existing profile at `device_path`,
+/// matching each emote to its old key by pasted text. Returns the emotes that don't have one too.
+fn existing_images(
+    emotes: Vec<Emote>,
+    options: &ProfileOptions,
+    device_path: &Path,
+    layout: OutputLayout,
+) -> Result<(Vec<EmoteImage>, Vec<Emote>)> {
+    // The layout (without images) is only needed to find where the profile was written
+    let placeholders = emotes
+        .iter()
+        .map(|emote| EmoteImage {
+            emote: emote.clone(),
+            bytes: Bytes::new(),
+            dimmed_bytes: None,
+            format: EncodedFormat::Png,
+        })
+        .collect();
+    let placeholders = ProfilesWithImages::from_images(placeholders, options);
+
+    // Image file names of each existing key, by pasted text
+    let mut existing_keys = HashMap::new();
+    for target in overwrite_targets(&placeholders, device_path, layout) {
+        for manifest_path in find_manifests(&target)? {
+            let string = fs::read_to_string(&manifest_path)
+                .with_context(|| format!("Failed to read file {:?}", manifest_path))?;
+            let manifest = serde_json::from_str::<Value>(&string)
+                .with_context(|| format!("Invalid JSON in {:?}", manifest_path))?;
+            let page_dir = manifest_path.parent().unwrap_or(&target);
+
+            let actions = manifest
+                .get("Actions")
+                .and_then(|actions| actions.as_object())
+                .into_iter()
+                .flatten();
+            for (position, action) in actions {
+                let text = action
+                    .pointer("/Settings/pastedText")
+                    .and_then(|t| t.as_str());
+                let images = action
+                    .get("States")
+                    .and_then(|states| states.as_array())
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|state| state.get("Image")?.as_str())
+                    .map(|image| page_dir.join(position).join("CustomImages").join(image))
+                    .collect::<Vec<_>>();
+                if let Some(text) = text {
+                    existing_keys.insert(text.to_owned(), images);
+                }
+            }
+        }
+    }
+
+    let read_image = |path: &PathBuf| fs::read(path).ok().filter(|bytes| !bytes.is_empty());
+
+    let mut missing = Vec::new();
+    let mut images = Vec::new();
+    for emote in emotes {
+        let code = emote.code(&options.action);
+        let paths = existing_keys.get(&code);
+        let bytes = paths.and_then(|paths| read_image(paths.first()?));
+        let bytes = match bytes {
+            Some(bytes) => bytes,
+            None => {
+                missing.push(emote);
+                continue;
+            }
+        };
+
+        let dimmed_bytes = paths
+            .and_then(|paths| read_image(paths.get(1)?))
+            .filter(|_| options.image.toggle_dim);
+        let extension = paths.and_then(|paths| paths.first()?.extension()?.to_str());
+        let format = match extension {
+            Some("gif") => EncodedFormat::Gif,
+            Some("jpg") => EncodedFormat::Jpeg,
+            _ => EncodedFormat::Png,
+        };
+
+        images.push(EmoteImage {
+            emote,
+            bytes: bytes.into(),
+            dimmed_bytes: dimmed_bytes.map(Bytes::from),
+            format,
+        });
+    }
+
+    Ok((images, missing))
+}
+
+/// Finds every `manifest.json` in the directory and its subdirectories
+fn find_manifests(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut manifests = Vec::new();
+    let mut dirs = vec![dir.to_owned()];
+
+    while let Some(dir) = dirs.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e).with_context(|| format!("Failed to read directory {:?}", dir)),
+        };
+
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.ends_with("manifest.json") {
+                manifests.push(path);
+            }
+        }
+    }
+
+    manifests.sort();
+    Ok(manifests)
+}
+
+/// Paths of all files in a directory and its subdirectories, relative to it
+fn list_files(dir: &Path) -> Result<BTreeSet<PathBuf>> {
+    let mut files = BTreeSet::new();
+    let mut dirs = vec![dir.to_owned()];
+
+    while let Some(current) = dirs.pop() {
+        let entries = match fs::read_dir(&current) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read directory {:?}", current))
+            }
+        };
+
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if let Ok(relative) = path.strip_prefix(dir) {
+                files.insert(relative.to_owned());
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+/// Lists the files that differ between the existing directory and the regenerated copy in
+/// `generated`, e.g. `+ page/state0.png` for a file that would be added. `-` is for files that
+/// would be removed, and `~` for files that would be changed.
+fn diff_dirs(existing: &Path, generated: &Path) -> Result<Vec<String>> {
+    let existing_files = list_files(existing)?;
+    let generated_files = list_files(generated)?;
+    let read = |path: &Path| fs::read(path).with_context(|| format!("Failed to read {:?}", path));
+
+    let mut changes = Vec::new();
+    for file in existing_files.union(&generated_files) {
+        let change = match (
+            existing_files.contains(file),
+            generated_files.contains(file),
+        ) {
+            (false, _) => '+',
+            (_, false) => '-',
+            _ if read(&existing.join(file))? != read(&generated.join(file))? => '~',
+            _ => continue,
+        };
+        changes.push(format!(
+            "{} {}",
+            change,
+            file.to_string_lossy().replace('\\', "/")
+        ));
+    }
+
+    Ok(changes)
+}
+
+/// For `--check`, fails with a summary of the differences if regenerating the profiles in
+/// `generated` changed anything compared to `existing`
+fn check_up_to_date(existing: &Path, generated: &Path) -> Result<()> {
+    let changes = diff_dirs(existing, generated)?;
+    if !changes.is_empty() {
+        bail!(
+            "Profiles in {:?} are out of date, {} would change. \
+            Re-run without --check to update them:\n{}",
+            existing,
+            plural(changes.len(), "file"),
+            changes.join("\n")
+        );
+    }
+    Ok(())
+}
+
+/// Directories that `write_profiles` would overwrite if they already exist. Pages other than the
+/// first are nested inside it, unless the layout is flat.
+fn overwrite_targets(
+    profiles: &ProfilesWithImages,
+    root_path: &Path,
+    layout: OutputLayout,
+) -> Vec<PathBuf> {
+    match (
+        layout,
+        profiles.manifests.first(),
+        profiles.parent_profile_uuid,
+    ) {
+        (OutputLayout::Flat, _, _) => (1..=profiles.manifests.len())
+            .map(|page| root_path.join(format!("page{}", page)))
+            .collect(),
+        (OutputLayout::Nested, Some((uuid, _)), Some(parent_uuid)) => vec![root_path
+            .join(sd_profile_dir(parent_uuid))
+            .join("Profiles")
+            .join(sd_profile_dir(*uuid))],
+        (OutputLayout::Nested, Some((uuid, _)), None) => {
+            vec![root_path.join(sd_profile_dir(*uuid))]
+        }
+        (OutputLayout::Nested, None, _) => Vec::new(),
+    }
+}
+
+/// Removes the pages left over from a previous run that had more pages. Only directories with the
+/// UUIDs that this tool derives for pages are removed, and only those after the current last page.
+fn remove_stale_pages(
+    profiles: &ProfilesWithImages,
+    root_path: &Path,
+    layout: OutputLayout,
+) -> Result<()> {
+    let page_count = profiles.manifests.len();
+    let remove_dir = |path: &Path| {
+        info!(?path, "Removing stale page");
+        fs::remove_dir_all(path).with_context(|| format!("Failed to remove directory {:?}", path))
+    };
+
+    if layout == OutputLayout::Flat {
+        for page in page_count + 1.. {
+            let path = root_path.join(format!("page{}", page));
+            if !path.exists() {
+                break;
+            }
+            remove_dir(&path)?;
+        }
+        return Ok(());
+    }
+
+    // Nested pages may have been moved up to the root's `Profiles` directory by the Stream Deck
+    // application, or to the parent's when nested in an existing profile
+    let search_dir = match (profiles.parent_profile_uuid, profiles.manifests.first()) {
+        (Some(parent_uuid), _) => root_path.join(sd_profile_dir(parent_uuid)),
+        (None, Some((uuid, _))) => root_path.join(sd_profile_dir(*uuid)),
+        (None, None) => return Ok(()),
+    };
+
+    let mut profile_dirs = HashMap::<OsString, Vec<PathBuf>>::new();
+    let mut dirs = vec![search_dir];
+    while let Some(dir) = dirs.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e).with_context(|| format!("Failed to read directory {:?}", dir)),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_dir() {
+                if let Some(name) = path.file_name() {
+                    profile_dirs
+                        .entry(name.to_owned())
+                        .or_default()
+                        .push(path.clone());
+                }
+                dirs.push(path);
+            }
+        }
+    }
+
+    // Each category's folder has pages of its own, derived from the category's seed. Without
+    // categories, every page is derived from the root's seed.
+    let category_pages = profiles.categories.iter().map(|c| c.pages).sum::<usize>();
+    let seeds = std::iter::once((profiles.uuid_seed.as_str(), page_count - category_pages)).chain(
+        profiles
+            .categories
+            .iter()
+            .map(|category| (category.uuid_seed.as_str(), category.pages)),
+    );
+
+    for (seed, page_count) in seeds {
+        // Pages are numbered without gaps, so the first missing one is the end of the old profile
+        for page in page_count.. {
+            let uuid = profile::uuid_v5(seed, page);
+            let paths = match profile_dirs.get(OsStr::new(&sd_profile_dir(uuid))) {
+                Some(paths) => paths,
+                None => break,
+            };
+            // Deeper pages may have been removed along with the one they were nested in
+            for path in paths.iter().filter(|path| path.exists()) {
+                remove_dir(path)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Fails if the directory exists and isn't empty, to avoid clobbering a profile by accident
+fn check_not_overwriting(path: &Path) -> Result<()> {
+    let is_empty = match fs::read_dir(path) {
+        Ok(mut entries) => entries.next().is_none(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => true,
+        Err(e) => return Err(e).with_context(|| format!("Failed to read directory {:?}", path)),
+    };
+
+    if !is_empty {
+        bail!(
+            "{:?} already exists and would be overwritten. Pass --force to update it (existing \
+            manifests are merged unless --no-merge is set), or choose a different --name or --out.",
+            path
+        );
+    }
+
+    Ok(())
+}
+
+fn sd_profile_dir(uuid: Uuid) -> String {
+    format!("{}.sdProfile", uuid.to_string().to_uppercase())
+}
+
+/// Adds a folder key to an existing parent profile, opening the given child profile. The key is
+/// placed in the first empty position, unless the parent already links to the child.
+fn link_child_profile(
+    parent_manifest_path: &Path,
+    child_uuid: Uuid,
+    child: &ProfileManifest,
+    pretty_json: bool,
+) -> Result<()> {
+    let string = fs::read_to_string(parent_manifest_path).with_context(|| {
+        format!(
+            "Failed to read parent profile manifest {:?}",
+            parent_manifest_path
+        )
+    })?;
+    let mut parent = serde_json::from_str::<Value>(&string).context("Invalid JSON")?;
+
+    // The key has to fit on the parent's device, which can be a different one than the child's
+    let parent_model_id = parent.pointer("/DeviceModel").and_then(|v| v.as_str());
+    let parent_model = match parent_model_id.and_then(DeviceModel::from_model_id) {
+        Some(model) => model,
+        None => {
+            warn!(
+                path = ?parent_manifest_path,
+                model_id = ?parent_model_id,
+                "Unrecognized device model in parent profile, assuming the same model as the profile"
+            );
+            child.device_model.clone()
+        }
+    };
+
+    let actions = if let Some(actions) = parent
+        .pointer_mut("/Actions")
+        .and_then(|json| json.as_object_mut())
+    {
+        actions
+    } else {
+        bail!("Parent manifest file has invalid `Actions` field");
+    };
+
+    let child_uuid_str = child_uuid.to_string().to_uppercase();
+    let already_linked = actions.values().any(|action| {
+        action
+            .pointer("/Settings/ProfileUUID")
+            .and_then(|v| v.as_str())
+            == Some(child_uuid_str.as_str())
+    });
+
+    if already_linked {
+        info!(path = ?parent_manifest_path, "Parent profile already links to profile");
+        return Ok(());
+    }
+
+    let (width, height) = parent_model.size();
+    let free_position = (0..height)
+        .flat_map(|y| (0..width).map(move |x| Position::new(x, y)))
+        .find(|pos| !actions.contains_key(&pos.to_string()));
+
+    let position = if let Some(position) = free_position {
+        position
+    } else {
+        bail!("Parent profile has no empty key to add a folder to");
+    };
+
+    let action = Action::open_child(&child.name, child_uuid, None);
+    actions.insert(position.to_string(), serde_json::to_value(&action)?);
+
+    fs::write(parent_manifest_path, manifest_bytes(&parent, pretty_json)?)
+        .with_context(|| format!("Failed to write file {:?}", parent_manifest_path))?;
+    info!(path = ?parent_manifest_path, %position, "Added folder to parent profile");
+
+    Ok(())
+}
+
+/// Writes the manifest and images for a single page to the given directory
+fn write_page(
+    manifest: &ProfileManifest,
+    path: &Path,
+    args: &Args,
+    written_files: &mut Vec<PathBuf>,
+) -> Result<()> {
+    fs::create_dir_all(path).with_context(|| format!("Failed to create path {:?}", path))?;
+
+    let manifest_path = path.join("manifest.json");
+    let mut json = serde_json::to_value(manifest)?;
+
+    let mut preserved = Vec::new();
+    if !args.no_merge {
+        match merge_manifests_if_exists(
+            &mut json,
+            &manifest_path,
+            args.preserve_custom_keys,
+            args.replace,
+            &args.extra_action,
+        ) {
+            Ok(positions) => preserved = positions,
+            Err(e) if args.strict => {
+                return Err(e).with_context(|| {
+                    format!("Failed to merge existing manifest file {:?}", manifest_path)
+                });
+            }
+            Err(e) => {
+                warn!(error = %e, path = ?manifest_path, "Failed to merge existing manifest file")
+            }
+        }
+    }
+
+    fs::write(&manifest_path, manifest_bytes(&json, args.pretty_json)?)
+        .with_context(|| format!("Failed to write file {:?}", &manifest_path))?;
+    written_files.push(manifest_path);
+
+    for (position, action) in manifest.actions.iter() {
+        // Keep the images of custom keys that replaced generated ones
+        if preserved.contains(&position.to_string()) {
+            continue;
+        }
+
+        let img_path = path
+            .join(format!("{},{}", position.x, position.y))
+            .join("CustomImages");
+
+        fs::create_dir_all(&img_path)
+            .with_context(|| format!("Failed to create path {:?}", &img_path))?;
+
+        let images = [&action.image, &action.dimmed_image];
+
+        for (state, image) in action.states.iter().zip(images.iter()) {
+            let img_file_path = img_path.join(&state.image);
+            if let Some(bytes) = image {
+                // Leave identical images alone, e.g. when they're reused by `--manifest-only`
+                let unchanged = fs::read(&img_file_path).is_ok_and(|old| old == bytes.as_ref());
+                if !unchanged {
+                    fs::write(&img_file_path, bytes)
+                        .with_context(|| format!("Failed to write image {:?}", &img_file_path))?;
+                }
+                written_files.push(img_file_path);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Serializes a manifest, on a single line unless `pretty` is set
+fn manifest_bytes(json: &Value, pretty: bool) -> Result<Vec<u8>> {
+    let bytes = if pretty {
+        serde_json::to_vec_pretty(json)?
+    } else {
+        serde_json::to_vec(json)?
+    };
+    Ok(bytes)
+}
+
+/// Writes the contents of a directory as a tar archive
+fn write_tar(dir: &Path, out: impl Write) -> Result<()> {
+    let mut builder = tar::Builder::new(out);
+    builder
+        .append_dir_all(".", dir)
+        .context("Failed to write tar archive")?;
+    builder
+        .into_inner()
+        .and_then(|mut out| out.flush())
+        .context("Failed to write tar archive")?;
+    Ok(())
+}
+
+/// Checks that every image referenced by a state of a generated key in the written manifests exists
+/// and isn't empty, returning the paths of the ones that don't. Keys with images that weren't
+/// written would otherwise show up blank. Keys kept from the existing profile are left alone, since
+/// their images are up to the Stream Deck application.
+fn missing_images(
+    written_files: &[PathBuf],
+    extra_actions: &[ExtraAction],
+) -> Result<Vec<PathBuf>> {
+    let mut missing = Vec::new();
+
+    for manifest_path in written_files
+        .iter()
+        .filter(|f| f.ends_with("manifest.json"))
+    {
+        let page_dir = manifest_path.parent().unwrap_or_else(|| Path::new(""));
+        let string = fs::read_to_string(manifest_path)
+            .with_context(|| format!("Failed to read file {:?}", manifest_path))?;
+        let manifest = serde_json::from_str::<Value>(&string)
+            .with_context(|| format!("Invalid JSON in {:?}", manifest_path))?;
+
+        let actions = manifest
+            .get("Actions")
+            .and_then(|actions| actions.as_object())
+            .into_iter()
+            .flatten();
+        let generated = actions.filter(|(position, action)| {
+            profile::is_generated_action(action, position, extra_actions)
+        });
+        for (position, action) in generated {
+            let images = action
+                .get("States")
+                .and_then(|states| states.as_array())
+                .into_iter()
+                .flatten()
+                .filter_map(|state| state.get("Image")?.as_str())
+                .filter(|image| !image.is_empty());
+
+            for image in images {
+                let path = page_dir.join(position).join("CustomImages").join(image);
+                let is_empty = fs::metadata(&path).map_or(true, |metadata| metadata.len() == 0);
+                if is_empty {
+                    missing.push(path);
+                }
+            }
+        }
+    }
+
+    Ok(missing)
+}
+
+/// Writes the SHA-256 checksum of each file, in the same format as `sha256sum`. Paths are relative
+/// to `root_path` and sorted, so the output of two runs can be compared directly.
+fn write_checksums(root_path: &Path, files: &[PathBuf], out: &Path) -> Result<()> {
+    let mut entries = files
+        .iter()
+        .map(|file| {
+            let bytes =
+                fs::read(file).with_context(|| format!("Failed to read file {:?}", file))?;
+            let relative = file.strip_prefix(root_path).unwrap_or(file);
+            let relative = relative.to_string_lossy().replace('\\', "/");
+            Ok((relative, Sha256::digest(&bytes)))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let mut lines = entries
+        .iter()
+        .map(|(relative, hash)| format!("{:x}  {}", hash, relative))
+        .collect::<Vec<_>>();
+    lines.push(String::new());
+
+    fs::write(out, lines.join("\n"))
+        .with_context(|| format!("Failed to write checksums to {:?}", out))?;
+    info!(path = ?out, files = files.len(), "Wrote checksums");
+
+    Ok(())
+}
+
+/// Copies actions from the existing manifest into positions that the new manifest doesn't use. If
+/// `preserve_custom_keys` is set, actions that weren't generated by this tool replace generated
+/// ones too, and their positions are returned. If `replace` is set, actions generated by this tool
+/// aren't copied, so that keys from a previous run don't linger. Website keys only count as
+/// generated if they match one of the `extra_actions`.
+fn merge_manifests_if_exists(
+    new_manifest: &mut Value,
+    existing_path: &Path,
+    preserve_custom_keys: bool,
+    replace: bool,
+    extra_actions: &[ExtraAction],
+) -> Result<Vec<String>> {
+    let string = match fs::read_to_string(existing_path) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).context("Could not read existing manifest file"),
+        Ok(s) => s,
+    };
+
+    let old_manifest = serde_json::from_str::<Value>(&string).context("Invalid JSON")?;
+
+    let old_actions = if let Some(actions) = old_manifest
+        .pointer("/Actions")
+        .and_then(|json| json.as_object())
+    {
+        actions
+    } else {
+        bail!("Existing manifest file has invalid `Actions` field");
+    };
+
+    let new_actions = if let Some(actions) = new_manifest
+        .pointer_mut("/Actions")
+        .and_then(|json| json.as_object_mut())
+    {
+        actions
+    } else {
+        bail!("New manifest file has invalid `Actions` field");
+    };
+
+    let mut preserved = Vec::new();
+    for (pos, action) in old_actions.into_iter() {
+        let is_custom = !profile::is_generated_action(action, pos, extra_actions);
+
+        match new_actions.get(pos) {
+            None if replace && !is_custom => {}
+            None => {
+                new_actions.insert(pos.to_owned(), action.clone());
+            }
+            Some(replaced) if preserve_custom_keys && is_custom => {
+                warn!(
+                    position = %pos,
+                    custom = ?action.get("Name"),
+                    replaced = ?replaced.pointer("/Settings/pastedText").or_else(|| replaced.get("Name")),
+                    "Keeping a custom key in place of a generated one"
+                );
+                new_actions.insert(pos.to_owned(), action.clone());
+                preserved.push(pos.to_owned());
+            }
+            Some(_) => {}
+        }
+    }
+
+    let old_version = old_manifest.get("Version").and_then(|v| v.as_str());
+    if old_version != Some(profile::MANIFEST_VERSION) {
+        info!(
+            path = ?existing_path,
+            version = ?old_version,
+            expected = profile::MANIFEST_VERSION,
+            "Existing manifest has a different version, keeping its unrecognized fields"
+        );
+    }
+
+    // Keep fields that the Stream Deck application added, even if we don't know about them
+    if let (Some(old_fields), Some(new_fields)) =
+        (old_manifest.as_object(), new_manifest.as_object_mut())
+    {
+        for (key, value) in old_fields {
+            if !new_fields.contains_key(key) {
+                new_fields.insert(key.clone(), value.clone());
+            }
+        }
+    }
+
+    Ok(preserved)
+}
+
+fn write_completions(shell: Shell, out: &mut impl Write) -> Result<()> {
+    Args::clap().gen_completions_to(env!("CARGO_PKG_NAME"), shell, out);
+    Ok(())
+}
+
+/// Whether to go ahead with `--restart`. Restarting interrupts anything using the Stream Deck (such
+/// as a live stream), so unless `--yes` is set, the user is asked first with `prompt`.
+fn confirm_restart(args: &Args, prompt: impl FnOnce(&str) -> Result<bool>) -> Result<bool> {
+    if args.yes {
+        return Ok(true);
+    }
+
+    let confirmed = prompt(
+        "Restart the Stream Deck application now? Anything using it, such as a live stream, will \
+        be interrupted.",
+    )?;
+    if !confirmed {
+        info!("Not restarting the Stream Deck application");
+    }
+    Ok(confirmed)
+}
+
+/// Asks a yes or no question on the terminal. The answer is no if stdin isn't a terminal (e.g.,
+/// when the HTML file is piped in), since nobody can answer.
+fn prompt_stdin(question: &str) -> Result<bool> {
+    let stdin = std::io::stdin();
+    if !stdin.is_terminal() {
+        warn!("Can't ask for confirmation without a terminal, pass --yes to restart anyway");
+        return Ok(false);
+    }
+
+    eprint!("{} [y/N] ", question);
+    let mut answer = String::new();
+    stdin
+        .lock()
+        .read_line(&mut answer)
+        .context("Failed to read answer")?;
+    Ok(matches!(answer.trim().to_lowercase().as_ref(), "y" | "yes"))
+}
+
+fn restart_stream_deck(strict: bool) -> Result<()> {
+    if !cfg!(target_os = "macos") {
+        if strict {
+            bail!("The --restart flag is currently only supported on macOS");
+        }
+        warn!(
+            "The --restart flag is currently only supported on macOS. \
+            See https://github.com/walfie/streamdeck-youtube-emotes/issues/1"
+        );
+        return Ok(());
+    }
+
+    info!("Restarting Stream Deck application");
+
+    let stop_result = Command::new("pkill").arg("Stream Deck").status();
+
+    if let Err(e) = stop_result {
+        if strict {
+            return Err(e).context("Failed to stop Stream Deck");
+        }
+        warn!(error = %e, "Failed to stop Stream Deck");
+    }
+
+    let start_result = Command::new("open")
+        .arg("/Applications/Stream Deck.app")
+        .status();
+
+    if let Err(e) = start_result {
+        if strict {
+            return Err(e).context("Failed to start Stream Deck");
+        }
+        warn!(error = %e, "Failed to start Stream Deck");
+    }
+
+    Ok(())
+}
+
+/// Command that lists the running Stream Deck application processes on the given OS (as in
+/// [`std::env::consts::OS`]), if it's available there. The command prints the process if the
+/// application is running.
+fn app_running_command(os: &str) -> Option<Command> {
+    match os {
+        "macos" => {
+            let mut command = Command::new("pgrep");
+            command.args(["-x", "Stream Deck"]);
+            Some(command)
+        }
+        "windows" => {
+            let mut command = Command::new("tasklist");
+            command.args(["/FI", "IMAGENAME eq StreamDeck.exe", "/NH"]);
+            Some(command)
+        }
+        _ => None,
+    }
+}
+
+/// Whether the Stream Deck application is running. If it can't be determined, it's assumed not to
+/// be, since this is only used for a warning.
+fn stream_deck_running() -> bool {
+    let output = match app_running_command(std::env::consts::OS).map(|mut c| c.output()) {
+        Some(Ok(output)) => output,
+        Some(Err(e)) => {
+            debug!(error = %e, "Failed to check whether Stream Deck is running");
+            return false;
+        }
+        None => return false,
+    };
+
+    // `tasklist` succeeds with an `INFO:` message when nothing matches
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stdout = stdout.trim();
+    output.status.success() && !stdout.is_empty() && !stdout.starts_with("INFO:")
+}
+
+/// Command that opens a directory in the file manager of the given OS (as in
+/// [`std::env::consts::OS`])
+fn file_manager_command(os: &str, path: &Path) -> Command {
+    let program = match os {
+        "macos" => "open",
+        "windows" => "explorer",
+        _ => "xdg-open",
+    };
+
+    let mut command = Command::new(program);
+    command.arg(path);
+    command
+}
+
+/// Reveals the generated profile. Failures are only logged, since the profile was already written.
+fn open_in_file_manager(path: &Path) {
+    let mut command = file_manager_command(std::env::consts::OS, path);
+    info!(?path, "Opening profile directory");
+
+    match command.status() {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            warn!(program = ?command.get_program(), "File manager command not found")
+        }
+        Err(e) => warn!(error = %e, "Failed to open profile directory"),
+        // Explorer exits with a non-zero status even when it succeeds
+        Ok(status) if !status.success() && !cfg!(target_os = "windows") => {
+            warn!(%status, "Failed to open profile directory")
+        }
+        Ok(_) => {}
+    }
+}
+
+/// How the generated profiles are laid out on the filesystem
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputLayout {
+    /// Each page is nested inside the previous one, as expected by the Stream Deck application
+    Nested,
+    /// Each page is written to its own `page<N>` directory, for inspection only
+    Flat,
+}
+
+impl FromStr for OutputLayout {
+    type Err = color_eyre::eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_ref() {
+            "nested" => Ok(OutputLayout::Nested),
+            "flat" => Ok(OutputLayout::Flat),
+            other => bail!("Unknown layout {}", other),
+        }
+    }
+}
+
+/// A device model paired with the UUID of a specific device, in the form `model=xl,uuid=...`
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeviceSpec {
+    pub model: DeviceModel,
+    pub uuid: Option<String>,
+}
+
+impl FromStr for DeviceSpec {
+    type Err = color_eyre::eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut model = None;
+        let mut uuid = None;
+
+        for field in s.split(',') {
+            let (key, value) = match field.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => bail!(
+                    "Expected a device in the form model=MODEL,uuid=UUID, got {:?}",
+                    s
+                ),
+            };
+            let duplicate = match key.to_lowercase().as_ref() {
+                "model" => model.replace(value.parse::<DeviceModel>()?).is_some(),
+                "uuid" => uuid.replace(value.to_owned()).is_some(),
+                other => bail!("Unknown device field {:?} in {:?}", other, s),
+            };
+            if duplicate {
+                bail!("Device field {:?} is given more than once in {:?}", key, s);
+            }
+        }
+
+        match model {
+            Some(model) => Ok(DeviceSpec {
+                model,
+                uuid: uuid.filter(|uuid| !uuid.is_empty()),
+            }),
+            None => bail!("Missing model in device {:?}", s),
+        }
+    }
+}
+
+/// Hidden subcommand, handled before the main arguments are parsed
+#[derive(StructOpt)]
+#[structopt(name = env!("CARGO_PKG_NAME"))]
+enum Completions {
+    /// Print a shell completion script to stdout.
+    ///
+    /// For example, to install completions for bash, add the following to `~/.bashrc`:
+    ///
+    ///     source <(streamdeck-youtube-emotes completions bash)
+    ///
+    /// For zsh, save the output to a file named `_streamdeck-youtube-emotes` in a directory listed
+    /// in `$fpath`. For fish, save it to `~/.config/fish/completions/streamdeck-youtube-emotes.fish`.
+    /// For PowerShell, add the output to the file at `$PROFILE`.
+    Completions {
+        #[structopt(possible_values = &Shell::variants(), case_insensitive = true)]
+        shell: Shell,
+    },
+}
+
+#[derive(StructOpt)]
+pub struct Args {
+    /// Path to an HTML file containing the memberships page for a channel.
+    /// E.g., Download the following page in a browser while logged in:
+    /// https://www.youtube.com/channel/UCP4nMSTdwU1KqYWu3UH5DHQ/memberships
+    ///
+    /// Use - to read from stdin.
+    #[structopt(
+        parse(from_os_str),
+        long,
+        required_unless_one = &["emotes-json", "channel-url", "images-dir"]
+    )]
+    pub html_file: Option<PathBuf>,
+
+    /// URL of a channel's memberships page to download, instead of saving it with a browser. Since
+    /// the emotes are only listed for members, this usually requires `--cookies`.
+    #[structopt(long, conflicts_with_all = &["html-file", "emotes-json"])]
+    pub channel_url: Option<Url>,
+
+    /// Path to a cookie file in the Netscape format (as exported by browser extensions), with the
+    /// cookies of a YouTube account that's a member of the channel. Used with `--channel-url`.
+    #[structopt(parse(from_os_str), long, requires = "channel-url")]
+    pub cookies: Option<PathBuf>,
+
+    /// Path to a JSON file listing emotes to use instead of a memberships page, in the form
+    /// `[{ "name": "...", "url": "...", "tier": "...", "id": "..." }]` (`tier` and `id` are
+    /// optional). Requires `--name`.
+    #[structopt(parse(from_os_str), long, conflicts_with = "html-file")]
+    pub emotes_json: Option<PathBuf>,
+
+    /// Directory of image files (PNG, JPEG, GIF, or WebP) to use as emotes instead of a
+    /// memberships page, each named after its file (e.g., `small9cm.png` is `small9cm`). Nothing
+    /// is downloaded. Requires `--name`.
+    #[structopt(
+        parse(from_os_str),
+        long,
+        conflicts_with_all = &["html-file", "channel-url", "emotes-json", "offline"]
+    )]
+    pub images_dir: Option<PathBuf>,
+
+    /// Write the `ytInitialData` JSON found in the memberships page to this path, before the
+    /// emotes are parsed from it. Useful for inspecting the page structure or sharing a reproducer
+    /// when parsing fails, since the rest of the page isn't included.
+    #[structopt(parse(from_os_str), long, conflicts_with = "emotes-json")]
+    pub dump_ytinitialdata: Option<PathBuf>,
+
+    /// Which image to download when the page lists several sizes of an emote. `largest` picks the
+    /// one with the most pixels (or the last one, if sizes aren't listed), while `first` picks the
+    /// first one listed.
+    #[structopt(long, default_value = "largest", possible_values = &["first", "largest"])]
+    pub thumbnail_select: youtube::ThumbnailSelect,
+
+    /// The emote prefix (also known as "family name"). For example, if the channel has an emote
+    /// `:_pomuSmall9cm:`, the emote prefix would be `pomu`. For some channels, there is no prefix,
+    /// so this option can be omitted.
+    ///
+    /// The prefix can also be given with its leading underscore (`_pomu`) or as the start of an
+    /// emote code (`:_pomu`), all of which are treated the same as `pomu`.
+    #[structopt(default_value = "", long)]
+    pub prefix: String,
+
+    /// Leave out the underscore at the start of emote codes, e.g. `:pomuSmall9cm:` instead of
+    /// `:_pomuSmall9cm:`, for chat systems that don't use it. The prefix is still normalized, so
+    /// `--prefix _pomu` gives the same codes as `--prefix pomu`.
+    #[structopt(long)]
+    pub no_underscore: bool,
+
+    /// How to transform emote names before inserting them into the pasted text. Defaults to
+    /// `capitalize-first` if a prefix is set (e.g., `:_pomuSmall9cm:`), otherwise `as-is`.
+    #[structopt(
+        long,
+        possible_values = &["as-is", "capitalize-first", "lowercase", "uppercase"]
+    )]
+    pub name_case: Option<NameCase>,
+
+    /// Text to paste instead of the emote's code, for chat systems that don't use YouTube's
+    /// `:_pomuSmall9cm:` syntax. `{prefix}` is replaced with the prefix, `{name}` with the emote
+    /// name (after `--name-case`), and `{Name}` with the name with its first letter capitalized.
+    /// Other text is pasted as is, e.g. `"{Name} "` pastes `Small9cm ` followed by a space.
+    #[structopt(long)]
+    pub text_template: Option<String>,
+
+    /// Name of the Stream Deck profile. Note that if the `profile-uuid` argument is unspecified, this name will
+    /// be used to determine the name of the output profile directory.
+    ///
+    /// If unspecified, the name of the channel is used.
+    #[structopt(long)]
+    pub name: Option<String>,
+
+    /// Always name the profile after the channel in the HTML file, even if `--name` is set (e.g.,
+    /// in a shell alias), and fail if the channel name can't be found
+    #[structopt(long, conflicts_with = "emotes-json")]
+    pub name_from_html: bool,
+
+    /// Device UUID for the Stream Deck. When `--model` is repeated, each device UUID is paired
+    /// with the model at the same position.
+    #[structopt(long, number_of_values = 1)]
+    pub device_uuid: Vec<String>,
+
+    /// Stream Deck to generate a profile for, as a model and the UUID of the device, e.g.
+    /// `--device model=xl,uuid=@(1)[4057/108/CL1234]`. Can be repeated for each device, as an
+    /// alternative to pairing up `--model` and `--device-uuid`.
+    #[structopt(
+        long,
+        number_of_values = 1,
+        conflicts_with_all = &["model", "device-uuid"]
+    )]
+    pub device: Vec<DeviceSpec>,
+
+    /// Path to the `manifest.json` of an existing profile for the Stream Deck. Its device model and
+    /// device UUID are used unless `--model` or `--device-uuid` are specified.
+    #[structopt(long)]
+    pub from_existing: Option<PathBuf>,
+
+    /// Override the UUID for the profile
+    #[structopt(long)]
+    pub profile_uuid: Option<Uuid>,
+
+    /// UUID of an existing profile to nest the generated profile in, as a folder. A folder key is
+    /// added to the first empty key of the existing profile.
+    #[structopt(long)]
+    pub parent_profile_uuid: Option<Uuid>,
+
+    /// Whether to include the name of the emote on each key
+    #[structopt(long)]
+    pub include_labels: bool,
+
+    /// Font size of the labels from `--include-labels`, from 6 to 40
+    #[structopt(long)]
+    pub label_size: Option<LabelSize>,
+
+    /// Shorten labels longer than this many characters, ending them with an ellipsis. Must be at
+    /// least 1.
+    #[structopt(long)]
+    pub label_max_chars: Option<NonZeroUsize>,
+
+    /// With `--label-max-chars`, wrap labels onto more lines at spaces, so that each line fits in
+    /// the limit instead of the whole label
+    #[structopt(long, requires = "label-max-chars")]
+    pub label_wrap: bool,
+
+    /// Overwrite existing manifest files instead of merging them.
+    #[structopt(long)]
+    pub no_merge: bool,
+
+    /// Write manifests as indented JSON instead of on a single line, for readable diffs when the
+    /// profiles are kept in version control. The Stream Deck application reads either format.
+    #[structopt(long)]
+    pub pretty_json: bool,
+
+    /// When merging, keep keys that were added in the Stream Deck application (anything other than
+    /// text, folder, and profile switching keys, and website keys from `--extra-action`) even where
+    /// a generated key would go. The generated key is left out of the profile.
+    #[structopt(long, conflicts_with = "no-merge")]
+    pub preserve_custom_keys: bool,
+
+    /// Allow writing to a profile directory that already exists. Without this, existing profiles
+    /// aren't modified.
+    #[structopt(long)]
+    pub force: bool,
+
+    /// Overwrite an existing profile like `--force`, and also remove any pages left over from a
+    /// previous run with more emotes
+    #[structopt(long)]
+    pub replace: bool,
+
+    /// Don't write anything, and instead fail if regenerating the profile would change any files
+    /// in the output directory, listing the ones that differ. Useful for checking that committed
+    /// profiles are up to date in CI.
+    #[structopt(long, conflicts_with_all = &["open", "restart", "cheatsheet", "checksums"])]
+    pub check: bool,
+
+    /// Build the profiles in this directory first (which must be empty), then move the ones that
+    /// changed into the output directory at the end, so that a failed or interrupted run doesn't
+    /// leave a partly written profile behind. Starts with a copy of the output directory, so it
+    /// needs as much free space. The directory is removed afterwards.
+    ///
+    /// The Stream Deck application isn't stopped while the profiles are moved, so quit it first if
+    /// it could save its own changes at the same time (or use `--restart` to restart it after).
+    #[structopt(long, conflicts_with = "check")]
+    pub stage_dir: Option<PathBuf>,
+
+    /// Output path to save the profile to. If unspecified, profiles will be saved to the
+    /// directory in the `STREAMDECK_PROFILES_DIR` environment variable if it's set, or the
+    /// default Stream Deck profile location (depending on platform) otherwise.
+    ///
+    /// Use - to write the profile directory to stdout as a tar archive.
+    #[structopt(long)]
+    pub out: Option<PathBuf>,
+
+    /// Version of the Stream Deck profiles directory to save to when `--out` is unspecified. Use
+    /// v1 for older versions of the Stream Deck application, which use `ProfilesV1`.
+    #[structopt(long, default_value = "v2", possible_values = &["v1", "v2"])]
+    pub profiles_version: ProfilesVersion,
+
+    /// How to lay out the generated files. `nested` is the structure expected by the Stream Deck
+    /// application. `flat` writes each page to its own `page<N>` directory, which is easier to
+    /// inspect by hand but can't be imported by the application.
+    #[structopt(long, default_value = "nested", possible_values = &["nested", "flat"])]
+    pub layout: OutputLayout,
+
+    /// Write a Markdown cheat sheet to this path, listing the emote and pasted text for each key on
+    /// each page
+    #[structopt(long)]
+    pub cheatsheet: Option<PathBuf>,
+
+    /// Write the SHA-256 checksum of every generated file to this path, to check whether two runs
+    /// produced the same output. Manifests are expected to be stable across runs with the same
+    /// options, while images are only stable as long as YouTube serves the same bytes.
+    #[structopt(long)]
+    pub checksums: Option<PathBuf>,
+
+    /// Path to a JSON file with custom labels and pasted text for specific emotes, keyed by emote
+    /// name (case-insensitive). E.g., `{ "small9cm": { "label": "9cm", "pasted_text": ":_pomu9cm:" } }`
+    ///
+    /// The label can also be styled per emote with `title_color` (`#RRGGBB`), `title_alignment`
+    /// (`top`, `middle`, or `bottom`), and `show_label` (`true` or `false`), which take precedence
+    /// over the command line options.
+    #[structopt(long)]
+    pub overrides: Option<PathBuf>,
+
+    /// JSON file that maps emote names (case-insensitive) to categories, e.g.
+    /// `{ "small9cm": "Reactions", "gm": "Greetings" }`. Each category gets its own folder, opened
+    /// from a key on the root page, and emotes that aren't listed go in an "Other" folder.
+    ///
+    /// Can't be used with `--plan-only` or `--suggest-model`, which only plan flat layouts.
+    #[structopt(long, conflicts_with_all = &["start-position", "plan-only", "suggest-model"])]
+    pub category_file: Option<PathBuf>,
+
+    /// List of emotes that should appear first, before all others (case-insensitive)
+    #[structopt(long)]
+    pub prioritize: Vec<String>,
+
+    /// List of emotes that should appear last, after all others (case-insensitive)
+    #[structopt(long)]
+    pub deprioritize: Vec<String>,
+
+    /// File with more emotes to put first, one name per line, after the ones in `--prioritize`.
+    /// Blank lines and lines starting with `#` are ignored.
+    #[structopt(long)]
+    pub prioritize_file: Option<PathBuf>,
+
+    /// File with more emotes to put last, one name per line, after the ones in `--deprioritize`.
+    /// Blank lines and lines starting with `#` are ignored.
+    #[structopt(long)]
+    pub deprioritize_file: Option<PathBuf>,
+
+    /// Snapshot file from `--save-snapshot` to compare the emotes against. The emotes that aren't
+    /// in the snapshot are logged as new.
+    #[structopt(long)]
+    pub since: Option<PathBuf>,
+
+    /// Only include the emotes that are new since the `--since` snapshot
+    #[structopt(long, requires = "since")]
+    pub only_new: bool,
+
+    /// After the profile is written, save the names and URLs of all emotes to this file, for use
+    /// with `--since` later
+    #[structopt(long)]
+    pub save_snapshot: Option<PathBuf>,
+
+    /// Only include emotes from this membership tier (case-insensitive). Can be repeated.
+    #[structopt(long, number_of_values = 1)]
+    pub tier: Vec<String>,
+
+    /// Choose which emotes to include and their order from a checklist in the terminal, before
+    /// any images are downloaded
+    #[cfg(feature = "interactive")]
+    #[structopt(long)]
+    pub interactive: bool,
+
+    /// Keep running after generating the profile, and generate it again whenever the input files
+    /// change (the HTML file, `--emotes-json`, `--overrides`, and the prioritize files). Existing
+    /// profiles are overwritten after the first run, as with `--force`.
+    #[cfg(feature = "watch")]
+    #[structopt(
+        long,
+        conflicts_with_all = &["channel-url", "check", "plan-only", "print-codes", "suggest-model"]
+    )]
+    pub watch: bool,
+
+    /// Fail if fewer than this many emotes are found in the HTML file, counting only the ones left
+    /// after `--tier` and `--limit`
+    #[structopt(long, default_value = "1")]
+    pub min_emotes: usize,
+
+    /// Only use the first N emotes, after applying `prioritize` and `deprioritize`
+    #[structopt(long)]
+    pub limit: Option<usize>,
+
+    /// The Stream Deck model to generate the profile for. Required unless `--from-existing` is
+    /// specified. Can be repeated to generate a separate profile for each model.
+    #[structopt(long, number_of_values = 1, possible_values = &["standard", "xl", "mini", "neo"])]
+    pub model: Vec<DeviceModel>,
+
+    /// Filter used to resize emote images to the key size of the Stream Deck model (e.g., 72x72
+    /// pixels for the standard model), from fastest (`nearest`, which also keeps pixel art sharp)
+    /// to smoothest (`lanczos3`)
+    #[structopt(
+        long,
+        default_value = "lanczos3",
+        possible_values = &["nearest", "triangle", "catmull-rom", "gaussian", "lanczos3"]
+    )]
+    pub resize_filter: ResizeFilter,
+
+    /// Leave a margin of this percentage of the key's size (from 0 to 49) around each emote, so
+    /// that it doesn't touch the edges of the key. E.g., 10 scales the emote to 80% of the key.
+    #[structopt(long, default_value = "0")]
+    pub key_padding: KeyPadding,
+
+    /// Sharpen images after resizing them, which can help small emotes that are scaled up
+    #[structopt(long)]
+    pub sharpen: bool,
+
+    /// How to order emotes once their images are downloaded. `original` keeps the order from the
+    /// page (after `--prioritize` and `--deprioritize`), while `by-size` puts the largest images
+    /// first, by pixel dimensions and then file size.
+    #[structopt(long, default_value = "original", possible_values = &["original", "by-size"])]
+    pub sort: EmoteSort,
+
+    /// Name for each page after the first, shown in the Stream Deck application's list of
+    /// profiles. `{name}` is replaced with the profile name and `{page}` with the page number, e.g.
+    /// `"{name} {page}"`. If unspecified, every page uses the profile name.
+    #[structopt(long)]
+    pub page_name_template: Option<String>,
+
+    /// Label color for emotes in a membership tier, in the form `TIER=#RRGGBB`. Can be repeated.
+    /// Emotes in other tiers use the default color.
+    #[structopt(long, number_of_values = 1)]
+    pub tier_colors: Vec<TierColor>,
+
+    /// Draw a colored dot in a corner of each emote's image, showing its membership tier. Tiers
+    /// use their `--tier-color`, or otherwise green, blue, purple, orange, and red, from the
+    /// lowest tier. Emotes whose tier isn't known don't get a badge.
+    #[structopt(long)]
+    pub tier_badges: bool,
+
+    /// Corner of the key to draw `--tier-badges` in
+    #[structopt(
+        long,
+        default_value = "top-right",
+        possible_values = &["top-left", "top-right", "bottom-left", "bottom-right"]
+    )]
+    pub badge_corner: BadgeCorner,
+
+    /// Diameter of `--tier-badges`, as a percentage of the key's size (from 1 to 50)
+    #[structopt(long, default_value = "25")]
+    pub badge_size: BadgeSize,
+
+    /// Make a key open a website (with the application's `com.elgato.streamdeck.system.website`
+    /// action) instead of pasting text, in the form `NAME=URL`, where `NAME` is an emote name
+    /// (case-insensitive) or a key position on the first page such as `0,1`. A position can be one
+    /// of the keys reserved for navigation, as long as it isn't used. Can be repeated.
+    #[structopt(long, number_of_values = 1)]
+    pub extra_action: Vec<ExtraAction>,
+
+    /// Give each emote key a second, darkened state. The Stream Deck application switches a key to
+    /// its other state every time it's pressed, so pressing a key dims it and pressing it again
+    /// brings it back, e.g. to keep track of emotes that were already used. The application can't
+    /// show an image only while a text key is held down.
+    #[structopt(long)]
+    pub toggle_dim: bool,
+
+    /// Format to save key images in. `jpeg` gives smaller files but no transparency (transparent
+    /// pixels become black), while `auto` uses JPEG only for images without transparent pixels.
+    /// Animated emotes are still saved as GIF with `--animated`.
+    #[structopt(long, default_value = "png", possible_values = &["png", "jpeg", "auto"])]
+    pub image_format: KeyImageFormat,
+
+    /// Keep animated emotes (GIF or WebP) animated, by saving them as GIF images. Other emotes, and
+    /// the dimmed state from `--toggle-dim`, are still static
+    #[structopt(long)]
+    pub animated: bool,
+
+    /// Skip animated emotes (GIF or WebP images with more than one frame) entirely, instead of
+    /// using their first frame
+    #[structopt(long, conflicts_with = "animated")]
+    pub no_animated: bool,
+
+    /// Which keys to reserve for navigating between pages. `minimal` reserves the leftmost column,
+    /// with Back and Next keys in its corners. `row` reserves the bottom row, with Back, Home, and
+    /// Next keys.
+    #[structopt(long, default_value = "minimal", possible_values = &["minimal", "row"])]
+    pub nav_layout: NavLayout,
+
+    /// Skip emotes whose downloaded image is smaller than this many pixels in width or height,
+    /// since they look blurry when scaled up to fill a key
+    #[structopt(long)]
+    pub min_source_resolution: Option<u32>,
+
+    /// Also save the downloaded images to this directory as they are, before resizing or any other
+    /// processing. Files are named after the emotes.
+    #[structopt(long, parse(from_os_str))]
+    pub save_originals: Option<PathBuf>,
+
+    /// Only regenerate the manifests, reusing the images of the existing profile instead of
+    /// downloading them again. Useful for changing the order or labels of the same emotes. Fails if
+    /// any emote isn't in the existing profile yet.
+    #[structopt(long)]
+    pub manifest_only: bool,
+
+    /// Center the emotes on the last row of the last page, instead of leaving the partial row
+    /// aligned to the left
+    #[structopt(long)]
+    pub center_last_page: bool,
+
+    /// Position of the key to place the first emote on, as `x,y` with `0,0` at the top left. The
+    /// keys before it on the first page are left empty, so that other buttons can be added there.
+    #[structopt(long)]
+    pub start_position: Option<Position>,
+
+    /// Print the text that each emote's key would paste (e.g., `:_pomuSmall9cm:`), one per line,
+    /// and exit without downloading images or writing a profile. `--model` isn't required.
+    #[structopt(long, conflicts_with = "plan-only")]
+    pub print_codes: bool,
+
+    /// Print how many emotes each device model holds on one screen, and how many pages each would
+    /// need for these emotes, to help choose a model. `--model` isn't required.
+    #[structopt(long, conflicts_with_all = &["plan-only", "print-codes"])]
+    pub suggest_model: bool,
+
+    /// Print how many pages the emotes would be split into, and how full they'd be, without
+    /// downloading images or writing anything
+    #[structopt(long)]
+    pub plan_only: bool,
+
+    /// Check that each emote's image URL can be downloaded, using HEAD requests so that the images
+    /// themselves aren't downloaded, and print the ones that fail. Nothing is written.
+    #[structopt(
+        long,
+        conflicts_with_all = &["plan-only", "print-codes", "suggest-model", "offline", "images-dir", "check"]
+    )]
+    pub validate_urls: bool,
+
+    /// Format of the output of `--print-codes`, `--plan-only`, and `--suggest-model`. `json` prints
+    /// a single object with a `schema_version` field, for use by other programs.
+    #[structopt(long, default_value = "text", possible_values = &["text", "json"])]
+    pub format: OutputFormat,
+
+    /// If all emotes fit on one page when the keys reserved by `--nav-layout` are used too, put
+    /// them there instead of adding a second page. A Back key is still kept if the profile is
+    /// nested in `--parent-profile-uuid`.
+    #[structopt(long)]
+    pub compact: bool,
+
+    /// Put the emotes on a single page without any navigation keys, not even a Back key. Emotes
+    /// that don't fit on the page are left out, with a warning.
+    #[structopt(long, conflicts_with = "category-file")]
+    pub no_navigation: bool,
+
+    /// Leave out emotes whose image is identical to an earlier emote's, e.g. the same art used
+    /// under another name or in another tier. Images are compared by their pixels, so the same
+    /// image saved in another format is also a duplicate.
+    #[structopt(long)]
+    pub dedupe_by_image: bool,
+
+    /// How pages of emotes link to each other. `chain` adds a Next key to each page, which opens
+    /// the following page one folder deeper. `index` adds an index page with a key for each page,
+    /// so that every page is one folder away.
+    #[structopt(long, default_value = "chain", possible_values = &["chain", "index"])]
+    pub nav_style: NavStyle,
+
+    /// Open the generated profile's directory in the file manager after writing it
+    #[structopt(long)]
+    pub open: bool,
+
+    /// Restart the Stream Deck application after creating the profile. Asks for confirmation
+    /// first, unless `--yes` is set.
+    #[structopt(long)]
+    pub restart: bool,
+
+    /// Don't ask for confirmation, e.g. before `--restart`
+    #[structopt(long, short = "y", alias = "non-interactive")]
+    pub yes: bool,
+
+    /// Proxy to use for downloading images, e.g. `http://proxy.example.com:8080`. If unspecified,
+    /// the `HTTPS_PROXY` and `HTTP_PROXY` environment variables are used.
+    #[structopt(long)]
+    pub proxy: Option<Url>,
+
+    /// Directory to cache downloaded images in, so that later runs don't download them again
+    #[structopt(long)]
+    pub cache_dir: Option<PathBuf>,
+
+    /// Don't download anything, and only use the images in `--cache-dir`. Emotes whose images
+    /// aren't cached are skipped.
+    #[structopt(long, requires = "cache-dir", conflicts_with = "channel-url")]
+    pub offline: bool,
+
+    /// Skip emotes whose images fail to download, instead of failing, and write their names, URLs,
+    /// and errors to this JSON file. Pass it to `--retry-failed` later to download only those.
+    #[structopt(long)]
+    pub failure_report: Option<PathBuf>,
+
+    /// Only download the emotes listed in a report written by `--failure-report`, and add them to
+    /// the existing profile, reusing its images for the other emotes like `--manifest-only`.
+    /// Emotes that still fail make the run fail, unless `--failure-report` is given too.
+    #[structopt(long, conflicts_with = "manifest-only")]
+    pub retry_failed: Option<PathBuf>,
+
+    /// Maximum number of redirects to follow for each download, before failing with an error
+    #[structopt(long, default_value = "5")]
+    pub max_redirects: usize,
+
+    /// Fail instead of logging a warning when something goes wrong but the profile could still be
+    /// generated (e.g., a decorated prefix, duplicate emote codes, or an existing manifest that
+    /// can't be merged).
+    #[structopt(long)]
+    pub strict: bool,
+
+    /// Only log warnings and errors. The summary line is still printed on success.
+    #[structopt(long, short)]
+    pub quiet: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetch::StubFetcher;
+    use crate::processing::tests::fixture_png;
+    use crate::profile::tests::{emote_images, placeholder_images};
+
+    fn args(extra: &[&str]) -> Args {
+        let base = ["streamdeck-youtube-emotes", "--html-file", "-"];
+        let required = ["--name", "Emotes", "--model", "standard"];
+        Args::from_iter(base.iter().chain(&required).chain(extra))
+    }
+
+    fn profiles_with_pages(pages: usize) -> ProfilesWithImages {
+        let manifests = (0..pages)
+            .map(|page| {
+                let manifest = ProfileManifest {
+                    actions: HashMap::new(),
+                    device_model: DeviceModel::Standard,
+                    device_uuid: "".into(),
+                    name: "Emotes".into(),
+                    version: "1.0".into(),
+                };
+
+                (profile::uuid_v5("Emotes", page), manifest)
+            })
+            .collect();
+
+        ProfilesWithImages {
+            manifests,
+            duplicate_pasted_texts: Vec::new(),
+            parent_profile_uuid: None,
+            nav_style: NavStyle::Chain,
+            downloaded_bytes: 0,
+            uuid_seed: "Emotes".into(),
+            categories: Vec::new(),
+            dropped_categories: Vec::new(),
+            failed_downloads: Vec::new(),
+        }
+    }
+
+    fn profiles() -> ProfilesWithImages {
+        profiles_with_pages(1)
+    }
+
+    /// Log output captured by [`capture_logs`]
+    #[derive(Clone, Default)]
+    struct Logs(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl Logs {
+        fn contents(&self) -> String {
+            String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+        }
+    }
+
+    impl Write for Logs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Records the logs of the current thread until the guard is dropped
+    fn capture_logs() -> (Logs, tracing::subscriber::DefaultGuard) {
+        let logs = Logs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        (logs, tracing::subscriber::set_default(subscriber))
+    }
+
+    #[test]
+    fn extra_action_without_pages_warns() -> Result<()> {
+        let args = args(&[
+            "--no-navigation",
+            "--extra-action",
+            "0,1=https://example.com",
+        ]);
+        let options = profile_options(&args, "Emotes", DeviceModel::Standard, String::new(), false);
+
+        let (logs, _guard) = capture_logs();
+        let profiles = ProfilesWithImages::from_images(Vec::new(), &options);
+        assert!(profiles.manifests.is_empty());
+        assert!(
+            logs.contents()
+                .contains("Extra action has no page to go on"),
+            "{}",
+            logs.contents()
+        );
+        assert!(!logs.contents().contains("already used"));
+
+        Ok(())
+    }
+
+    /// Profiles with the default options, laid out from `count` emotes with placeholder images
+    fn generated_profiles(count: usize) -> ProfilesWithImages {
+        let options = profile_options(
+            &args(&[]),
+            "Emotes",
+            DeviceModel::Standard,
+            "".into(),
+            false,
+        );
+        ProfilesWithImages::from_images(emote_images(count), &options)
+    }
+
+    #[test]
+    fn checksums_are_stable() -> Result<()> {
+        let mut outputs = Vec::new();
+        for _ in 0..2 {
+            let dir = tempfile::tempdir()?;
+            let files = write_profiles(generated_profiles(20), dir.path().to_owned(), &args(&[]))?;
+
+            let checksums_path = dir.path().join("checksums.txt");
+            write_checksums(dir.path(), &files, &checksums_path)?;
+            outputs.push(fs::read_to_string(checksums_path)?);
+        }
+
+        assert_eq!(outputs[0], outputs[1]);
+        assert_eq!(outputs[0].lines().count(), 20 + 2 + 2);
+        assert!(outputs[0].contains("/manifest.json\n"));
+
+        Ok(())
+    }
+
+    fn read_dir_recursive(root: &Path) -> Result<Vec<(PathBuf, Vec<u8>)>> {
+        let mut files = Vec::new();
+        let mut dirs = vec![root.to_owned()];
+
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else {
+                    let contents = fs::read(&path)?;
+                    files.push((path.strip_prefix(root)?.to_owned(), contents));
+                }
+            }
+        }
+
+        files.sort();
+        Ok(files)
+    }
+
+    #[test]
+    fn tar_matches_filesystem() -> Result<()> {
+        let expected_dir = tempfile::tempdir()?;
+        write_profiles(
+            profiles_with_pages(3),
+            expected_dir.path().to_owned(),
+            &args(&[]),
+        )?;
+
+        let staging_dir = tempfile::tempdir()?;
+        write_profiles(
+            profiles_with_pages(3),
+            staging_dir.path().to_owned(),
+            &args(&[]),
+        )?;
+        let mut archive = Vec::new();
+        write_tar(staging_dir.path(), &mut archive)?;
+
+        let extracted_dir = tempfile::tempdir()?;
+        tar::Archive::new(archive.as_slice()).unpack(extracted_dir.path())?;
+
+        let expected = read_dir_recursive(expected_dir.path())?;
+        assert_eq!(expected.len(), 3);
+        assert_eq!(expected, read_dir_recursive(extracted_dir.path())?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn nested_in_parent_profile() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let parent_uuid = Uuid::parse_str("AC20BCF3-0A7C-4243-BB74-5C0DC5681BA5")?;
+        let parent_dir = dir.path().join(sd_profile_dir(parent_uuid));
+        fs::create_dir_all(&parent_dir)?;
+        fs::write(
+            parent_dir.join("manifest.json"),
+            r#"{"Actions":{"0,0":{"Name":"Hotkey"}},"Name":"Home"}"#,
+        )?;
+
+        // Writing twice shouldn't add a second folder key to the parent
+        for _ in 0..2 {
+            let mut profiles = profiles_with_pages(2);
+            profiles.parent_profile_uuid = Some(parent_uuid);
+            write_profiles(profiles, dir.path().to_owned(), &args(&["--force"]))?;
+        }
+
+        let root_uuid = profile::uuid_v5("Emotes", 0);
+        let root_dir = parent_dir.join("Profiles").join(sd_profile_dir(root_uuid));
+        assert!(root_dir.join("manifest.json").is_file());
+
+        let parent = serde_json::from_slice::<Value>(&fs::read(parent_dir.join("manifest.json"))?)?;
+        let actions = parent["Actions"].as_object().unwrap();
+        assert_eq!(actions.len(), 2);
+        assert_eq!(actions["0,0"]["Name"], "Hotkey");
+        assert_eq!(
+            actions["1,0"]["Settings"]["ProfileUUID"],
+            root_uuid.to_string().to_uppercase()
+        );
+
+        // The folder key goes on the parent's grid, even if the child is for a bigger device
+        let neo_actions = (0..4)
+            .map(|x| (format!("{},0", x), serde_json::json!({ "Name": "Hotkey" })))
+            .collect::<serde_json::Map<_, _>>();
+        let neo_parent = serde_json::json!({
+            "Actions": neo_actions,
+            "DeviceModel": DeviceModel::Neo.id(),
+            "Name": "Neo",
+        });
+        fs::write(
+            parent_dir.join("manifest.json"),
+            serde_json::to_vec(&neo_parent)?,
+        )?;
+        let mut profiles = profiles_with_pages(1);
+        profiles.parent_profile_uuid = Some(parent_uuid);
+        write_profiles(profiles, dir.path().to_owned(), &args(&["--force"]))?;
+        let parent = serde_json::from_slice::<Value>(&fs::read(parent_dir.join("manifest.json"))?)?;
+        assert_eq!(
+            parent["Actions"]["0,1"]["Settings"]["ProfileUUID"],
+            root_uuid.to_string().to_uppercase()
+        );
+        assert!(parent["Actions"].get("4,0").is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn index_pages_are_siblings() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut profiles = profiles_with_pages(4);
+        profiles.nav_style = NavStyle::Index;
+        let uuids = profiles
+            .manifests
+            .iter()
+            .map(|(uuid, _)| *uuid)
+            .collect::<Vec<_>>();
+
+        write_profiles(profiles, dir.path().to_owned(), &args(&[]))?;
+
+        let root_dir = dir.path().join(sd_profile_dir(uuids[0]));
+        assert!(root_dir.join("manifest.json").is_file());
+        for uuid in &uuids[1..] {
+            let page_dir = root_dir.join("Profiles").join(sd_profile_dir(*uuid));
+            assert!(page_dir.join("manifest.json").is_file());
+            assert!(!page_dir.join("Profiles").exists());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn detects_missing_images() -> Result<()> {
+        let emote = |name: &str, image: Option<Bytes>| {
+            Emote {
+                url: format!("http://example.com/{}.png", name),
+                name: name.into(),
+                ..Default::default()
+            }
+            .to_action(&ActionOptions::default(), image)
+        };
+
+        let profiles = || {
+            let mut actions = HashMap::new();
+            actions.insert(Position::new(1, 0), emote("present", Some("image".into())));
+            actions.insert(Position::new(2, 0), emote("missing", None));
+
+            let mut profiles = profiles();
+            profiles.manifests[0].1.actions = actions;
+            profiles
+        };
+        let root_uuid = profiles().manifests[0].0;
+
+        let dir = tempfile::tempdir()?;
+        let written = write_profiles(profiles(), dir.path().to_owned(), &args(&[]))?;
+
+        let expected = dir
+            .path()
+            .join(sd_profile_dir(root_uuid))
+            .join("2,0/CustomImages/state0.png");
+        assert_eq!(missing_images(&written, &[])?, vec![expected.clone()]);
+
+        // A key added in the Stream Deck application is kept, but its images aren't checked
+        let manifest_path = dir
+            .path()
+            .join(sd_profile_dir(root_uuid))
+            .join("manifest.json");
+        let mut manifest: Value = serde_json::from_slice(&fs::read(&manifest_path)?)?;
+        manifest["Actions"]["3,0"] = serde_json::json!({
+            "Name": "Hotkey",
+            "UUID": "com.elgato.streamdeck.system.hotkey",
+            "States": [{ "Image": "state0.png" }],
+        });
+        fs::write(&manifest_path, serde_json::to_vec(&manifest)?)?;
+
+        let written = write_profiles(profiles(), dir.path().to_owned(), &args(&["--force"]))?;
+        let manifest: Value = serde_json::from_slice(&fs::read(&manifest_path)?)?;
+        assert_eq!(manifest["Actions"]["3,0"]["Name"], "Hotkey");
+        assert_eq!(missing_images(&written, &[])?, vec![expected]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn toggle_dim_states() -> Result<()> {
+        let emote = Emote {
+            url: "http://example.com/image.png".into(),
+            name: "small9cm".into(),
+            ..Default::default()
+        };
+
+        let action = emote
+            .to_action(
+                &ActionOptions {
+                    prefix: "pomu".into(),
+                    name_case: NameCase::CapitalizeFirst,
+                    include_label: true,
+                    ..Default::default()
+                },
+                Some("image".into()),
+            )
+            .with_dimmed_state("dimmed".into());
+
+        let mut actions = HashMap::new();
+        actions.insert(Position::new(1, 0), action);
+
+        let mut profiles = profiles();
+        profiles.manifests[0].1.actions = actions;
+        let root_uuid = profiles.manifests[0].0;
+
+        let dir = tempfile::tempdir()?;
+        write_profiles(profiles, dir.path().to_owned(), &args(&[]))?;
+
+        let profile_dir = dir.path().join(sd_profile_dir(root_uuid));
+        let images_dir = profile_dir.join("1,0").join("CustomImages");
+        assert_eq!(fs::read(images_dir.join("state0.png"))?, b"image");
+        assert_eq!(fs::read(images_dir.join("state1.png"))?, b"dimmed");
+
+        let manifest =
+            serde_json::from_slice::<Value>(&fs::read(profile_dir.join("manifest.json"))?)?;
+        let action = &manifest["Actions"]["1,0"];
+        let states = action["States"].as_array().unwrap();
+        assert_eq!(states.len(), 2);
+        assert_eq!(states[1]["Title"], "small9cm");
+
+        // The application starts at `State` and moves to the next state on every press, so the
+        // key stays dimmed until it's pressed again, and pastes the same text either way
+        let image_after = |presses: usize| -> Result<Vec<u8>> {
+            let initial = action["State"].as_u64().unwrap() as usize;
+            let state = &states[(initial + presses) % states.len()];
+            Ok(fs::read(images_dir.join(state["Image"].as_str().unwrap()))?)
+        };
+        assert_eq!(image_after(0)?, b"image");
+        assert_eq!(image_after(1)?, b"dimmed");
+        assert_eq!(image_after(2)?, b"image");
+        assert_eq!(action["Settings"]["pastedText"], ":_pomuSmall9cm:");
+
+        Ok(())
+    }
+
+    #[test]
+    fn flat_layout() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let args = args(&["--layout", "flat"]);
+        write_profiles(profiles_with_pages(2), dir.path().to_owned(), &args)?;
+
+        let mut entries = fs::read_dir(dir.path())?
+            .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+            .collect::<Result<Vec<_>>>()?;
+        entries.sort();
+
+        assert_eq!(entries, vec!["page1", "page2"]);
+        assert!(dir.path().join("page1").join("manifest.json").is_file());
+        assert!(dir.path().join("page2").join("manifest.json").is_file());
+
+        Ok(())
+    }
+
+    fn emotes(names: &[&str]) -> Vec<Emote> {
+        names
+            .iter()
+            .map(|name| Emote {
+                url: format!("http://example.com/{}.png", name),
+                name: (*name).to_owned(),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn names(emotes: &[Emote]) -> Vec<&str> {
+        emotes.iter().map(|emote| emote.name.as_str()).collect()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn read_only_output_directory() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir()?;
+        check_writable(dir.path())?;
+        check_writable(&dir.path().join("ProfilesV2").join("nested"))?;
+        assert!(!dir.path().join("ProfilesV2").exists());
+
+        let file = dir.path().join("file");
+        fs::write(&file, "")?;
+        let error = check_writable(&file.join("ProfilesV2")).unwrap_err();
+        assert!(
+            matches!(&error, error::Error::OutputPathUnavailable { path, .. } if path.ends_with("ProfilesV2")),
+            "{:?}",
+            error
+        );
+        assert!(error.to_string().contains("is not writable"), "{}", error);
+
+        fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o555))?;
+        let result = check_writable(dir.path());
+        let is_root = fs::write(dir.path().join("probe"), "").is_ok();
+        fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o755))?;
+
+        // Permissions aren't enforced for root, so there's nothing to check
+        if !is_root {
+            let error = format!("{:#}", result.unwrap_err());
+            assert!(error.contains("is not writable"), "{}", error);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn existing_device() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("manifest.json");
+
+        fs::write(
+            &path,
+            r#"{"Actions":{},"DeviceModel":"20GAT9901","DeviceUUID":"@(1)[4057/108/CL1234]"}"#,
+        )?;
+        let (model, device_uuid) = read_existing_device(&path)?;
+        assert_eq!(model, DeviceModel::XL);
+        assert_eq!(device_uuid, "@(1)[4057/108/CL1234]");
+
+        fs::write(&path, r#"{"Actions":{},"DeviceModel":"20XXX9901"}"#)?;
+        let error = read_existing_device(&path).unwrap_err().to_string();
+        assert!(error.contains("Unrecognized device model"), "{}", error);
+
+        Ok(())
+    }
+
+    #[test]
+    fn multiple_models() -> Result<()> {
+        let args = args(&[
+            "--model",
+            "xl",
+            "--device-uuid",
+            "",
+            "--device-uuid",
+            "XL-UUID",
+        ]);
+        assert_eq!(args.model, vec![DeviceModel::Standard, DeviceModel::XL]);
+
+        let devices = resolve_devices(&[], &args.model, &args.device_uuid, None)?;
+        assert_eq!(
+            devices,
+            vec![
+                (DeviceModel::Standard, "".to_owned()),
+                (DeviceModel::XL, "XL-UUID".to_owned())
+            ]
+        );
+
+        let generated = devices
+            .into_iter()
+            .map(|(model, device_uuid)| {
+                let options = profile_options(&args, "Emotes", model, device_uuid, true);
+                ProfilesWithImages::from_images(emote_images(40), &options)
+            })
+            .collect::<Vec<_>>();
+
+        let (standard, xl) = (&generated[0].manifests, &generated[1].manifests);
+        assert_ne!(standard[0].0, xl[0].0);
+        assert_ne!(standard[0].0, profile::uuid_v5("Emotes", 0));
+        assert!(standard
+            .iter()
+            .all(|(uuid, _)| xl.iter().all(|(other, _)| uuid != other)));
+
+        // 13 emotes per page on a 5x3 grid, 30 on an 8x4 grid
+        assert_eq!(standard.len(), 4);
+        assert_eq!(xl.len(), 2);
+        assert_eq!(xl[0].1.device_uuid, "XL-UUID");
+        for (manifests, (width, height)) in [(standard, (5, 3)), (xl, (8, 4))] {
+            for (_, manifest) in manifests {
+                assert!(manifest
+                    .actions
+                    .keys()
+                    .all(|pos| pos.x < width && pos.y < height));
+            }
+        }
+
+        let error = resolve_devices(&[], &[], &[], None)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("--model"), "{}", error);
+
+        Ok(())
+    }
+
+    #[test]
+    fn label_max_chars_is_positive() {
+        let parse = |value: &str| {
+            let base = ["streamdeck-youtube-emotes", "--html-file", "-"];
+            Args::from_iter_safe(base.iter().chain(&["--label-max-chars", value]))
+        };
+        assert!(parse("0").is_err());
+        assert!(parse("-1").is_err());
+        let args = parse("1").unwrap();
+        assert_eq!(action_options(&args).label_max_chars, Some(1));
+    }
+
+    #[test]
+    fn parse_device_specs() -> Result<()> {
+        let spec: DeviceSpec = "model=xl,uuid=@(1)[4057/108/CL1234]".parse()?;
+        assert_eq!(
+            spec,
+            DeviceSpec {
+                model: DeviceModel::XL,
+                uuid: Some("@(1)[4057/108/CL1234]".into()),
+            }
+        );
+
+        let spec: DeviceSpec = "uuid = MINI-UUID, model = Mini".parse()?;
+        assert_eq!(spec.model, DeviceModel::Mini);
+        assert_eq!(spec.uuid.as_deref(), Some("MINI-UUID"));
+        assert_eq!("model=neo".parse::<DeviceSpec>()?.uuid, None);
+
+        for invalid in [
+            "uuid=ABC",
+            "model=plus",
+            "model=xl,serial=1",
+            "model=xl,model=mini",
+            "xl",
+        ] {
+            assert!(invalid.parse::<DeviceSpec>().is_err(), "{}", invalid);
+        }
+
+        let args = Args::from_iter(&[
+            "streamdeck-youtube-emotes",
+            "--html-file",
+            "-",
+            "--device",
+            "model=standard,uuid=STANDARD-UUID",
+            "--device",
+            "model=xl",
+        ]);
+        let devices = resolve_devices(&args.device, &args.model, &args.device_uuid, None)?;
+        assert_eq!(
+            devices,
+            vec![
+                (DeviceModel::Standard, "STANDARD-UUID".to_owned()),
+                (DeviceModel::XL, "".to_owned())
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn restart_confirmation() -> Result<()> {
+        let yes = args(&["--restart", "--yes"]);
+        assert!(confirm_restart(&yes, |_| panic!("prompted despite --yes"))?);
+
+        let ask = args(&["--restart"]);
+        let mut asked = None;
+        let confirmed = confirm_restart(&ask, |question| {
+            asked = Some(question.to_owned());
+            Ok(false)
+        })?;
+        assert!(!confirmed);
+        assert!(asked
+            .unwrap()
+            .contains("Restart the Stream Deck application"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn app_running_commands() {
+        let command = app_running_command("macos").unwrap();
+        assert_eq!(command.get_program(), "pgrep");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            vec!["-x", "Stream Deck"]
+        );
+
+        let command = app_running_command("windows").unwrap();
+        assert_eq!(command.get_program(), "tasklist");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            vec!["/FI", "IMAGENAME eq StreamDeck.exe", "/NH"]
+        );
+
+        // There's no Stream Deck application for Linux
+        assert!(app_running_command("linux").is_none());
+    }
+
+    #[test]
+    fn file_manager_commands() {
+        let path = Path::new("/profiles/ABC.sdProfile");
+        for (os, program) in [
+            ("macos", "open"),
+            ("windows", "explorer"),
+            ("linux", "xdg-open"),
+            ("freebsd", "xdg-open"),
+        ] {
+            let command = file_manager_command(os, path);
+            assert_eq!(command.get_program(), program);
+            assert_eq!(
+                command.get_args().collect::<Vec<_>>(),
+                vec![path.as_os_str()]
+            );
+        }
+    }
+
+    #[test]
+    fn profiles_dir_from_env() -> Result<()> {
+        let dir = profiles_dir(Some("/tmp/sandbox/ProfilesV2".into()), ProfilesVersion::V1)?;
+        assert_eq!(dir, Path::new("/tmp/sandbox/ProfilesV2"));
+
+        // An empty value is treated as unset
+        let default = profiles_dir(None, ProfilesVersion::V2);
+        assert_eq!(
+            profiles_dir(Some("".into()), ProfilesVersion::V2).ok(),
+            default.ok()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn profiles_versions() {
+        let home = Path::new("/home/pomu");
+        assert_eq!(
+            app_profiles_dir("macos", home, ProfilesVersion::V1).unwrap(),
+            home.join("Library/Application Support/com.elgato.StreamDeck/ProfilesV1")
+        );
+        assert_eq!(
+            app_profiles_dir("windows", home, ProfilesVersion::V1).unwrap(),
+            home.join("AppData/Roaming/Elgato/StreamDeck/ProfilesV1")
+        );
+        assert_eq!(
+            app_profiles_dir("macos", home, ProfilesVersion::V2).unwrap(),
+            home.join("Library/Application Support/com.elgato.StreamDeck/ProfilesV2")
+        );
+        assert!(app_profiles_dir("linux", home, ProfilesVersion::V1).is_none());
+
+        let args = args(&["--profiles-version", "v1"]);
+        assert_eq!(args.profiles_version, ProfilesVersion::V1);
+    }
+
+    #[test]
+    fn print_codes() {
+        let mut emotes = emotes(&["Small9cm", "hic"]);
+        emotes[1].pasted_text = Some(":custom:".into());
+
+        let args = args(&["--prefix", "pomu", "--print-codes"]);
+        let codes = emote_codes(&args, &emotes);
+        assert_eq!(codes, vec![":_pomuSmall9cm:", ":custom:"]);
+
+        // Same text as the generated keys
+        let options = profile_options(&args, "Emotes", DeviceModel::Standard, "".into(), false);
+        let profiles = ProfilesWithImages::from_images(placeholder_images(emotes), &options);
+        let mut pasted = profiles.manifests[0]
+            .1
+            .actions
+            .values()
+            .filter_map(|action| match &action.settings {
+                profile::Settings::Text { pasted_text, .. } => Some(pasted_text.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        pasted.sort();
+        assert_eq!(pasted, vec![":_pomuSmall9cm:", ":custom:"]);
+    }
+
+    #[test]
+    fn plan_description() {
+        let options = profile_options(
+            &args(&[]),
+            "Emotes",
+            DeviceModel::Standard,
+            "".into(),
+            false,
+        );
+        let plan = profile::plan_layout(30, &options);
+        assert_eq!(
+            describe_plan(&plan, 30, &DeviceModel::Standard),
+            "standard: 3 pages for 30 emotes, up to 12 per page (6 on the last page), \
+            3 keys reserved for navigation"
+        );
+    }
+
+    #[test]
+    fn model_suggestion() {
+        let base = [
+            "streamdeck-youtube-emotes",
+            "--html-file",
+            "-",
+            "--name",
+            "Emotes",
+        ];
+        let describe = |args: &Args| {
+            model_suggestions(args, "Emotes", 30)
+                .iter()
+                .map(describe_suggestion)
+                .collect::<Vec<_>>()
+        };
+        let args = Args::from_iter(base.iter().chain(&["--suggest-model"]));
+        assert_eq!(
+            describe(&args),
+            vec![
+                "xl: 32 keys, 2 pages (fits on one screen with --compact)",
+                "standard: 15 keys, 3 pages",
+                "neo: 8 keys, 5 pages",
+                "mini: 6 keys, 8 pages",
+            ]
+        );
+
+        let args = Args::from_iter(base.iter().chain(&["--suggest-model", "--compact"]));
+        assert_eq!(
+            describe(&args)[0],
+            "xl: 32 keys, 1 page (fits on one screen with --compact)"
+        );
+    }
+
+    #[test]
+    fn summary_line() {
+        assert_eq!(
+            summary("Emotes", 1, 1, "/profiles/ABC.sdProfile"),
+            "✓ Wrote profile 'Emotes' (1 page, 1 emote) to /profiles/ABC.sdProfile"
+        );
+        assert_eq!(
+            summary("Emotes", 3, 42, "stdout"),
+            "✓ Wrote profile 'Emotes' (3 pages, 42 emotes) to stdout"
+        );
+        assert_eq!(plural(0, "emote"), "0 emotes");
+    }
+
+    #[test]
+    fn tier_filter() {
+        let mut emotes = emotes(&["a", "b", "c", "d"]);
+        for (emote, tier) in emotes
+            .iter_mut()
+            .zip(&["Member", "Member", "Super", "Super"])
+        {
+            emote.tier = Some(tier.to_string());
+        }
+
+        let tiers = vec!["super".to_owned(), "Gold".to_owned()];
+        let (unknown, available) = filter_tiers(&mut emotes, &tiers);
+
+        assert_eq!(names(&emotes), vec!["c", "d"]);
+        assert_eq!(unknown, vec!["Gold"]);
+        assert_eq!(available, vec!["Member", "Super"]);
+    }
+
+    #[test]
+    fn min_emotes_threshold() {
+        assert!(check_min_emotes(2, 3).is_err());
+        assert!(check_min_emotes(3, 3).is_ok());
+        assert!(check_min_emotes(1, args(&[]).min_emotes).is_ok());
+
+        // Emotes filtered out by `--tier` don't count
+        let emotes = ["a", "b", "c"]
+            .iter()
+            .zip(&["Member", "Super", "Super"])
+            .map(|(name, tier)| Emote {
+                name: name.to_string(),
+                tier: Some(tier.to_string()),
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        let select = |extra: &[&str]| select_emotes(&args(extra), emotes.clone());
+        assert!(select(&["--min-emotes", "3"]).is_ok());
+        assert!(select(&["--min-emotes", "3", "--tier", "Member"]).is_err());
+        assert!(select(&["--min-emotes", "2", "--tier", "Super"]).is_ok());
+        assert!(select(&["--min-emotes", "2", "--limit", "1"]).is_err());
+    }
+
+    #[test]
+    fn prioritize_from_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("favorites.txt");
+        fs::write(&path, "# Favorites\n\n  e \nB\r\n# b is also good\n\nc\n")?;
+
+        let prioritize = with_names_from_file(&["c".to_owned()], Some(&path))?;
+        assert_eq!(prioritize, vec!["c", "e", "B", "c"]);
+
+        let mut emotes = emotes(&["a", "b", "c", "d", "e"]);
+        sort_emotes(&mut emotes, &prioritize, &[]);
+        assert_eq!(names(&emotes), vec!["c", "e", "b", "a", "d"]);
+
+        let error = with_names_from_file(&[], Some(&dir.path().join("missing.txt"))).unwrap_err();
+        assert!(error.to_string().contains("missing.txt"), "{}", error);
+
+        Ok(())
+    }
+
+    #[test]
+    fn limit_keeps_prioritized_emotes() {
+        let mut emotes = emotes(&["a", "b", "c", "d", "e"]);
+        let prioritize = vec!["D".to_owned(), "c".to_owned()];
+        let deprioritize = vec!["a".to_owned()];
+
+        sort_emotes(&mut emotes, &prioritize, &deprioritize);
+        assert_eq!(names(&emotes), vec!["d", "c", "b", "e", "a"]);
+
+        limit_emotes(&mut emotes, 3);
+        assert_eq!(names(&emotes), vec!["d", "c", "b"]);
+
+        limit_emotes(&mut emotes, 10);
+        assert_eq!(emotes.len(), 3);
+    }
+
+    #[test]
+    fn completions_for_each_shell() -> Result<()> {
+        for shell in &["bash", "zsh", "fish", "powershell"] {
+            let mut out = Vec::new();
+            write_completions(
+                shell.parse().map_err(color_eyre::eyre::Report::msg)?,
+                &mut out,
+            )?;
+            assert!(!out.is_empty(), "no completions for {}", shell);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn strict_fails_on_invalid_existing_manifest() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let uuid = profile::uuid_v5("Emotes", 0).to_string().to_uppercase();
+        let profile_dir = dir.path().join(format!("{}.sdProfile", uuid));
+        fs::create_dir_all(&profile_dir)?;
+        fs::write(profile_dir.join("manifest.json"), "not json")?;
+
+        write_profiles(profiles(), dir.path().to_owned(), &args(&["--force"]))?;
+
+        fs::write(profile_dir.join("manifest.json"), "not json")?;
+        let args = args(&["--force", "--strict"]);
+        let result = write_profiles(profiles(), dir.path().to_owned(), &args);
+        assert!(result.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn merge_keeps_unknown_fields() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("manifest.json");
+        fs::write(
+            &path,
+            r#"{"Actions":{"1,1":{"Name":"Hotkey"}},"Name":"Old","Version":"2.0","Icon":"x.png"}"#,
+        )?;
+
+        let mut manifest = serde_json::to_value(&profiles().manifests[0].1)?;
+        merge_manifests_if_exists(&mut manifest, &path, false, false, &[])?;
+
+        assert_eq!(manifest["Icon"], "x.png");
+        assert_eq!(manifest["Actions"]["1,1"]["Name"], "Hotkey");
+        assert_eq!(manifest["Name"], "Emotes");
+        assert_eq!(manifest["Version"], profile::MANIFEST_VERSION);
+
+        Ok(())
+    }
+
+    #[test]
+    fn merge_website_keys() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("manifest.json");
+        let website = |url: &str| {
+            serde_json::json!({
+                "UUID": profile::WEBSITE_ACTION_UUID,
+                "Settings": { "openInBrowser": true, "path": url }
+            })
+        };
+        let existing = serde_json::json!({ "Actions": {
+            "1,0": website("https://example.com/mine"),
+            "2,0": website("https://example.com/extra"),
+            "3,0": website("https://example.com/old"),
+            "4,1": website("https://example.com/elsewhere"),
+        } });
+        fs::write(&path, serde_json::to_vec(&existing)?)?;
+        let extra_actions = vec![
+            "emote0=https://example.com/extra".parse::<ExtraAction>()?,
+            "3,0=https://example.com/new".parse()?,
+        ];
+
+        // Only the keys from `--extra-action` are removed by `--replace`
+        let mut manifest = serde_json::json!({ "Actions": {} });
+        merge_manifests_if_exists(&mut manifest, &path, false, true, &extra_actions)?;
+        let mut positions = manifest["Actions"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        positions.sort();
+        assert_eq!(positions, vec!["1,0", "4,1"]);
+
+        // Website keys that the user added are kept in place of generated keys
+        let mut manifest = serde_json::json!({ "Actions": {
+            "1,0": { "UUID": "com.elgato.streamdeck.system.text" },
+            "2,0": { "UUID": "com.elgato.streamdeck.system.text" },
+        } });
+        let preserved =
+            merge_manifests_if_exists(&mut manifest, &path, true, false, &extra_actions)?;
+        assert_eq!(preserved, vec!["1,0"]);
+        assert_eq!(
+            manifest["Actions"]["1,0"],
+            website("https://example.com/mine")
+        );
+        assert_eq!(
+            manifest["Actions"]["2,0"]["UUID"],
+            "com.elgato.streamdeck.system.text"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn name_from_html() -> Result<()> {
+        let json = serde_json::json!({
+            "metadata": { "channelMetadataRenderer": { "title": "Pomu Rainpuff" } }
+        });
+        let parse = |extra: &[&str]| {
+            let base = ["streamdeck-youtube-emotes", "--html-file", "-"];
+            Args::from_iter(base.iter().chain(extra))
+        };
+
+        assert_eq!(profile_name(&parse(&[]), &json)?, "Pomu Rainpuff");
+        assert_eq!(
+            profile_name(&parse(&["--name", "Emotes"]), &json)?,
+            "Emotes"
+        );
+        let forced = parse(&["--name", "Emotes", "--name-from-html"]);
+        assert_eq!(profile_name(&forced, &json)?, "Pomu Rainpuff");
+
+        let json = serde_json::json!({});
+        let error = profile_name(&forced, &json).unwrap_err().to_string();
+        assert!(error.contains("--name-from-html"), "{}", error);
+
+        Ok(())
+    }
+
+    #[test]
+    fn dump_ytinitialdata() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("ytInitialData.json");
+        let html = r#"<script>var ytInitialData = {"contents":{"twoColumnBrowseResultsRenderer":{"tabs":[]}}};</script>"#;
+
+        // The JSON is written even though there are no emotes in it
+        let args = args(&["--dump-ytinitialdata", path.to_str().unwrap()]);
+        assert!(read_memberships_page(&args, html).is_err());
+
+        let json: Value = serde_json::from_str(&fs::read_to_string(&path)?)?;
+        assert!(json
+            .pointer("/contents/twoColumnBrowseResultsRenderer/tabs")
+            .is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn preserve_custom_keys() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let profile_dir = dir
+            .path()
+            .join(sd_profile_dir(profile::uuid_v5("Emotes", 0)));
+        let profiles = || generated_profiles(2);
+        write_profiles(profiles(), dir.path().to_owned(), &args(&[]))?;
+
+        // Replace the first emote with a hotkey, as if edited in the Stream Deck application
+        let manifest_path = profile_dir.join("manifest.json");
+        let mut manifest: Value = serde_json::from_slice(&fs::read(&manifest_path)?)?;
+        manifest["Actions"]["1,0"] = serde_json::json!({
+            "Name": "Hotkey",
+            "UUID": "com.elgato.streamdeck.system.hotkey",
+        });
+        fs::write(&manifest_path, serde_json::to_vec(&manifest)?)?;
+        let image_path = profile_dir.join("1,0/CustomImages/state0.png");
+        fs::write(&image_path, "custom")?;
+
+        let preserve = args(&["--force", "--preserve-custom-keys"]);
+        write_profiles(profiles(), dir.path().to_owned(), &preserve)?;
+
+        let manifest: Value = serde_json::from_slice(&fs::read(&manifest_path)?)?;
+        assert_eq!(manifest["Actions"]["1,0"]["Name"], "Hotkey");
+        assert_eq!(fs::read(&image_path)?, b"custom");
+        assert_eq!(
+            manifest["Actions"]["2,0"]["UUID"],
+            "com.elgato.streamdeck.system.text"
+        );
+
+        // Without the flag, the generated key takes its place again
+        write_profiles(profiles(), dir.path().to_owned(), &args(&["--force"]))?;
+        let manifest: Value = serde_json::from_slice(&fs::read(&manifest_path)?)?;
+        assert_eq!(
+            manifest["Actions"]["1,0"]["UUID"],
+            "com.elgato.streamdeck.system.text"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn offline_uses_cached_images() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cache = fetch::ImageCache::new(dir.path())?;
+        cache.put("http://example.com/cached.png", &fixture_png(72, 36))?;
+
+        let emotes = cached_emotes(emotes(&["cached", "missing"]), &cache)?;
+        assert_eq!(names(&emotes), vec!["cached"]);
+
+        let fetcher = fetch::CachedFetcher::<StubFetcher> {
+            inner: None,
+            cache: Some(cache),
+        };
+        let options = profile_options(
+            &args(&[]),
+            "Emotes",
+            DeviceModel::Standard,
+            "".into(),
+            false,
+        );
+        let profiles = ProfilesWithImages::new(&fetcher, emotes, &options).await?;
+        let action = &profiles.manifests[0].1.actions[&profile::Position::new(1, 0)];
+        let image = action.image.as_deref().unwrap();
+        assert_eq!(processing::image_dimensions(image), Some((72, 36)));
+
+        let empty = fetch::ImageCache::new(&dir.path().join("empty"))?;
+        assert!(cached_emotes(vec![], &empty).is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn manifest_only_reuses_images() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let profile_dir = dir
+            .path()
+            .join(sd_profile_dir(profile::uuid_v5("Emotes", 0)));
+        let emotes = (0..2)
+            .map(|i| Emote {
+                url: format!("http://example.com/{}.png", i),
+                name: format!("emote{}", i),
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        let options =
+            |args: &Args| profile_options(args, "Emotes", DeviceModel::Standard, "".into(), false);
+
+        // Each image has a different height, to tell them apart once they're resized
+        let fetcher = StubFetcher::new(vec![
+            ("http://example.com/0.png", fixture_png(72, 36)),
+            ("http://example.com/1.png", fixture_png(72, 24)),
+        ]);
+        let profiles =
+            ProfilesWithImages::new(&fetcher, emotes.clone(), &options(&args(&[]))).await?;
+        let written = write_profiles(profiles, dir.path().to_owned(), &args(&[]))?;
+
+        let past = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+        for path in &written {
+            if path.ends_with("state0.png") {
+                fs::File::options()
+                    .write(true)
+                    .open(path)?
+                    .set_modified(past)?;
+            }
+        }
+
+        // Regenerating with the same emotes doesn't download or touch any images
+        let manifest_only = args(&["--manifest-only"]);
+        let manifest_only_options = options(&manifest_only);
+        let fetcher = StubFetcher::default();
+        let regenerate = |emotes: Vec<Emote>| {
+            load_profiles(
+                &manifest_only,
+                &fetcher,
+                emotes,
+                &manifest_only_options,
+                dir.path(),
+            )
+        };
+        let profiles = regenerate(emotes.clone()).await?;
+        write_profiles(profiles, dir.path().to_owned(), &manifest_only)?;
+        assert!(fetcher.requested().is_empty());
+        for path in written.iter().filter(|path| path.ends_with("state0.png")) {
+            assert_eq!(fs::metadata(path)?.modified()?, past);
+        }
+
+        // Images follow their emotes to the new positions
+        let reversed = emotes.iter().rev().cloned().collect();
+        let profiles = regenerate(reversed).await?;
+        write_profiles(profiles, dir.path().to_owned(), &manifest_only)?;
+        assert!(fetcher.requested().is_empty());
+        let manifest: Value =
+            serde_json::from_slice(&fs::read(profile_dir.join("manifest.json"))?)?;
+        assert_eq!(
+            manifest["Actions"]["1,0"]["Settings"]["pastedText"],
+            ":_emote1:"
+        );
+        let dimensions = |position: &str| -> Result<_> {
+            let path = profile_dir.join(position).join("CustomImages/state0.png");
+            Ok(processing::image_dimensions(&fs::read(path)?))
+        };
+        assert_eq!(dimensions("1,0")?, Some((72, 24)));
+        assert_eq!(dimensions("2,0")?, Some((72, 36)));
+
+        // Emotes that weren't in the old profile have no image to reuse
+        let mut new_emotes = emotes;
+        new_emotes.push(Emote {
+            url: "http://example.com/2.png".into(),
+            name: "emote2".into(),
+            ..Default::default()
+        });
+        let error = match regenerate(new_emotes).await {
+            Ok(_) => panic!("expected missing images to fail"),
+            Err(e) => e,
+        };
+        assert!(error.to_string().contains("emote2"), "{}", error);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn retry_failed_downloads_only_the_failed_emotes() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let profile_dir = dir
+            .path()
+            .join(sd_profile_dir(profile::uuid_v5("Emotes", 0)));
+        let report_path = dir.path().join("failures.json");
+        let report_arg = report_path.to_string_lossy();
+        let emotes = (0..4)
+            .map(|i| Emote {
+                url: format!("http://example.com/{}.png", i),
+                name: format!("emote{}", i),
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        let options =
+            |args: &Args| profile_options(args, "Emotes", DeviceModel::Standard, "".into(), false);
+        // Each image has a different height, to tell them apart once they're resized
+        let images = [36, 24, 18, 12]
+            .iter()
+            .enumerate()
+            .map(|(i, &height)| {
+                (
+                    format!("http://example.com/{}.png", i),
+                    fixture_png(72, height),
+                )
+            })
+            .collect::<Vec<_>>();
+        let fetcher = |indices: &[usize]| {
+            StubFetcher::new(
+                indices
+                    .iter()
+                    .map(|&i| (images[i].0.as_str(), images[i].1.clone())),
+            )
+        };
+
+        // The first run skips the emotes that fail, instead of failing
+        let first = args(&["--failure-report", &report_arg]);
+        let profiles =
+            ProfilesWithImages::new(&fetcher(&[0, 3]), emotes.clone(), &options(&first)).await?;
+        let failed = profiles
+            .failed_downloads
+            .iter()
+            .map(|failure| failure.emote.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(failed, vec!["emote1", "emote2"]);
+        write_profiles(profiles, dir.path().to_owned(), &first)?;
+
+        let report = report::FailureReport::new(
+            emotes[1..3]
+                .iter()
+                .map(|emote| report::FailedEmote {
+                    name: emote.name.clone(),
+                    url: emote.url.clone(),
+                    error: "Failed to download".into(),
+                })
+                .collect(),
+        );
+        report.write(&report_path)?;
+
+        // Only the emotes in the report are downloaded again
+        let retry = args(&["--retry-failed", &report_arg]);
+        let fetcher = fetcher(&[0, 1, 2, 3]);
+        let profiles =
+            load_profiles(&retry, &fetcher, emotes, &options(&retry), dir.path()).await?;
+        let mut requested = fetcher.requested();
+        requested.sort();
+        assert_eq!(
+            requested,
+            vec!["http://example.com/1.png", "http://example.com/2.png"]
+        );
+        write_profiles(profiles, dir.path().to_owned(), &retry)?;
+
+        // They're placed in their order on the page, along with the emotes that were kept
+        let manifest: Value =
+            serde_json::from_slice(&fs::read(profile_dir.join("manifest.json"))?)?;
+        let dimensions = |position: &str| -> Result<_> {
+            let path = profile_dir.join(position).join("CustomImages/state0.png");
+            Ok(processing::image_dimensions(&fs::read(path)?))
+        };
+        for (i, height) in [36, 24, 18, 12].iter().enumerate() {
+            let position = format!("{},0", i + 1);
+            assert_eq!(
+                manifest["Actions"][&position]["Settings"]["pastedText"],
+                format!(":_emote{}:", i)
+            );
+            assert_eq!(dimensions(&position)?, Some((72, *height)));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_mode() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let profiles = || generated_profiles(20);
+        let existing = dir.path().join("existing");
+        write_profiles(profiles(), existing.clone(), &args(&[]))?;
+
+        // Regenerating the same profile into a copy of it changes nothing
+        let regenerate = || -> Result<tempfile::TempDir> {
+            let generated = tempfile::tempdir()?;
+            let copy_options = CopyOptions {
+                content_only: true,
+                ..Default::default()
+            };
+            fs_extra::dir::copy(&existing, generated.path(), &copy_options)?;
+            write_profiles(profiles(), generated.path().to_owned(), &args(&["--check"]))?;
+            Ok(generated)
+        };
+        let generated = regenerate()?;
+        assert_eq!(
+            diff_dirs(&existing, generated.path())?,
+            Vec::<String>::new()
+        );
+        check_up_to_date(&existing, generated.path())?;
+
+        // Modified and removed files are reported
+        let manifest = find_manifests(&existing)?.remove(0);
+        fs::write(&manifest, "{}")?;
+        let image = list_files(&existing)?
+            .into_iter()
+            .find(|file| file.ends_with("state0.png"))
+            .unwrap();
+        fs::remove_file(existing.join(&image))?;
+
+        let generated = regenerate()?;
+        let relative = |path: &Path| path.to_string_lossy().replace('\\', "/");
+        let changes = diff_dirs(&existing, generated.path())?;
+        assert_eq!(changes.len(), 2, "{:?}", changes);
+        assert!(changes.contains(&format!(
+            "~ {}",
+            relative(manifest.strip_prefix(&existing)?)
+        )));
+        assert!(changes.contains(&format!("+ {}", relative(&image))));
+
+        let error = check_up_to_date(&existing, generated.path()).unwrap_err();
+        assert!(
+            error.to_string().contains("2 files would change"),
+            "{}",
+            error
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn stage_then_sync_matches_direct_write() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+
+        // Both start with an older version of the profile, and an unrelated one
+        let direct = dir.path().join("direct");
+        let live = dir.path().join("live");
+        for root in [&direct, &live].iter() {
+            write_profiles(generated_profiles(40), root.to_path_buf(), &args(&[]))?;
+            fs::create_dir_all(root.join("Other.sdProfile"))?;
+            fs::write(root.join("Other.sdProfile/manifest.json"), "{}")?;
+        }
+
+        write_profiles(
+            generated_profiles(20),
+            direct.clone(),
+            &args(&["--replace"]),
+        )?;
+
+        let stage_path = dir.path().join("stage");
+        let stage = stage::StageDir::create(&live, &stage_path)?;
+        let written = write_profiles(
+            generated_profiles(20),
+            stage.path.clone(),
+            &args(&["--replace"]),
+        )?;
+        assert!(written.iter().all(|file| file.starts_with(&stage_path)));
+        stage.sync(&live, &written)?;
+
+        assert_eq!(diff_dirs(&direct, &live)?, Vec::<String>::new());
+        assert!(!stage_path.exists());
+
+        // The stage directory has to start out empty
+        fs::create_dir_all(&stage_path)?;
+        fs::write(stage_path.join("leftover"), "")?;
+        let error = stage::StageDir::create(&live, &stage_path).unwrap_err();
+        assert!(error.to_string().contains("must be empty"), "{}", error);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failed_run_removes_stage_directory() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let images = dir.path().join("images");
+        fs::create_dir(&images)?;
+        // Both become `:_pomuSmall9cm:`, which fails with `--strict` after the stage is created
+        fs::write(images.join("small9cm.png"), fixture_png(16, 16))?;
+        fs::write(images.join("Small9cm.png"), fixture_png(16, 8))?;
+
+        let stage_path = dir.path().join("stage");
+        let live = dir.path().join("live");
+        let args = |extra: &[&str]| {
+            let images = images.to_string_lossy();
+            let out = live.to_string_lossy();
+            let stage = stage_path.to_string_lossy();
+            let base = ["streamdeck-youtube-emotes", "--images-dir", &images];
+            let required = ["--name", "Emotes", "--model", "standard", "--out", &out];
+            let stage = ["--stage-dir", &stage, "--prefix", "pomu"];
+            Args::from_iter(base.iter().chain(&required).chain(&stage).chain(extra))
+        };
+
+        run(&mut args(&["--strict"])).await.unwrap_err();
+        assert!(!stage_path.exists());
+        assert!(!live
+            .join(sd_profile_dir(profile::uuid_v5("Emotes", 0)))
+            .exists());
+
+        // The next run can use the same stage directory
+        run(&mut args(&[])).await?;
+        assert!(!stage_path.exists());
+        assert!(live
+            .join(sd_profile_dir(profile::uuid_v5("Emotes", 0)))
+            .exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn pretty_json_manifests() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let profiles = || generated_profiles(20);
+
+        let compact = dir.path().join("compact");
+        let pretty = dir.path().join("pretty");
+        write_profiles(profiles(), compact.clone(), &args(&[]))?;
+        write_profiles(profiles(), pretty.clone(), &args(&["--pretty-json"]))?;
+        // Merged manifests are pretty-printed too
+        write_profiles(
+            profiles(),
+            pretty.clone(),
+            &args(&["--pretty-json", "--force"]),
+        )?;
+
+        let compact_manifests = find_manifests(&compact)?;
+        let pretty_manifests = find_manifests(&pretty)?;
+        assert_eq!(compact_manifests.len(), pretty_manifests.len());
+        for (compact_path, pretty_path) in compact_manifests.iter().zip(&pretty_manifests) {
+            let compact_string = fs::read_to_string(compact_path)?;
+            let pretty_string = fs::read_to_string(pretty_path)?;
+            assert!(!compact_string.contains('\n'));
+            assert!(pretty_string.contains('\n'));
+            assert_eq!(
+                serde_json::from_str::<Value>(&pretty_string)?,
+                serde_json::from_str::<Value>(&compact_string)?
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn replace_removes_stale_pages() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let page_dir = |page| sd_profile_dir(profile::uuid_v5("Emotes", page));
+        let root_dir = dir.path().join(page_dir(0));
+        let third_page = root_dir
+            .join("Profiles")
+            .join(page_dir(1))
+            .join("Profiles")
+            .join(page_dir(2));
+
+        // 12 emotes per page, so 30 emotes take 3 pages
+        write_profiles(generated_profiles(30), dir.path().to_owned(), &args(&[]))?;
+        assert!(third_page.join("manifest.json").exists());
+
+        // An unrelated profile nested in the root profile is kept
+        let other = root_dir
+            .join("Profiles")
+            .join("00000000-0000-0000-0000-000000000000.sdProfile");
+        fs::create_dir_all(&other)?;
+
+        write_profiles(
+            generated_profiles(20),
+            dir.path().to_owned(),
+            &args(&["--replace"]),
+        )?;
+        assert!(!third_page.exists());
+        assert!(other.exists());
+
+        // The key that led to the removed page is gone too
+        let manifest_path = root_dir
+            .join("Profiles")
+            .join(page_dir(1))
+            .join("manifest.json");
+        let manifest: Value = serde_json::from_slice(&fs::read(manifest_path)?)?;
+        let actions = manifest["Actions"].as_object().unwrap();
+        assert!(actions
+            .values()
+            .all(|action| action["Name"] != "Create Folder"));
+
+        // Same keys as a profile written from scratch
+        let fresh = tempfile::tempdir()?;
+        write_profiles(generated_profiles(20), fresh.path().to_owned(), &args(&[]))?;
+        let fresh_manifest: Value = serde_json::from_slice(&fs::read(
+            fresh
+                .path()
+                .join(page_dir(0))
+                .join("Profiles")
+                .join(page_dir(1))
+                .join("manifest.json"),
+        )?)?;
+        assert_eq!(manifest["Actions"], fresh_manifest["Actions"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn replace_removes_stale_category_pages() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let profiles = |count, categorize| {
+            let mut images = emote_images(count);
+            for image in images.iter_mut() {
+                image.emote.category = Some("Reactions".into());
+            }
+            let mut options = profile_options(
+                &args(&[]),
+                "Emotes",
+                DeviceModel::Standard,
+                "".into(),
+                false,
+            );
+            options.categorize = categorize;
+            ProfilesWithImages::from_images(images, &options)
+        };
+        let page_dir = |seed, page| sd_profile_dir(profile::uuid_v5(seed, page));
+        let root_dir = dir.path().join(page_dir("Emotes", 0));
+        let main_page = root_dir.join("Profiles").join(page_dir("Emotes", 1));
+        let category_page = |page| {
+            (1..=page).fold(
+                root_dir
+                    .join("Profiles")
+                    .join(page_dir("Emotes/Reactions", 0)),
+                |path, page| {
+                    path.join("Profiles")
+                        .join(page_dir("Emotes/Reactions", page))
+                },
+            )
+        };
+
+        // Pages of an earlier run without categories are removed
+        write_profiles(profiles(30, false), dir.path().to_owned(), &args(&[]))?;
+        assert!(main_page.join("manifest.json").exists());
+
+        // 12 emotes per page, so 30 emotes take 3 pages
+        let replace = args(&["--replace"]);
+        write_profiles(profiles(30, true), dir.path().to_owned(), &replace)?;
+        assert!(!main_page.exists());
+        assert!(category_page(2).join("manifest.json").exists());
+
+        // The category shrinks to 2 pages
+        write_profiles(profiles(20, true), dir.path().to_owned(), &replace)?;
+        assert!(category_page(1).join("manifest.json").exists());
+        assert!(!category_page(2).exists());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn duplicate_pasted_texts_warn_or_fail_with_strict() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let images = dir.path().join("images");
+        fs::create_dir(&images)?;
+        // Both become `:_pomuSmall9cm:` once the first letter is capitalized
+        fs::write(images.join("small9cm.png"), fixture_png(16, 16))?;
+        fs::write(images.join("Small9cm.png"), fixture_png(16, 8))?;
+
+        let args = |out: &str, extra: &[&str]| {
+            let images = images.to_string_lossy();
+            let out = dir.path().join(out);
+            let out = out.to_string_lossy();
+            let base = ["streamdeck-youtube-emotes", "--images-dir", &images];
+            let required = ["--name", "Emotes", "--model", "standard", "--out", &out];
+            let prefix = ["--prefix", "pomu"];
+            Args::from_iter(base.iter().chain(&required).chain(&prefix).chain(extra))
+        };
+
+        let (logs, _guard) = capture_logs();
+        run(&mut args("warn", &[])).await?;
+        assert!(
+            logs.contents()
+                .contains("Multiple emotes have the same pasted text"),
+            "{}",
+            logs.contents()
+        );
+
+        let error = run(&mut args("strict", &["--strict"])).await.unwrap_err();
+        assert!(error.to_string().contains(":_pomuSmall9cm:"), "{}", error);
+
+        Ok(())
+    }
+
+    #[test]
+    fn existing_profile_requires_force() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let uuid = profile::uuid_v5("Emotes", 0);
+        let profile_dir = dir.path().join(sd_profile_dir(uuid));
+
+        // An empty directory doesn't have anything to lose
+        fs::create_dir_all(&profile_dir)?;
+        write_profiles(profiles(), dir.path().to_owned(), &args(&[]))?;
+
+        let manifest = fs::read(profile_dir.join("manifest.json"))?;
+        let error = write_profiles(profiles(), dir.path().to_owned(), &args(&[])).unwrap_err();
+        assert!(error.to_string().contains("--force"), "{}", error);
+
+        write_profiles(profiles(), dir.path().to_owned(), &args(&["--force"]))?;
+        assert_eq!(fs::read(profile_dir.join("manifest.json"))?, manifest);
+
+        // Flat layouts check each page directory
+        let args = args(&["--layout", "flat"]);
+        write_profiles(profiles_with_pages(2), dir.path().to_owned(), &args)?;
+        assert!(write_profiles(profiles_with_pages(2), dir.path().to_owned(), &args).is_err());
+
+        Ok(())
+    }
 }
@@ -0,0 +1,234 @@
+use crate::profile::{is_hex_color, Emote, TITLE_ALIGNMENTS};
+use color_eyre::eyre::{bail, Result, WrapErr};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Customizations for a single emote, as specified in the overrides file
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmoteOverride {
+    pub label: Option<String>,
+    pub pasted_text: Option<String>,
+    /// Color of the label, as `#RRGGBB`
+    pub title_color: Option<String>,
+    /// One of [`TITLE_ALIGNMENTS`]
+    pub title_alignment: Option<String>,
+    /// Whether to show a label, regardless of `--include-labels`
+    pub show_label: Option<bool>,
+}
+
+/// Map of emote name to its overrides, e.g.:
+///
+/// ```json
+/// {
+///   "small9cm": { "label": "9cm", "pasted_text": ":_pomuSmall9cm:" },
+///   "yay": { "title_color": "#ff88cc", "title_alignment": "top", "show_label": true }
+/// }
+/// ```
+///
+/// Fields that aren't set use the options given on the command line.
+pub type Overrides = HashMap<String, EmoteOverride>;
+
+pub fn read_overrides(path: &Path) -> Result<Overrides> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read overrides file {:?}", path))?;
+
+    let overrides = serde_json::from_str::<Overrides>(&contents)
+        .with_context(|| format!("Failed to parse overrides file {:?}", path))?;
+    validate_overrides(&overrides).with_context(|| format!("Invalid overrides file {:?}", path))?;
+
+    Ok(overrides)
+}
+
+/// Checks that the label styles are ones that the Stream Deck application understands
+fn validate_overrides(overrides: &Overrides) -> Result<()> {
+    for (name, emote_override) in overrides {
+        if let Some(color) = emote_override.title_color.as_deref() {
+            if !is_hex_color(color) {
+                bail!(
+                    "Invalid title_color {:?} for {:?}, expected #RRGGBB",
+                    color,
+                    name
+                );
+            }
+        }
+
+        if let Some(alignment) = emote_override.title_alignment.as_deref() {
+            if !TITLE_ALIGNMENTS.contains(&alignment) {
+                bail!(
+                    "Invalid title_alignment {:?} for {:?}, expected one of: {}",
+                    alignment,
+                    name,
+                    TITLE_ALIGNMENTS.join(", ")
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Applies overrides to the matching emotes (by case-insensitive name). Returns the names of any
+/// overrides that didn't match an emote.
+pub fn apply_overrides(emotes: &mut [Emote], overrides: &Overrides) -> Vec<String> {
+    let mut unmatched = Vec::new();
+
+    for (name, emote_override) in overrides.iter() {
+        let lower_name = name.to_lowercase();
+        let mut matched = false;
+
+        for emote in emotes
+            .iter_mut()
+            .filter(|emote| emote.name.to_lowercase() == lower_name)
+        {
+            matched = true;
+
+            if let Some(label) = &emote_override.label {
+                emote.label = Some(label.clone());
+            }
+
+            if let Some(pasted_text) = &emote_override.pasted_text {
+                emote.pasted_text = Some(pasted_text.clone());
+            }
+
+            if let Some(color) = &emote_override.title_color {
+                emote.title_color = Some(color.to_lowercase());
+            }
+
+            if let Some(alignment) = &emote_override.title_alignment {
+                emote.title_alignment = Some(alignment.clone());
+            }
+
+            if let Some(show_label) = emote_override.show_label {
+                emote.show_label = Some(show_label);
+            }
+        }
+
+        if !matched {
+            unmatched.push(name.clone());
+        }
+    }
+
+    unmatched.sort();
+    unmatched
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::{ActionOptions, NameCase, Settings, State};
+
+    #[test]
+    fn override_one_emote() -> Result<()> {
+        let mut emotes = ["small9cm", "yay"]
+            .iter()
+            .map(|name| Emote {
+                name: (*name).to_owned(),
+                url: format!("http://example.com/{}.png", name),
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+
+        let overrides = serde_json::from_str::<Overrides>(
+            r#"{
+                "Small9cm": { "label": "9cm", "pasted_text": ":_pomu9cm:" },
+                "missing": { "label": "Missing" }
+            }"#,
+        )?;
+
+        let unmatched = apply_overrides(&mut emotes, &overrides);
+        assert_eq!(unmatched, vec!["missing"]);
+
+        let actions = emotes
+            .iter()
+            .map(|emote| {
+                emote.to_action(
+                    &ActionOptions {
+                        prefix: "pomu".into(),
+                        name_case: NameCase::CapitalizeFirst,
+                        include_label: true,
+                        ..Default::default()
+                    },
+                    None,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(actions[0].states[0].title, "9cm");
+        assert!(matches!(
+            &actions[0].settings,
+            Settings::Text { pasted_text, .. } if pasted_text == ":_pomu9cm:"
+        ));
+
+        assert_eq!(actions[1].states[0].title, "yay");
+        assert!(matches!(
+            &actions[1].settings,
+            Settings::Text { pasted_text, .. } if pasted_text == ":_pomuYay:"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn override_label_style() -> Result<()> {
+        let mut emotes = ["small9cm", "yay", "ANGRY"]
+            .iter()
+            .map(|name| Emote {
+                name: (*name).to_owned(),
+                url: format!("http://example.com/{}.png", name),
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+
+        let overrides = serde_json::from_str::<Overrides>(
+            r##"{
+                "yay": { "title_color": "#FF88CC", "title_alignment": "top" },
+                "ANGRY": { "show_label": false }
+            }"##,
+        )?;
+        validate_overrides(&overrides)?;
+        assert!(apply_overrides(&mut emotes, &overrides).is_empty());
+
+        let states = emotes
+            .iter()
+            .map(|emote| {
+                emote.to_action(
+                    &ActionOptions {
+                        include_label: true,
+                        ..Default::default()
+                    },
+                    None,
+                )
+            })
+            .map(|action| action.states.into_iter().next().unwrap())
+            .collect::<Vec<_>>();
+
+        let default = State::default();
+        assert_eq!(states[0].title, "small9cm");
+        assert_eq!(states[0].title_color, default.title_color);
+        assert_eq!(states[0].title_alignment, default.title_alignment);
+
+        assert_eq!(states[1].title, "yay");
+        assert_eq!(states[1].title_color, "#ff88cc");
+        assert_eq!(states[1].title_alignment, "top");
+
+        assert_eq!(states[2].title, "");
+
+        // Labels can be shown for single emotes without `--include-labels`
+        emotes[0].show_label = Some(true);
+        let action = emotes[0].to_action(&ActionOptions::default(), None);
+        assert_eq!(action.states[0].title, "small9cm");
+
+        for invalid in [
+            r#"{ "yay": { "title_color": "pink" } }"#,
+            r#"{ "yay": { "title_alignment": "left" } }"#,
+        ] {
+            let overrides = serde_json::from_str::<Overrides>(invalid)?;
+            assert!(validate_overrides(&overrides).is_err(), "{}", invalid);
+        }
+
+        Ok(())
+    }
+}
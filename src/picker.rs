@@ -0,0 +1,60 @@
+use crate::profile::Emote;
+use color_eyre::eyre::{bail, Result};
+use dialoguer::{theme::ColorfulTheme, MultiSelect, Sort};
+
+/// Prompts for which emotes to include and the order to place them in
+pub fn pick(emotes: Vec<Emote>) -> Result<Vec<Emote>> {
+    let theme = ColorfulTheme::default();
+    let names = emotes.iter().map(|e| e.name.as_str()).collect::<Vec<_>>();
+
+    let selected = MultiSelect::with_theme(&theme)
+        .with_prompt("Select emotes (space to toggle, enter to confirm)")
+        .items(&names)
+        .defaults(&vec![true; names.len()])
+        .interact()?;
+
+    if selected.is_empty() {
+        bail!("No emotes were selected");
+    }
+
+    let selected_names = selected.iter().map(|&i| names[i]).collect::<Vec<_>>();
+    let order = Sort::with_theme(&theme)
+        .with_prompt("Reorder emotes (space to pick up or drop, enter to confirm)")
+        .items(&selected_names)
+        .interact()?;
+
+    Ok(apply_selection(emotes, &selected, &order))
+}
+
+/// Keeps the emotes at the `selected` indices, arranged so that the `i`th emote in the result is
+/// `selected[order[i]]`
+fn apply_selection(emotes: Vec<Emote>, selected: &[usize], order: &[usize]) -> Vec<Emote> {
+    let mut emotes = emotes.into_iter().map(Some).collect::<Vec<_>>();
+
+    order
+        .iter()
+        .filter_map(|&i| selected.get(i))
+        .filter_map(|&i| emotes.get_mut(i)?.take())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selection_to_emotes() {
+        let emotes = ["a", "b", "c", "d"]
+            .iter()
+            .map(|name| Emote {
+                name: name.to_string(),
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+
+        let picked = apply_selection(emotes, &[0, 2, 3], &[2, 0, 1]);
+        let names = picked.iter().map(|e| e.name.as_str()).collect::<Vec<_>>();
+
+        assert_eq!(names, vec!["d", "a", "c"]);
+    }
+}
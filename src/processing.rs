@@ -0,0 +1,853 @@
+use bytes::Bytes;
+use color_eyre::eyre::{bail, Result, WrapErr};
+use image::codecs::gif::{GifDecoder, GifEncoder, Repeat};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::{CompressionType, FilterType as PngFilterType, PngEncoder};
+use image::codecs::webp::WebPDecoder;
+use image::imageops::FilterType;
+use image::{AnimationDecoder, DynamicImage, Frame, ImageFormat, RgbaImage};
+use sha2::{Digest, Sha256};
+use std::io::Cursor;
+use std::str::FromStr;
+
+/// Options for transforming downloaded emote images before they're written to the profile
+#[derive(Clone, Debug)]
+pub struct ImageOptions {
+    /// Resize images to fit within a square of this many pixels, usually the key size
+    pub resize: u32,
+    /// Filter used when resizing
+    pub resize_filter: ResizeFilter,
+    /// Sharpen images after resizing them
+    pub sharpen: bool,
+    /// Generate a darkened copy of each image, for the second state of keys with `--toggle-dim`
+    pub toggle_dim: bool,
+    /// Keep animated GIF and WebP images animated, as a GIF, instead of using their first frame
+    pub animated: bool,
+    /// Format to encode static images in
+    pub format: KeyImageFormat,
+    /// Margin to leave around the emote, inside the key
+    pub padding: KeyPadding,
+    /// Dot to draw in a corner of the image, e.g. to show the emote's membership tier
+    pub badge: Option<Badge>,
+}
+
+impl Default for ImageOptions {
+    /// Resizes to the key size of the standard model
+    fn default() -> Self {
+        Self {
+            resize: 72,
+            resize_filter: ResizeFilter::default(),
+            sharpen: false,
+            toggle_dim: false,
+            animated: false,
+            format: KeyImageFormat::default(),
+            padding: KeyPadding::default(),
+            badge: None,
+        }
+    }
+}
+
+impl ImageOptions {
+    /// Resizes (and sharpens, if enabled) the image, then adds padding and the badge
+    fn resize(&self, image: DynamicImage) -> DynamicImage {
+        let size = self.resize;
+        let image = image.resize(size, size, self.resize_filter.filter_type());
+        let image = if self.sharpen {
+            image.unsharpen(SHARPEN_SIGMA, SHARPEN_THRESHOLD)
+        } else {
+            image
+        };
+
+        let image = self.pad(image);
+        match &self.badge {
+            Some(badge) => badge.draw(image),
+            None => image,
+        }
+    }
+
+    /// Scales the image down to leave a margin of `padding` on each side, centered on a transparent
+    /// square the size of the key
+    fn pad(&self, image: DynamicImage) -> DynamicImage {
+        if self.padding.is_none() {
+            return image;
+        }
+
+        let canvas_size = self.resize;
+        let inner_size = canvas_size * (100 - 2 * self.padding.0 as u32) / 100;
+        let image = image.resize(inner_size, inner_size, self.resize_filter.filter_type());
+
+        let mut canvas = RgbaImage::new(canvas_size, canvas_size);
+        let x = (canvas_size - image.width()) / 2;
+        let y = (canvas_size - image.height()) / 2;
+        image::imageops::overlay(&mut canvas, &image.to_rgba8(), x.into(), y.into());
+        DynamicImage::ImageRgba8(canvas)
+    }
+}
+
+/// Margin around emotes inside their keys, as a percentage of the key's size on each side
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct KeyPadding(u8);
+
+impl KeyPadding {
+    /// Any more than this wouldn't leave room for the emote
+    pub const MAX: u8 = 49;
+
+    fn is_none(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl FromStr for KeyPadding {
+    type Err = color_eyre::eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let percent = match s.trim().trim_end_matches('%').parse::<u8>() {
+            Ok(percent) => percent,
+            Err(_) => bail!(
+                "Invalid key padding {:?}, expected a whole percentage from 0 to {}",
+                s,
+                Self::MAX
+            ),
+        };
+
+        if percent > Self::MAX {
+            bail!(
+                "Key padding {}% is out of range, expected a percentage from 0 to {}",
+                percent,
+                Self::MAX
+            );
+        }
+
+        Ok(Self(percent))
+    }
+}
+
+/// A filled circle drawn in a corner of a key image
+#[derive(Clone, Debug, PartialEq)]
+pub struct Badge {
+    /// Color as RGB
+    pub color: [u8; 3],
+    pub style: BadgeStyle,
+}
+
+/// Where badges are drawn, and how large they are
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BadgeStyle {
+    pub corner: BadgeCorner,
+    pub size: BadgeSize,
+}
+
+impl Badge {
+    fn draw(&self, image: DynamicImage) -> DynamicImage {
+        let mut image = image.to_rgba8();
+        let (width, height) = image.dimensions();
+        let diameter = (width.min(height) * self.style.size.0 as u32 / 100).max(1);
+        // Keep the badge a little away from the edges, where keys have rounded corners
+        let margin = width.min(height) / 20;
+
+        let left = match self.style.corner {
+            BadgeCorner::TopLeft | BadgeCorner::BottomLeft => margin,
+            BadgeCorner::TopRight | BadgeCorner::BottomRight => width - margin - diameter,
+        };
+        let top = match self.style.corner {
+            BadgeCorner::TopLeft | BadgeCorner::TopRight => margin,
+            BadgeCorner::BottomLeft | BadgeCorner::BottomRight => height - margin - diameter,
+        };
+
+        let radius = diameter as f32 / 2.0;
+        let [r, g, b] = self.color;
+        for y in top..top + diameter {
+            for x in left..left + diameter {
+                let dx = x as f32 + 0.5 - (left as f32 + radius);
+                let dy = y as f32 + 0.5 - (top as f32 + radius);
+                if dx * dx + dy * dy <= radius * radius {
+                    image.put_pixel(x, y, image::Rgba([r, g, b, u8::MAX]));
+                }
+            }
+        }
+
+        DynamicImage::ImageRgba8(image)
+    }
+}
+
+/// Corner of the key that badges are drawn in
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum BadgeCorner {
+    TopLeft,
+    #[default]
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl FromStr for BadgeCorner {
+    type Err = color_eyre::eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_ref() {
+            "top-left" => Ok(Self::TopLeft),
+            "top-right" => Ok(Self::TopRight),
+            "bottom-left" => Ok(Self::BottomLeft),
+            "bottom-right" => Ok(Self::BottomRight),
+            other => bail!("Unknown badge corner {}", other),
+        }
+    }
+}
+
+/// Diameter of a badge, as a percentage of the key's size
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BadgeSize(u8);
+
+impl BadgeSize {
+    /// Badges larger than this would cover most of the emote (and not fit inside the margin)
+    pub const MAX: u8 = 50;
+}
+
+impl Default for BadgeSize {
+    fn default() -> Self {
+        Self(25)
+    }
+}
+
+impl FromStr for BadgeSize {
+    type Err = color_eyre::eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().trim_end_matches('%').parse::<u8>() {
+            Ok(percent) if (1..=Self::MAX).contains(&percent) => Ok(Self(percent)),
+            _ => bail!(
+                "Invalid badge size {:?}, expected a whole percentage from 1 to {}",
+                s,
+                Self::MAX
+            ),
+        }
+    }
+}
+
+/// Blur radius of the unsharp mask used for `sharpen`, in pixels
+const SHARPEN_SIGMA: f32 = 0.8;
+/// Minimum brightness difference for a pixel to be sharpened, to avoid amplifying noise
+const SHARPEN_THRESHOLD: i32 = 2;
+
+/// Filters from the `image` crate that can be used for resizing, from fastest to highest quality
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ResizeFilter {
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    #[default]
+    Lanczos3,
+}
+
+impl FromStr for ResizeFilter {
+    type Err = color_eyre::eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_ref() {
+            "nearest" => Ok(Self::Nearest),
+            "triangle" => Ok(Self::Triangle),
+            "catmull-rom" => Ok(Self::CatmullRom),
+            "gaussian" => Ok(Self::Gaussian),
+            "lanczos3" => Ok(Self::Lanczos3),
+            other => bail!("Unknown resize filter {}", other),
+        }
+    }
+}
+
+impl ResizeFilter {
+    fn filter_type(&self) -> FilterType {
+        match self {
+            Self::Nearest => FilterType::Nearest,
+            Self::Triangle => FilterType::Triangle,
+            Self::CatmullRom => FilterType::CatmullRom,
+            Self::Gaussian => FilterType::Gaussian,
+            Self::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+/// Format that static key images are encoded in
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum KeyImageFormat {
+    #[default]
+    Png,
+    /// Transparent pixels are blended with black, the background of the keys
+    Jpeg,
+    /// JPEG for images without any transparent pixels, and PNG otherwise
+    Auto,
+}
+
+impl FromStr for KeyImageFormat {
+    type Err = color_eyre::eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_ref() {
+            "png" => Ok(Self::Png),
+            "jpeg" | "jpg" => Ok(Self::Jpeg),
+            "auto" => Ok(Self::Auto),
+            other => bail!("Unknown image format {}", other),
+        }
+    }
+}
+
+/// Quality of JPEG key images, from 1 to 100
+const JPEG_QUALITY: u8 = 90;
+
+/// Format of a processed image, which determines the name of its file in the profile
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum EncodedFormat {
+    /// PNG, which every static image is re-encoded as unless JPEG is chosen
+    #[default]
+    Png,
+    Jpeg,
+    /// Animated GIF
+    Gif,
+}
+
+impl EncodedFormat {
+    /// File name of the image for the first state of a key
+    pub fn state_image_name(&self) -> &'static str {
+        match self {
+            Self::Png => "state0.png",
+            Self::Jpeg => "state0.jpg",
+            Self::Gif => "state0.gif",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ProcessedImage {
+    pub bytes: Bytes,
+    pub dimmed: Option<Bytes>,
+    pub format: EncodedFormat,
+}
+
+/// Decodes, transforms, and re-encodes an image as PNG or JPEG, or as GIF for animated images if
+/// `animated` is set. This is CPU-bound, so in async code it should be called via
+/// [`process_image_blocking`].
+pub fn process_image(bytes: &[u8], options: &ImageOptions) -> Result<ProcessedImage> {
+    if options.animated {
+        if let Some(frames) = animation_frames(bytes)? {
+            return process_animation(frames, options);
+        }
+    }
+
+    let image = image::load_from_memory(bytes).context("Failed to decode image")?;
+    let image = options.resize(image);
+
+    let dimmed = if options.toggle_dim {
+        Some(encode_png(&darken(&image))?)
+    } else {
+        None
+    };
+
+    let use_jpeg = match options.format {
+        KeyImageFormat::Png => false,
+        KeyImageFormat::Jpeg => true,
+        KeyImageFormat::Auto => is_opaque(&image),
+    };
+
+    Ok(if use_jpeg {
+        ProcessedImage {
+            bytes: encode_jpeg(&image)?,
+            dimmed,
+            format: EncodedFormat::Jpeg,
+        }
+    } else {
+        ProcessedImage {
+            bytes: encode_png(&image)?,
+            dimmed,
+            format: EncodedFormat::Png,
+        }
+    })
+}
+
+/// Decodes every frame of an animated GIF or WebP image. Returns `None` for other formats and for
+/// images with a single frame, which are handled like any other static image.
+fn animation_frames(bytes: &[u8]) -> Result<Option<Vec<Frame>>> {
+    let frames = match image::guess_format(bytes) {
+        Ok(ImageFormat::Gif) => GifDecoder::new(Cursor::new(bytes))
+            .and_then(|decoder| decoder.into_frames().collect_frames()),
+        Ok(ImageFormat::WebP) => {
+            let decoder = WebPDecoder::new(Cursor::new(bytes)).context("Failed to decode image")?;
+            if !decoder.has_animation() {
+                return Ok(None);
+            }
+            decoder.into_frames().collect_frames()
+        }
+        _ => return Ok(None),
+    };
+
+    let frames = frames.context("Failed to decode animation frames")?;
+    Ok(if frames.len() > 1 { Some(frames) } else { None })
+}
+
+/// Whether an image is a GIF or WebP with more than one frame. Only the first two frames are
+/// decoded, and images that can't be decoded count as static.
+pub fn is_animated(bytes: &[u8]) -> bool {
+    let frames = match image::guess_format(bytes) {
+        Ok(ImageFormat::Gif) => match GifDecoder::new(Cursor::new(bytes)) {
+            Ok(decoder) => decoder.into_frames().take(2).filter(Result::is_ok).count(),
+            Err(_) => return false,
+        },
+        Ok(ImageFormat::WebP) => match WebPDecoder::new(Cursor::new(bytes)) {
+            Ok(decoder) if decoder.has_animation() => {
+                decoder.into_frames().take(2).filter(Result::is_ok).count()
+            }
+            _ => return false,
+        },
+        _ => return false,
+    };
+    frames > 1
+}
+
+/// Resizes each frame and re-encodes the animation as a GIF that loops forever. The dimmed image
+/// (if enabled) is a static PNG of the first frame.
+fn process_animation(frames: Vec<Frame>, options: &ImageOptions) -> Result<ProcessedImage> {
+    let frames = frames
+        .into_iter()
+        .map(|frame| {
+            let delay = frame.delay();
+            let image = options.resize(DynamicImage::ImageRgba8(frame.into_buffer()));
+            Frame::from_parts(image.to_rgba8(), 0, 0, delay)
+        })
+        .collect::<Vec<_>>();
+
+    let dimmed = match frames.first() {
+        Some(frame) if options.toggle_dim => Some(encode_png(&darken(&DynamicImage::ImageRgba8(
+            frame.buffer().clone(),
+        )))?),
+        _ => None,
+    };
+
+    let mut buf = Vec::new();
+    {
+        let mut encoder = GifEncoder::new(&mut buf);
+        encoder
+            .set_repeat(Repeat::Infinite)
+            .context("Failed to encode animation")?;
+        encoder
+            .encode_frames(frames)
+            .context("Failed to encode animation")?;
+    }
+
+    Ok(ProcessedImage {
+        bytes: buf.into(),
+        dimmed,
+        format: EncodedFormat::Gif,
+    })
+}
+
+/// Reads the width and height of an image from its header, without decoding it
+pub fn image_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    image::ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .ok()?
+        .into_dimensions()
+        .ok()
+}
+
+/// File extension for the format of an image, or `bin` if it isn't a format we know
+pub fn image_extension(bytes: &[u8]) -> &'static str {
+    image::guess_format(bytes)
+        .ok()
+        .and_then(|format| format.extensions_str().first().copied())
+        .unwrap_or("bin")
+}
+
+/// Hashes the decoded pixels and dimensions of an image, so that the same image gives the same
+/// hash regardless of how it's encoded. Images that can't be decoded are hashed by their bytes.
+pub fn pixel_hash(bytes: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    match image::load_from_memory(bytes) {
+        Ok(image) => {
+            let image = image.to_rgba8();
+            hasher.update(image.width().to_be_bytes());
+            hasher.update(image.height().to_be_bytes());
+            hasher.update(image.as_raw());
+        }
+        Err(_) => hasher.update(bytes),
+    }
+    hasher.finalize().into()
+}
+
+/// Runs [`process_image`] on tokio's blocking thread pool, so that it doesn't stall downloads
+pub async fn process_image_blocking(
+    bytes: Bytes,
+    options: &ImageOptions,
+) -> Result<ProcessedImage> {
+    let options = options.clone();
+    tokio::task::spawn_blocking(move || process_image(&bytes, &options))
+        .await
+        .context("Image processing task failed")?
+}
+
+/// Scales down the color channels, keeping transparency as-is
+fn darken(image: &DynamicImage) -> DynamicImage {
+    let mut image = image.to_rgba8();
+    for pixel in image.pixels_mut() {
+        for channel in pixel.0[..3].iter_mut() {
+            *channel = (*channel as u16 * 3 / 5) as u8;
+        }
+    }
+    DynamicImage::ImageRgba8(image)
+}
+
+/// Encodes with fixed compression and filter settings, and without any metadata chunks (such as
+/// timestamps), so that the same image always gives the same bytes. The output can still change
+/// between versions of the `image` and `png` crates, so checksums are only stable for a given
+/// build of this tool.
+fn encode_png(image: &DynamicImage) -> Result<Bytes> {
+    let mut buf = Vec::new();
+    let encoder =
+        PngEncoder::new_with_quality(&mut buf, CompressionType::Default, PngFilterType::Adaptive);
+    image
+        .write_with_encoder(encoder)
+        .context("Failed to encode image")?;
+    Ok(buf.into())
+}
+
+/// Whether every pixel of the image is fully opaque
+fn is_opaque(image: &DynamicImage) -> bool {
+    !image.color().has_alpha() || image.to_rgba8().pixels().all(|pixel| pixel[3] == u8::MAX)
+}
+
+/// Encodes as JPEG, blending any transparent pixels with black since JPEG has no alpha channel
+fn encode_jpeg(image: &DynamicImage) -> Result<Bytes> {
+    let mut image = image.to_rgba8();
+    for pixel in image.pixels_mut() {
+        let alpha = pixel[3] as u16;
+        for channel in pixel.0[..3].iter_mut() {
+            *channel = (*channel as u16 * alpha / 255) as u8;
+        }
+    }
+    let image = DynamicImage::ImageRgba8(image).to_rgb8();
+
+    let mut buf = Vec::new();
+    let encoder = JpegEncoder::new_with_quality(&mut buf, JPEG_QUALITY);
+    image
+        .write_with_encoder(encoder)
+        .context("Failed to encode image")?;
+    Ok(buf.into())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use image::Rgba;
+
+    /// Generates a PNG with a gradient, so that resizing has an effect on the pixels
+    pub fn fixture_png(width: u32, height: u32) -> Bytes {
+        let image = RgbaImage::from_fn(width, height, |x, y| {
+            Rgba([(x * 255 / width) as u8, (y * 255 / height) as u8, 128, 255])
+        });
+
+        encode_png(&DynamicImage::ImageRgba8(image)).unwrap()
+    }
+
+    /// Generates an animated GIF where each frame is a different solid color
+    pub fn fixture_gif(width: u32, height: u32, frames: u8) -> Bytes {
+        let frames = (0..frames).map(|i| {
+            let image = RgbaImage::from_pixel(width, height, Rgba([i * 50, 0, 255 - i * 50, 255]));
+            Frame::new(image)
+        });
+
+        let mut buf = Vec::new();
+        GifEncoder::new(&mut buf).encode_frames(frames).unwrap();
+        buf.into()
+    }
+
+    #[test]
+    fn animation_detection() -> Result<()> {
+        assert!(is_animated(&fixture_gif(16, 16, 3)));
+        assert!(is_animated(&fixture_gif(16, 16, 2)));
+        assert!(!is_animated(&fixture_gif(16, 16, 1)));
+        assert!(!is_animated(&fixture_png(16, 16)));
+        assert!(!is_animated(b"not an image"));
+
+        let mut webp = Vec::new();
+        let image = RgbaImage::from_pixel(16, 16, Rgba([255, 0, 0, 255]));
+        image::codecs::webp::WebPEncoder::new_lossless(&mut webp).encode(
+            image.as_raw(),
+            16,
+            16,
+            image::ExtendedColorType::Rgba8,
+        )?;
+        assert_eq!(image::guess_format(&webp)?, ImageFormat::WebP);
+        assert!(!is_animated(&webp));
+
+        Ok(())
+    }
+
+    #[test]
+    fn animated_gif() -> Result<()> {
+        let options = ImageOptions {
+            resize: 72,
+            animated: true,
+            ..Default::default()
+        };
+        let output = process_image(&fixture_gif(96, 96, 3), &options)?;
+        assert_eq!(output.format, EncodedFormat::Gif);
+
+        let frames = GifDecoder::new(Cursor::new(&output.bytes))?
+            .into_frames()
+            .collect_frames()?;
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].buffer().dimensions(), (72, 72));
+
+        // Without `animated`, only the first frame is kept
+        let options = ImageOptions {
+            animated: false,
+            ..options
+        };
+        let output = process_image(&fixture_gif(96, 96, 3), &options)?;
+        assert_eq!(output.format, EncodedFormat::Png);
+        assert_eq!(image::guess_format(&output.bytes)?, ImageFormat::Png);
+
+        // Static images are resized like any other image
+        let options = ImageOptions {
+            animated: true,
+            ..Default::default()
+        };
+        let output = process_image(&fixture_gif(16, 16, 1), &options)?;
+        assert_eq!(output.format, EncodedFormat::Png);
+        assert_eq!(image_dimensions(&output.bytes), Some((72, 72)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn jpeg_format() -> Result<()> {
+        let transparent = {
+            let image = RgbaImage::from_fn(16, 16, |x, _| Rgba([255, 0, 0, (x * 16) as u8]));
+            encode_png(&DynamicImage::ImageRgba8(image))?
+        };
+
+        let options = ImageOptions {
+            format: KeyImageFormat::Auto,
+            ..Default::default()
+        };
+        let output = process_image(&fixture_png(16, 16), &options)?;
+        assert_eq!(output.format, EncodedFormat::Jpeg);
+        assert_eq!(image::guess_format(&output.bytes)?, ImageFormat::Jpeg);
+
+        let output = process_image(&transparent, &options)?;
+        assert_eq!(output.format, EncodedFormat::Png);
+        assert_eq!(image::guess_format(&output.bytes)?, ImageFormat::Png);
+
+        // Forcing JPEG blends transparent pixels with black
+        let options = ImageOptions {
+            format: KeyImageFormat::Jpeg,
+            ..Default::default()
+        };
+        let output = process_image(&transparent, &options)?;
+        assert_eq!(output.format, EncodedFormat::Jpeg);
+        let image = image::load_from_memory(&output.bytes)?.to_rgb8();
+        assert!(image.get_pixel(0, 0)[0] < 16);
+        assert!(image.get_pixel(71, 0)[0] > 200);
+
+        Ok(())
+    }
+
+    #[test]
+    fn resize_to_key_size() -> Result<()> {
+        let options = ImageOptions {
+            resize: 72,
+            ..Default::default()
+        };
+        let output = process_image(&fixture_png(256, 128), &options)?;
+
+        let image = image::load_from_memory(&output.bytes)?;
+        assert_eq!((image.width(), image.height()), (72, 36));
+        assert_eq!(output.dimmed, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn key_padding() -> Result<()> {
+        assert_eq!("20".parse::<KeyPadding>()?, KeyPadding(20));
+        assert_eq!("10%".parse::<KeyPadding>()?, KeyPadding(10));
+        for invalid in ["50", "-1", "12.5", "wide", ""] {
+            let error = invalid.parse::<KeyPadding>().unwrap_err().to_string();
+            assert!(error.contains("from 0 to 49"), "{}", error);
+        }
+
+        let options = ImageOptions {
+            resize: 100,
+            padding: KeyPadding(20),
+            ..Default::default()
+        };
+        let output = process_image(&fixture_png(64, 64), &options)?;
+
+        // The emote takes up the middle 60% of the key, and the rest is transparent
+        let image = image::load_from_memory(&output.bytes)?.to_rgba8();
+        assert_eq!(image.dimensions(), (100, 100));
+        let opaque = |x, y| image.get_pixel(x, y)[3] == 255;
+        assert!(opaque(20, 20) && opaque(79, 79) && opaque(50, 50));
+        assert!(!opaque(19, 50) && !opaque(80, 50) && !opaque(50, 19) && !opaque(50, 80));
+
+        Ok(())
+    }
+
+    #[test]
+    fn resize_filters() -> Result<()> {
+        let filters = ["nearest", "triangle", "catmull-rom", "gaussian", "lanczos3"];
+
+        let mut outputs = Vec::new();
+        for filter in filters.iter() {
+            let options = ImageOptions {
+                resize: 96,
+                resize_filter: filter.parse()?,
+                ..Default::default()
+            };
+            let output = process_image(&fixture_png(12, 12), &options)?;
+            assert!(
+                !outputs.contains(&output.bytes),
+                "{} gave the same output",
+                filter
+            );
+            outputs.push(output.bytes);
+        }
+
+        // A gradient has no edges to sharpen, so use a checkerboard
+        let checkerboard = RgbaImage::from_fn(12, 12, |x, y| {
+            let value = if (x + y) % 2 == 0 { 255 } else { 0 };
+            Rgba([value, value, value, 255])
+        });
+        let checkerboard = encode_png(&DynamicImage::ImageRgba8(checkerboard))?;
+        let mut options = ImageOptions {
+            resize: 96,
+            ..Default::default()
+        };
+        let smooth = process_image(&checkerboard, &options)?;
+        options.sharpen = true;
+        let sharpened = process_image(&checkerboard, &options)?;
+        assert_ne!(smooth, sharpened);
+        assert!("bicubic".parse::<ResizeFilter>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn encoding_is_deterministic() -> Result<()> {
+        let options = ImageOptions {
+            resize: 72,
+            toggle_dim: true,
+            ..Default::default()
+        };
+
+        let input = fixture_png(200, 150);
+        let first = process_image(&input, &options)?;
+        let second = process_image(&input, &options)?;
+        assert_eq!(first, second);
+
+        // Only the chunks needed for the image itself, without timestamps or text
+        let mut chunk_types = Vec::new();
+        let mut rest = &first.bytes[8..];
+        while rest.len() >= 12 {
+            let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            chunk_types.push(String::from_utf8_lossy(&rest[4..8]).into_owned());
+            rest = &rest[12 + len..];
+        }
+        assert!(chunk_types
+            .iter()
+            .all(|t| ["IHDR", "IDAT", "IEND"].contains(&t.as_str())));
+
+        Ok(())
+    }
+
+    #[test]
+    fn toggle_dim_darkens() -> Result<()> {
+        let options = ImageOptions {
+            toggle_dim: true,
+            ..Default::default()
+        };
+        let output = process_image(&fixture_png(16, 16), &options)?;
+
+        let normal = image::load_from_memory(&output.bytes)?.to_rgba8();
+        let dimmed = image::load_from_memory(&output.dimmed.unwrap())?.to_rgba8();
+        assert_eq!(normal.dimensions(), dimmed.dimensions());
+
+        let (normal, dimmed) = (normal.get_pixel(15, 15), dimmed.get_pixel(15, 15));
+        assert!(dimmed[0] < normal[0] && dimmed[2] < normal[2]);
+        assert_eq!(dimmed[3], normal[3]);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn blocking_matches_single_threaded() -> Result<()> {
+        let options = ImageOptions {
+            resize: 96,
+            toggle_dim: true,
+            ..Default::default()
+        };
+        let inputs = (1..=20)
+            .map(|i| fixture_png(i * 10, 200 - i * 5))
+            .collect::<Vec<_>>();
+
+        let expected = inputs
+            .iter()
+            .map(|bytes| process_image(bytes, &options))
+            .collect::<Result<Vec<_>>>()?;
+
+        let futures = inputs
+            .into_iter()
+            .map(|bytes| process_image_blocking(bytes, &options));
+        let actual = futures::future::join_all(futures)
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?;
+
+        assert_eq!(expected, actual);
+
+        Ok(())
+    }
+
+    /// Compares processing 150 emotes inline in the download futures against running them on the
+    /// blocking pool. Timings are only meaningful in release mode, and the blocking pool can only
+    /// be faster with more than one core.
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "benchmark, run with `cargo test --release -- --ignored --nocapture benchmark`"]
+    async fn benchmark_150_emotes() -> Result<()> {
+        let options = ImageOptions {
+            resize: 72,
+            toggle_dim: true,
+            ..Default::default()
+        };
+        let inputs = (0..150)
+            .map(|i| fixture_png(128 + i % 8, 128))
+            .collect::<Vec<_>>();
+
+        let start = std::time::Instant::now();
+        let options = &options;
+        let futures = inputs
+            .iter()
+            .map(|bytes| async move { process_image(bytes, options) });
+        let inline = futures::future::join_all(futures)
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?;
+        let inline_time = start.elapsed();
+
+        let start = std::time::Instant::now();
+        let futures = inputs
+            .into_iter()
+            .map(|bytes| process_image_blocking(bytes, options));
+        let blocking = futures::future::join_all(futures)
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?;
+        let blocking_time = start.elapsed();
+
+        assert_eq!(inline, blocking);
+        println!(
+            "inline: {:?}, blocking pool: {:?}",
+            inline_time, blocking_time
+        );
+
+        Ok(())
+    }
+}
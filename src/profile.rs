@@ -1,25 +1,65 @@
+use crate::error::Error;
+use crate::fetch::ImageFetcher;
+use crate::processing::{
+    image_dimensions, image_extension, is_animated, pixel_hash, process_image_blocking, Badge,
+    BadgeStyle, EncodedFormat, ImageOptions,
+};
+use crate::strings::{capitalize_first, strip_prefix_ci, truncate_chars};
 use bytes::Bytes;
 use color_eyre::eyre::bail;
 use color_eyre::eyre::{Result, WrapErr};
 use serde::{Serialize, Serializer};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use tracing::info;
+use tracing::{info, warn};
 use uuid::Uuid;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct Emote {
     pub name: String,
     pub url: String,
+    /// YouTube's ID for the emote (its `emojiId`), if the page includes one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Overrides the name shown on the key when labels are enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Overrides the text that's pasted when the key is pressed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pasted_text: Option<String>,
+    /// Name of the membership tier that unlocks the emote, if known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tier: Option<String>,
+    /// Overrides the color of the label, as `#RRGGBB`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title_color: Option<String>,
+    /// Overrides the position of the label: `top`, `middle`, or `bottom`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title_alignment: Option<String>,
+    /// Overrides whether the key has a label, regardless of `--include-labels`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub show_label: Option<bool>,
+    /// Folder to place the emote in, when emotes are organized into categories
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
 }
 
 #[derive(Debug)]
 pub struct EmoteImage {
     pub emote: Emote,
     pub bytes: Bytes,
+    /// Image for the key's dimmed state, with `--toggle-dim`
+    pub dimmed_bytes: Option<Bytes>,
+    /// Format of `bytes`, which determines the name of the image file
+    pub format: EncodedFormat,
 }
 
+/// Version of the manifest format that profiles are written in
+pub const MANIFEST_VERSION: &str = "1.0";
+
 pub fn uuid_v5(name: &str, page: usize) -> Uuid {
     let url = format!(
         "https://github.com/walfie/streamdeck-youtube-emotes#{}_page{}",
@@ -28,491 +68,2866 @@ pub fn uuid_v5(name: &str, page: usize) -> Uuid {
     Uuid::new_v5(&Uuid::NAMESPACE_URL, url.as_bytes())
 }
 
-impl Emote {
-    pub fn to_action(&self, prefix: &str, include_label: bool, image: Option<Bytes>) -> Action {
-        let mut state = State::new_image();
+/// Normalizes a user-provided emote prefix, so that `pomu`, `_pomu`, and `:_pomu` (the start of an
+/// emote code such as `:_pomuSmall9cm:`) are all treated as `pomu`.
+pub fn normalize_prefix(prefix: &str) -> String {
+    let prefix = prefix.trim();
+    let prefix = prefix.strip_prefix(':').unwrap_or(prefix);
+    let prefix = prefix.strip_suffix(':').unwrap_or(prefix);
+    prefix.strip_prefix('_').unwrap_or(prefix).to_owned()
+}
 
-        if include_label {
-            state.title = self.name.clone();
-        }
+/// Guesses whether `prefix` is wrong for the given emote names. Names from YouTube usually leave out
+/// the prefix, but if most names look like they start with a family name (like `pomuHi` and
+/// `pomuLove`) and none of them start with `prefix`, that family name is returned as a suggestion.
+pub fn suggest_prefix<'a>(
+    prefix: &str,
+    names: impl IntoIterator<Item = &'a str>,
+) -> Option<String> {
+    if prefix.is_empty() {
+        return None;
+    }
 
-        let mut name = self.name.clone();
-        if !prefix.is_empty() && !name.is_empty() {
-            if let Some(c) = name.get_mut(0..1) {
-                c.make_ascii_uppercase();
-            }
+    let mut counts = BTreeMap::<&str, usize>::new();
+    let mut total = 0;
+
+    for name in names {
+        if strip_prefix_ci(name, prefix).is_some() {
+            return None;
         }
 
-        Action {
-            name: "Text".into(),
-            state: 0,
-            states: vec![state],
-            image,
-            settings: Settings::Text {
-                is_sending_enter: false,
-                pasted_text: format!(":_{}{}:", prefix, name),
-            },
+        total += 1;
+        let family_len = name
+            .find(|c: char| !c.is_ascii_lowercase())
+            .filter(|&len| len >= 2 && !name[len..].starts_with(|c: char| c.is_lowercase()));
+        if let Some(len) = family_len {
+            *counts.entry(&name[..len]).or_default() += 1;
         }
     }
+
+    let (family, count) = counts.into_iter().max_by_key(|(_, count)| *count)?;
+    if count >= 2 && count * 2 >= total {
+        Some(family.to_owned())
+    } else {
+        None
+    }
 }
 
-pub struct ProfilesWithImages {
-    pub manifests: Vec<(Uuid, ProfileManifest)>,
+/// Makes an emote name safe to use as a file or directory name, by replacing characters that
+/// aren't allowed in paths on some platforms and collapsing whitespace
+pub fn sanitize_name(name: &str) -> String {
+    let replaced = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => ' ',
+            c => c,
+        })
+        .collect::<String>();
+
+    let collapsed = replaced.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    // Windows doesn't allow names that end with a dot, which also rules out `.` and `..`
+    let trimmed = collapsed.trim_end_matches('.');
+    if trimmed.is_empty() {
+        "_".to_owned()
+    } else {
+        trimmed.to_owned()
+    }
 }
 
-impl ProfilesWithImages {
-    pub async fn new(
-        root_profile_uuid: Uuid,
-        model: DeviceModel,
-        device_uuid: String,
-        name: String,
-        emotes: Vec<Emote>,
-        prefix: &str,
-        include_label: bool,
-    ) -> Result<Self> {
-        let image_futures = emotes.into_iter().map(|emote| async move {
-            info!(name = %emote.name, url = %emote.url, "Downloading image");
-            let resp = reqwest::get(&emote.url)
-                .await
-                .with_context(|| format!("Failed to call URL {}", emote.url))?;
+/// How an emote's name is transformed before being inserted into the pasted text
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NameCase {
+    AsIs,
+    CapitalizeFirst,
+    Lowercase,
+    Uppercase,
+}
 
-            if !resp.status().is_success() {
-                bail!(
-                    "Received non-success code {} from URL {}",
-                    resp.status(),
-                    emote.url
-                );
-            }
+impl FromStr for NameCase {
+    type Err = color_eyre::eyre::Error;
 
-            Ok(EmoteImage {
-                emote,
-                bytes: resp.bytes().await?,
-            })
-        });
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_ref() {
+            "as-is" => Ok(NameCase::AsIs),
+            "capitalize-first" => Ok(NameCase::CapitalizeFirst),
+            "lowercase" => Ok(NameCase::Lowercase),
+            "uppercase" => Ok(NameCase::Uppercase),
+            other => bail!("Unknown name case {}", other),
+        }
+    }
+}
 
-        let images = futures::future::join_all(image_futures)
-            .await
-            .into_iter()
-            .collect::<Result<Vec<EmoteImage>>>()
-            .context("failed to load images")?;
+impl NameCase {
+    /// Emote codes with a prefix have the first letter of the name capitalized (e.g.,
+    /// `:_pomuSmall9cm:`), while emote codes without a prefix use the name as-is.
+    pub fn default_for_prefix(prefix: &str) -> Self {
+        if prefix.is_empty() {
+            NameCase::AsIs
+        } else {
+            NameCase::CapitalizeFirst
+        }
+    }
 
-        let (width, height) = model.size();
-        let max_len = (width * height) as usize;
+    pub fn apply(&self, name: &str) -> String {
+        match self {
+            Self::AsIs => name.to_owned(),
+            Self::CapitalizeFirst => capitalize_first(name),
+            Self::Lowercase => name.to_lowercase(),
+            Self::Uppercase => name.to_uppercase(),
+        }
+    }
+}
 
-        let mut manifests = Vec::new();
-        let mut manifest_actions: Vec<Option<Action>> = Vec::new();
-
-        for image in images.into_iter() {
-            if manifest_actions.len() >= max_len {
-                let manifest_uuid = if manifests.is_empty() {
-                    root_profile_uuid
-                } else {
-                    uuid_v5(&name, manifests.len())
-                };
+/// A title color for the labels of emotes in a membership tier, parsed from `TIER=#RRGGBB`
+#[derive(Clone, Debug, PartialEq)]
+pub struct TierColor {
+    pub tier: String,
+    pub color: String,
+}
 
-                let mut manifest = ProfileManifest {
-                    actions: HashMap::new(),
-                    device_model: model.clone(),
-                    device_uuid: device_uuid.clone(),
-                    name: name.clone(),
-                    version: "1.0".to_owned(),
-                };
+impl FromStr for TierColor {
+    type Err = color_eyre::eyre::Error;
 
-                manifest.set_actions(std::mem::take(&mut manifest_actions));
+    fn from_str(s: &str) -> Result<Self> {
+        let (tier, color) = match s.rsplit_once('=') {
+            Some((tier, color)) if !tier.trim().is_empty() => (tier.trim(), color.trim()),
+            _ => bail!(
+                "Expected a tier color in the form TIER=#RRGGBB, got {:?}",
+                s
+            ),
+        };
 
-                manifests.push((manifest_uuid, manifest));
-            }
+        if !is_hex_color(color) {
+            bail!(
+                "Invalid color {:?} for tier {:?}, expected #RRGGBB",
+                color,
+                tier
+            );
+        }
 
-            if manifest_actions.len() % (width as usize) == 0 {
-                manifest_actions.push(None);
-            }
+        Ok(TierColor {
+            tier: tier.to_owned(),
+            color: color.to_lowercase(),
+        })
+    }
+}
 
-            manifest_actions.push(Some(image.emote.to_action(
-                prefix,
-                include_label,
-                Some(image.bytes.clone()),
-            )));
-        }
+impl TierColor {
+    /// The color as RGB
+    fn rgb(&self) -> [u8; 3] {
+        let channel = |i: usize| u8::from_str_radix(&self.color[i..i + 2], 16).unwrap_or(0);
+        [channel(1), channel(3), channel(5)]
+    }
+}
 
-        if !manifest_actions.is_empty() {
-            let mut manifest = ProfileManifest {
-                actions: HashMap::new(),
-                device_model: model.clone(),
-                device_uuid: device_uuid.clone(),
-                name: name.clone(),
-                version: "1.0".to_owned(),
-            };
+/// Colors of tier badges for tiers without a `--tier-color`, in the order that tiers first appear
+/// on the memberships page (which lists the lowest tier first)
+const TIER_BADGE_COLORS: &[[u8; 3]] = &[
+    [0x2e, 0x9e, 0x44], // green
+    [0x1e, 0x88, 0xe5], // blue
+    [0x8e, 0x24, 0xaa], // purple
+    [0xf5, 0x7c, 0x00], // orange
+    [0xe5, 0x39, 0x35], // red
+];
+
+/// Color of the badge for an emote in `tier`, where `tiers` lists every tier in order
+fn tier_badge_color(tier: &str, tiers: &[String], tier_colors: &[TierColor]) -> [u8; 3] {
+    if let Some(tier_color) = tier_colors
+        .iter()
+        .find(|c| c.tier.eq_ignore_ascii_case(tier))
+    {
+        return tier_color.rgb();
+    }
 
-            manifest.set_actions(std::mem::take(&mut manifest_actions));
+    let index = tiers.iter().position(|t| t == tier).unwrap_or(0);
+    TIER_BADGE_COLORS[index % TIER_BADGE_COLORS.len()]
+}
 
-            let manifest_uuid = if manifests.is_empty() {
-                root_profile_uuid
-            } else {
-                uuid_v5(&name, manifests.len())
-            };
+/// Image options for one emote, which add a badge for its tier if `tier_badges` is set
+fn image_options(options: &ProfileOptions, tiers: &[String], emote: &Emote) -> ImageOptions {
+    match (options.tier_badges, &emote.tier) {
+        (Some(style), Some(tier)) => ImageOptions {
+            badge: Some(Badge {
+                color: tier_badge_color(tier, tiers, &options.tier_colors),
+                style,
+            }),
+            ..options.image.clone()
+        },
+        _ => options.image.clone(),
+    }
+}
 
-            manifests.push((manifest_uuid, manifest));
-        }
+/// Whether the color is in the form `#RRGGBB`, which is how manifests store title colors
+pub fn is_hex_color(color: &str) -> bool {
+    color.len() == 7 && color.starts_with('#') && color[1..].chars().all(|c| c.is_ascii_hexdigit())
+}
 
-        for (_, manifest) in manifests.iter_mut().skip(1) {
-            let action = Action {
-                name: "Open Folder".into(),
-                state: 0,
-                states: vec![State {
-                    title: "Back".into(),
-                    ..State::new_image()
-                }],
-                settings: Settings::BackToParent {},
-                image: Some(include_bytes!("../images/back.png").as_ref().into()),
-            };
+/// Positions of a key's title that the Stream Deck application supports
+pub const TITLE_ALIGNMENTS: &[&str] = &["top", "middle", "bottom"];
 
-            manifest.actions.insert(Position::new(0, 0), action);
+/// Shortens a label to at most `max_chars` characters, ending it with an ellipsis if anything was
+/// cut off. With `wrap`, the limit is per line instead, and words are moved to new lines as needed.
+pub fn fit_label(label: &str, max_chars: usize, wrap: bool) -> String {
+    let truncate = |text: &str| {
+        if text.chars().count() <= max_chars {
+            return text.to_owned();
         }
+        let mut truncated = truncate_chars(text, max_chars.saturating_sub(1)).to_owned();
+        truncated.push('…');
+        truncated
+    };
 
-        let mut child_uuid: Option<Uuid> = None;
-        for (uuid, manifest) in manifests.iter_mut().rev() {
-            if let Some(child) = child_uuid {
-                let action = Action {
-                    name: "Create Folder".into(),
-                    state: 0,
-                    states: vec![State {
-                        title: "Next".into(),
-                        ..State::new_image()
-                    }],
-                    settings: Settings::OpenChild {
-                        profile_uuid: child.clone(),
-                    },
-                    image: Some(include_bytes!("../images/forward.png").as_ref().into()),
-                };
+    if !wrap {
+        return truncate(label);
+    }
 
-                manifest
-                    .actions
-                    .insert(Position::new(0, height - 1), action);
+    let mut lines = Vec::<String>::new();
+    for word in label.split_whitespace() {
+        match lines.last_mut() {
+            Some(line) if line.chars().count() + 1 + word.chars().count() <= max_chars => {
+                line.push(' ');
+                line.push_str(word);
             }
+            _ => lines.push(truncate(word)),
+        }
+    }
+    lines.join("\n")
+}
 
-            child_uuid = Some(uuid.clone());
+/// Font size of emote labels, in points. Stored as the string that manifests use.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LabelSize(String);
+
+impl LabelSize {
+    /// Sizes outside of this range are rejected, since the Stream Deck application ignores them
+    pub const MIN: u8 = 6;
+    pub const MAX: u8 = 40;
+}
+
+impl FromStr for LabelSize {
+    type Err = color_eyre::eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let size = match s.trim().parse::<u8>() {
+            Ok(size) => size,
+            Err(_) => bail!(
+                "Invalid label size {:?}, expected a whole number from {} to {}",
+                s,
+                Self::MIN,
+                Self::MAX
+            ),
+        };
+
+        if !(Self::MIN..=Self::MAX).contains(&size) {
+            bail!(
+                "Label size {} is out of range, expected a number from {} to {}",
+                size,
+                Self::MIN,
+                Self::MAX
+            );
         }
 
-        Ok(Self { manifests })
+        Ok(LabelSize(size.to_string()))
     }
 }
 
-#[derive(Serialize)]
-#[serde(rename_all = "PascalCase")]
-pub struct ProfileManifest {
-    pub actions: HashMap<Position, Action>,
-    pub device_model: DeviceModel,
-    #[serde(rename = "DeviceUUID")]
-    pub device_uuid: String, // e.g., `@(1)[4057/128/DL16K1A70561]`
-    pub name: String,
-    pub version: String, // `1.0`
+/// A key that opens a website, parsed from `NAME=URL`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExtraAction {
+    pub target: ExtraActionTarget,
+    pub url: String,
 }
 
-#[derive(Clone)]
-pub enum DeviceModel {
-    Standard,
-    XL,
-    Mini,
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExtraActionTarget {
+    /// Replaces the key of the emote with this name (case-insensitive)
+    Emote(String),
+    /// Adds a key at this position of the first page, if it's free
+    Position(Position),
 }
 
-impl FromStr for DeviceModel {
+impl FromStr for ExtraAction {
     type Err = color_eyre::eyre::Error;
 
     fn from_str(s: &str) -> Result<Self> {
-        match s.to_lowercase().as_ref() {
-            "standard" => Ok(DeviceModel::Standard),
-            "xl" => Ok(DeviceModel::XL),
-            "mini" => Ok(DeviceModel::Mini),
-            other => bail!("Unknown device model {}", other),
+        // URLs can contain `=`, but emote names can't
+        let (name, url) = match s.split_once('=') {
+            Some((name, url)) if !name.trim().is_empty() && !url.trim().is_empty() => {
+                (name.trim(), url.trim())
+            }
+            _ => bail!("Expected an extra action in the form NAME=URL, got {:?}", s),
+        };
+
+        let target = match name.parse() {
+            Ok(position) => ExtraActionTarget::Position(position),
+            Err(_) => ExtraActionTarget::Emote(name.to_owned()),
+        };
+
+        Ok(ExtraAction {
+            target,
+            url: url.to_owned(),
+        })
+    }
+}
+
+impl ExtraAction {
+    fn settings(&self) -> Settings {
+        Settings::OpenUrl {
+            open_in_browser: true,
+            path: self.url.clone(),
         }
     }
 }
 
-impl DeviceModel {
-    pub fn id(&self) -> &'static str {
-        match self {
-            Self::Standard => "20GBA9901",
-            Self::XL => "20GAT9901",
-            Self::Mini => "unknown", // TODO: Find correct value
+impl Emote {
+    /// Text pasted by the emote's key: its YouTube code (`:_pomuSmall9cm:`, or `:pomuSmall9cm:`
+    /// without `underscore`) unless a text template or override applies
+    pub fn code(&self, options: &ActionOptions) -> String {
+        self.pasted_text.clone().unwrap_or_else(|| {
+            let name = options.name_case.apply(&self.name);
+            match &options.text_template {
+                Some(template) => template
+                    .replace("{prefix}", &options.prefix)
+                    .replace("{name}", &name)
+                    .replace("{Name}", &NameCase::CapitalizeFirst.apply(&self.name)),
+                None => {
+                    let underscore = if options.underscore { "_" } else { "" };
+                    format!(":{}{}{}:", underscore, options.prefix, name)
+                }
+            }
+        })
+    }
+
+    pub fn to_action(&self, options: &ActionOptions, image: Option<Bytes>) -> Action {
+        let mut state = State::new_image();
+
+        if self.show_label.unwrap_or(options.include_label) {
+            let label = self.label.as_ref().unwrap_or(&self.name);
+            state.title = match options.label_max_chars {
+                Some(max_chars) => fit_label(label, max_chars, options.label_wrap),
+                None => label.clone(),
+            };
+        }
+        if let Some(LabelSize(size)) = &options.label_size {
+            state.f_size = size.clone();
+        }
+        if let Some(color) = &self.title_color {
+            state.title_color = color.clone();
+        }
+        if let Some(alignment) = &self.title_alignment {
+            state.title_alignment = alignment.clone();
+        }
+
+        let pasted_text = self.code(options);
+
+        Action {
+            name: "Text".into(),
+            state: 0,
+            states: vec![state],
+            image,
+            settings: Settings::Text {
+                is_sending_enter: false,
+                pasted_text,
+            },
+            emote: Some(self.clone()),
+            dimmed_image: None,
         }
     }
+}
 
-    pub fn size(&self) -> (u8, u8) {
-        match self {
-            Self::Standard => (5, 3),
-            Self::XL => (8, 4),
-            Self::Mini => (3, 2),
+/// Finds pasted texts that are shared by more than one action, along with the names of the emotes
+/// that produce them. This usually happens when two emote names only differ by the case of their
+/// first letter, since it gets capitalized when a prefix is set.
+pub fn duplicate_pasted_texts<'a>(
+    actions: impl IntoIterator<Item = &'a Action>,
+) -> Vec<(String, Vec<String>)> {
+    let mut names_by_text = BTreeMap::<&str, Vec<String>>::new();
+
+    for action in actions {
+        if let (Settings::Text { pasted_text, .. }, Some(emote)) = (&action.settings, &action.emote)
+        {
+            names_by_text
+                .entry(pasted_text)
+                .or_default()
+                .push(emote.name.clone());
         }
     }
+
+    names_by_text
+        .into_iter()
+        .filter(|(_, names)| names.len() > 1)
+        .map(|(text, names)| (text.to_owned(), names))
+        .collect()
 }
-impl Serialize for DeviceModel {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        serializer.serialize_str(self.id())
+
+/// How emotes are split into pages, which can be worked out before any images are downloaded
+#[derive(Debug, PartialEq)]
+pub struct LayoutPlan {
+    /// Number of emotes on each page of emotes, in order
+    pub emotes_per_page: Vec<usize>,
+    /// Keys on each page of emotes that are kept free for navigation
+    pub reserved: Vec<Position>,
+    /// Keys on the first page of emotes before `start_position`, which are left empty
+    pub skipped: Vec<Position>,
+    /// How the pages are linked. This is `Chain` if there's only one page, or if the index page
+    /// wouldn't have room for every page.
+    pub nav_style: NavStyle,
+}
+
+impl LayoutPlan {
+    /// Total number of pages, including the index page
+    pub fn pages(&self) -> usize {
+        match self.nav_style {
+            NavStyle::Chain => self.emotes_per_page.len(),
+            NavStyle::Index => self.emotes_per_page.len() + 1,
+        }
     }
 }
 
-impl ProfileManifest {
-    pub fn set_actions(&mut self, actions: Vec<Option<Action>>) {
-        let (width, _height) = self.device_model.size();
+/// Every key position of the device, row by row
+fn grid_cells(model: &DeviceModel) -> Vec<Position> {
+    let (width, height) = model.size();
+    (0..height)
+        .flat_map(|y| (0..width).map(move |x| Position::new(x, y)))
+        .collect()
+}
 
-        for (index, action) in actions.into_iter().enumerate() {
-            let index = index as u8;
-            let pos = Position::new(index % width, index / width);
+/// Picks `count` of the free keys, filling rows from the top but centering the emotes of the last
+/// row within the free keys of that row
+fn center_last_row(free_cells: &[Position], count: usize) -> Vec<Position> {
+    let mut slots = Vec::with_capacity(count);
+    let mut rows = free_cells.chunk_by(|a, b| a.y == b.y);
 
-            if let Some(action) = action {
-                self.actions.insert(pos, action);
-            } else {
-                self.actions.remove(&pos);
-            }
+    while slots.len() < count {
+        let row = match rows.next() {
+            Some(row) => row,
+            None => break,
+        };
+        let remaining = count - slots.len();
+        if remaining >= row.len() {
+            slots.extend_from_slice(row);
+        } else {
+            let offset = (row.len() - remaining) / 2;
+            slots.extend_from_slice(&row[offset..offset + remaining]);
         }
     }
+
+    slots
 }
 
-#[derive(Eq, PartialEq, Hash, Debug)]
-pub struct Position {
-    pub x: u8,
-    pub y: u8,
+/// Keys of the index page that can link to pages, which is all of them except for the Back key of
+/// a nested profile
+fn index_cells(options: &ProfileOptions) -> Vec<Position> {
+    let back = options.nav_layout.back_position(&options.model);
+    grid_cells(&options.model)
+        .into_iter()
+        .filter(|pos| options.parent_profile_uuid.is_none() || *pos != back)
+        .collect()
 }
 
-impl Position {
-    pub fn new(x: u8, y: u8) -> Self {
-        Self { x, y }
+/// Works out how many pages `emote_count` emotes need and how they're filled, the same way that
+/// [`ProfilesWithImages::from_images`] lays them out
+pub fn plan_layout(emote_count: usize, options: &ProfileOptions) -> LayoutPlan {
+    let model = &options.model;
+    let nav_layout = options.nav_layout;
+    let cells = grid_cells(model);
+
+    // A single page without a child only needs a Back key, and only if it's nested in a parent
+    let is_back = |pos: &Position| {
+        options.parent_profile_uuid.is_some()
+            && !options.no_navigation
+            && *pos == nav_layout.back_position(model)
+    };
+    let (width, _) = model.size();
+    let index = |pos: &Position| pos.y as usize * width as usize + pos.x as usize;
+    let skipped = match options.start_position {
+        Some(start) => cells
+            .iter()
+            .filter(|pos| index(pos) < index(&start))
+            .copied()
+            .collect(),
+        None => Vec::new(),
+    };
+
+    let fits_on_one_page = emote_count
+        <= cells
+            .iter()
+            .filter(|pos| !is_back(pos) && !skipped.contains(pos))
+            .count();
+    let single_page = options.no_navigation || (options.compact && fits_on_one_page);
+    let reserved = cells
+        .iter()
+        .filter(|pos| {
+            if single_page {
+                is_back(pos)
+            } else {
+                nav_layout.is_reserved(pos, model)
+            }
+        })
+        .copied()
+        .collect::<Vec<_>>();
+
+    let per_page = (cells.len() - reserved.len()).max(1);
+    let first_page = cells
+        .iter()
+        .filter(|pos| !reserved.contains(pos) && !skipped.contains(pos))
+        .count();
+
+    // The first page can hold fewer emotes than the others, or even none. Without navigation,
+    // the emotes that don't fit on it are left out.
+    let mut emotes_per_page = Vec::new();
+    let mut remaining = emote_count;
+    if options.no_navigation {
+        remaining = remaining.min(first_page);
+    }
+    while remaining > 0 {
+        let capacity = if emotes_per_page.is_empty() {
+            first_page
+        } else {
+            per_page
+        };
+        emotes_per_page.push(capacity.min(remaining));
+        remaining -= capacity.min(remaining);
     }
-}
 
-impl fmt::Display for Position {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
-        write!(f, "{},{}", self.x, self.y)
+    let pages = emotes_per_page.len();
+    let nav_style = match options.nav_style {
+        NavStyle::Index if pages > 1 && pages <= index_cells(options).len() => NavStyle::Index,
+        _ => NavStyle::Chain,
+    };
+
+    LayoutPlan {
+        emotes_per_page,
+        reserved,
+        skipped,
+        nav_style,
     }
 }
 
-impl Serialize for Position {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        serializer.collect_str(self)
-    }
+pub struct ProfilesWithImages {
+    pub manifests: Vec<(Uuid, ProfileManifest)>,
+    /// Pasted texts shared by more than one emote, see [`duplicate_pasted_texts`]
+    pub duplicate_pasted_texts: Vec<(String, Vec<String>)>,
+    /// Existing profile that the root profile is nested in, as a folder
+    pub parent_profile_uuid: Option<Uuid>,
+    /// How the pages link to each other, see [`ProfilesWithImages::parent_page`]
+    pub nav_style: NavStyle,
+    /// Total size of the downloaded images, before any processing
+    pub downloaded_bytes: u64,
+    /// Name that the UUIDs of the pages after the first are derived from, see [`uuid_v5`]
+    pub uuid_seed: String,
+    /// Folders of emotes opened from the root page, with [`ProfileOptions::categorize`]
+    pub categories: Vec<CategoryPages>,
+    /// Categories that were left out because there weren't enough keys on the root page
+    pub dropped_categories: Vec<String>,
+    /// Emotes left out because their images couldn't be downloaded, with
+    /// [`ProfileOptions::skip_failed_downloads`]
+    pub failed_downloads: Vec<FailedDownload>,
 }
 
-#[derive(Serialize)]
-#[serde(rename_all = "PascalCase")]
-pub struct Action {
-    pub state: u8,
-    pub states: Vec<State>,
+/// An emote whose image couldn't be downloaded
+#[derive(Clone, Debug)]
+pub struct FailedDownload {
+    pub emote: Emote,
+    pub error: String,
+}
+
+/// The pages of one category's folder, which are laid out like a profile of their own
+#[derive(Debug, PartialEq)]
+pub struct CategoryPages {
     pub name: String,
-    #[serde(flatten)]
-    pub settings: Settings,
-    #[serde(skip_serializing)]
-    pub image: Option<Bytes>,
+    /// Index of the category's first page in `manifests`
+    pub first_page: usize,
+    /// Number of pages in the category, including its index page
+    pub pages: usize,
+    /// How the category's pages link to each other
+    pub nav_style: NavStyle,
+    /// Name that the UUIDs of the category's pages are derived from, see [`uuid_v5`]
+    pub uuid_seed: String,
 }
 
-#[derive(Serialize, Debug)]
-#[serde(tag = "UUID", content = "Settings", rename_all = "PascalCase")]
-pub enum Settings {
-    #[serde(rename = "com.elgato.streamdeck.profile.backtoparent")]
-    BackToParent {},
-    #[serde(rename = "com.elgato.streamdeck.profile.openchild")]
-    OpenChild {
-        #[serde(rename = "ProfileUUID", serialize_with = "uuid_uppercase")]
-        profile_uuid: Uuid,
-    },
-    #[serde(rename = "com.elgato.streamdeck.system.text", rename_all = "camelCase")]
-    Text {
-        is_sending_enter: bool,
-        pasted_text: String,
-    },
+/// Folder for emotes that aren't assigned a category
+pub const DEFAULT_CATEGORY: &str = "Other";
+
+/// Options that control how profiles are generated from a list of emotes
+#[derive(Clone)]
+pub struct ProfileOptions {
+    pub root_profile_uuid: Uuid,
+    pub parent_profile_uuid: Option<Uuid>,
+    pub model: DeviceModel,
+    pub device_uuid: String,
+    pub name: String,
+    /// Name that the UUIDs of pages are derived from. Usually the same as `name`.
+    pub uuid_seed: String,
+    /// How each emote's key is generated
+    pub action: ActionOptions,
+    pub nav_layout: NavLayout,
+    pub nav_style: NavStyle,
+    pub image: ImageOptions,
+    /// Title colors keyed by tier name. Emotes in other tiers keep the default color.
+    pub tier_colors: Vec<TierColor>,
+    /// Draw a badge on the images of emotes with a known tier, in the tier's color from
+    /// `tier_colors`, or a default color for each tier
+    pub tier_badges: Option<BadgeStyle>,
+    /// Order of emotes after downloading their images
+    pub sort: EmoteSort,
+    /// Name for pages other than the root, where `{name}` is replaced with the profile name and
+    /// `{page}` with the page number. Pages use the profile name if unset.
+    pub page_name_template: Option<String>,
+    /// Use the navigation keys for emotes too, if that makes the emotes fit on a single page
+    pub compact: bool,
+    /// Drop emotes whose image has the same pixels as an earlier emote's
+    pub dedupe_by_image: bool,
+    /// Keys that open a website instead of pasting text
+    pub extra_actions: Vec<ExtraAction>,
+    /// Directory to save the downloaded images to, before they're processed
+    pub save_originals: Option<PathBuf>,
+    /// Drop emotes whose downloaded image is smaller than this many pixels in either dimension
+    pub min_source_resolution: Option<u32>,
+    /// Center the emotes in the last row of the last page, instead of aligning them to the left
+    pub center_last_page: bool,
+    /// Key of the first page of emotes to place the first emote on. Keys before it are left empty.
+    pub start_position: Option<Position>,
+    /// Put each [`Emote::category`] in its own folder, opened from a root page with a key for each
+    /// category. Emotes without a category go in the [`DEFAULT_CATEGORY`] folder.
+    pub categorize: bool,
+    /// Put the emotes on a single page without any navigation keys, leaving out the emotes that
+    /// don't fit
+    pub no_navigation: bool,
+    /// Drop emotes with an animated image, instead of using its first frame
+    pub no_animated: bool,
+    /// Leave out emotes whose images fail to download, listing them in
+    /// [`ProfilesWithImages::failed_downloads`], instead of failing
+    pub skip_failed_downloads: bool,
 }
 
-fn uuid_uppercase<S>(uuid: &Uuid, serializer: S) -> Result<S::Ok, S::Error>
-where
-    S: Serializer,
-{
-    serializer.serialize_str(&uuid.to_string().to_uppercase())
+/// Options for the text pasted by an emote's key, and its label, see [`Emote::to_action`]
+#[derive(Clone, Debug)]
+pub struct ActionOptions {
+    pub prefix: String,
+    /// Whether emote codes start with an underscore, as on YouTube
+    pub underscore: bool,
+    pub name_case: NameCase,
+    pub include_label: bool,
+    /// Font size of the emote labels, instead of the default
+    pub label_size: Option<LabelSize>,
+    /// Maximum number of characters in an emote label, see [`fit_label`]
+    pub label_max_chars: Option<usize>,
+    /// Wrap emote labels onto multiple lines instead of truncating them
+    pub label_wrap: bool,
+    /// Pasted text with `{prefix}`, `{name}` (after `name_case`), and `{Name}` (capitalized)
+    /// replaced, instead of the emote's code. Anything else in it is pasted as is.
+    pub text_template: Option<String>,
 }
 
-#[derive(Serialize)]
-#[serde(rename_all = "PascalCase")]
-pub struct State {
-    pub f_family: String,
-    pub f_size: String,
-    pub f_style: String,
-    pub f_underline: String,
-    pub image: String,
-    pub title: String,
-    pub title_alignment: String,
-    pub title_color: String,
-    pub title_show: String,
+impl Default for ActionOptions {
+    /// Pastes the emote's code as on YouTube, without a prefix or label
+    fn default() -> Self {
+        Self {
+            prefix: "".into(),
+            underscore: true,
+            name_case: NameCase::AsIs,
+            include_label: false,
+            label_size: None,
+            label_max_chars: None,
+            label_wrap: false,
+            text_template: None,
+        }
+    }
 }
 
-impl State {
-    fn new_image() -> Self {
+/// Emote images as downloaded and processed by [`download_images`], before they're laid out
+pub struct DownloadedImages {
+    pub images: Vec<EmoteImage>,
+    /// Total size of the downloaded images, before any processing
+    pub downloaded_bytes: u64,
+    /// Emotes that were skipped, with [`ProfileOptions::skip_failed_downloads`]
+    pub failed: Vec<FailedDownload>,
+}
+
+/// An emote image as downloaded and processed by [`download_images`], before it's placed in the
+/// profile
+struct Download {
+    image: EmoteImage,
+    /// Image as downloaded, before processing
+    original: Bytes,
+    downloaded_bytes: u64,
+    /// Width and height of the original image, if they could be read
+    dimensions: Option<(u32, u32)>,
+    /// Hash of the pixels, with `--dedupe-by-image`
+    hash: Option<[u8; 32]>,
+    /// Whether the original image has more than one frame, checked with `--no-animated`
+    animated: bool,
+}
+
+/// Writes each emote's downloaded image to the directory, named after the emote. Names that would
+/// be the same file after sanitizing get a numbered suffix.
+fn save_originals<'a>(
+    dir: &Path,
+    originals: impl Iterator<Item = (&'a str, &'a Bytes)>,
+) -> Result<()> {
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create directory {:?}", dir))?;
+
+    let mut used = HashSet::new();
+    let mut count = 0;
+    for (name, bytes) in originals {
+        let base = sanitize_name(name);
+        let extension = image_extension(bytes);
+        let file_name = (1..)
+            .map(|i| match i {
+                1 => format!("{}.{}", base, extension),
+                i => format!("{}_{}.{}", base, i, extension),
+            })
+            .find(|file_name| used.insert(file_name.to_lowercase()))
+            .expect("ran out of file names");
+
+        let path = dir.join(file_name);
+        fs::write(&path, bytes).with_context(|| format!("Failed to write image {:?}", path))?;
+        count += 1;
+    }
+
+    info!(?dir, count, "Saved original images");
+    Ok(())
+}
+
+/// Downloads and processes the emote images, leaving out the ones that the options filter out (e.g.
+/// duplicates with `dedupe_by_image`), in the order that they'll be laid out in
+pub async fn download_images(
+    fetcher: &impl ImageFetcher,
+    emotes: Vec<Emote>,
+    options: &ProfileOptions,
+) -> Result<DownloadedImages> {
+    let mut tiers = Vec::new();
+    for tier in emotes.iter().filter_map(|emote| emote.tier.as_ref()) {
+        if !tiers.contains(tier) {
+            tiers.push(tier.clone());
+        }
+    }
+    let tiers = &tiers;
+
+    let image_futures = emotes.into_iter().map(|emote| async move {
+        info!(name = %emote.name, url = %emote.url, "Downloading image");
+        let bytes = match fetcher.fetch(&emote.url).await {
+            Ok(bytes) => bytes,
+            Err(e) if options.skip_failed_downloads => {
+                warn!(
+                    name = %emote.name,
+                    url = %emote.url,
+                    error = %e,
+                    "Skipping emote whose image failed to download"
+                );
+                let error = e.to_string();
+                return Ok(Err(FailedDownload { emote, error }));
+            }
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!("Failed to load emote {} from URL {}", emote.name, emote.url)
+                })
+            }
+        };
+        let downloaded_bytes = bytes.len() as u64;
+        let dimensions = image_dimensions(&bytes);
+        let hash = if options.dedupe_by_image {
+            let bytes = bytes.clone();
+            let hash = tokio::task::spawn_blocking(move || pixel_hash(&bytes))
+                .await
+                .context("Image hashing task failed")?;
+            Some(hash)
+        } else {
+            None
+        };
+        let animated = if options.no_animated {
+            let bytes = bytes.clone();
+            tokio::task::spawn_blocking(move || is_animated(&bytes))
+                .await
+                .context("Animation detection task failed")?
+        } else {
+            false
+        };
+        let original = bytes.clone();
+        let image_options = image_options(options, tiers, &emote);
+        let processed = process_image_blocking(bytes, &image_options)
+            .await
+            .with_context(|| {
+                format!(
+                    "Failed to process image of emote {} from URL {}",
+                    emote.name, emote.url
+                )
+            })?;
+
+        let image = EmoteImage {
+            emote,
+            bytes: processed.bytes,
+            dimmed_bytes: processed.dimmed,
+            format: processed.format,
+        };
+        Ok(Ok(Download {
+            image,
+            original,
+            downloaded_bytes,
+            dimensions,
+            hash,
+            animated,
+        }))
+    });
+
+    // Each download's error names its emote and URL, so the first one is returned as is
+    let results = futures::future::join_all(image_futures)
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>>>()?;
+    let mut downloads = Vec::new();
+    let mut failed = Vec::new();
+    for result in results {
+        match result {
+            Ok(download) => downloads.push(download),
+            Err(failure) => failed.push(failure),
+        }
+    }
+
+    if let Some(dir) = &options.save_originals {
+        let originals = downloads
+            .iter()
+            .map(|download| (download.image.emote.name.as_str(), &download.original));
+        save_originals(dir, originals)?;
+    }
+
+    let downloaded_bytes = downloads
+        .iter()
+        .map(|download| download.downloaded_bytes)
+        .sum::<u64>();
+    let average_bytes = downloaded_bytes
+        .checked_div(downloads.len() as u64)
+        .unwrap_or(0);
+    info!(
+        total_bytes = downloaded_bytes,
+        average_bytes,
+        count = downloads.len(),
+        "Downloaded images"
+    );
+
+    // Images whose size can't be read are kept, since they were processed successfully
+    if let Some(min) = options.min_source_resolution {
+        downloads.retain(|download| match download.dimensions {
+            Some((width, height)) if width.min(height) < min => {
+                warn!(
+                    name = %download.image.emote.name,
+                    width,
+                    height,
+                    min_source_resolution = min,
+                    "Skipping emote with a low resolution image"
+                );
+                false
+            }
+            _ => true,
+        });
+    }
+
+    downloads.retain(|download| {
+        if download.animated {
+            warn!(
+                name = %download.image.emote.name,
+                "Skipping emote with an animated image"
+            );
+        }
+        !download.animated
+    });
+
+    // Keep the first emote with each image, in the order from the page (before `--sort`)
+    let mut seen = HashMap::new();
+    downloads.retain(|download| {
+        if let Some(hash) = download.hash {
+            let name = &download.image.emote.name;
+            if let Some(first) = seen.get(&hash) {
+                info!(
+                    %name,
+                    duplicate_of = %first,
+                    "Skipping emote with the same image as another"
+                );
+                return false;
+            }
+            seen.insert(hash, name.clone());
+        }
+        true
+    });
+
+    if options.sort == EmoteSort::BySize {
+        // Largest first, by pixel count and then file size. The sort is stable, so images of
+        // the same size keep their original order.
+        downloads.sort_by_key(|download| {
+            let pixels = download.dimensions.map_or(0, |(w, h)| w as u64 * h as u64);
+            std::cmp::Reverse((pixels, download.downloaded_bytes))
+        });
+    }
+
+    let images = downloads
+        .into_iter()
+        .map(|download| download.image)
+        .collect();
+
+    Ok(DownloadedImages {
+        images,
+        downloaded_bytes,
+        failed,
+    })
+}
+
+impl ProfilesWithImages {
+    pub async fn new(
+        fetcher: &impl ImageFetcher,
+        emotes: Vec<Emote>,
+        options: &ProfileOptions,
+    ) -> Result<Self> {
+        let downloaded = download_images(fetcher, emotes, options).await?;
+        Ok(Self {
+            downloaded_bytes: downloaded.downloaded_bytes,
+            failed_downloads: downloaded.failed,
+            ..Self::from_images(downloaded.images, options)
+        })
+    }
+
+    /// Lays out already-downloaded emote images into pages, adding navigation between them
+    pub fn from_images(images: Vec<EmoteImage>, options: &ProfileOptions) -> Self {
+        let (mut manifests, nav_style, categories, dropped_categories) = if options.categorize {
+            let (manifests, categories, dropped) = Self::category_pages(images, options);
+            (manifests, NavStyle::Index, categories, dropped)
+        } else {
+            let (manifests, nav_style) = Self::layout_pages(&images, options);
+            (manifests, nav_style, Vec::new(), Vec::new())
+        };
+
+        Self::apply_extra_actions(&mut manifests, options);
+
+        let actions = manifests
+            .iter()
+            .flat_map(|(_, manifest)| manifest.actions.values());
+        let duplicate_pasted_texts = duplicate_pasted_texts(actions);
+        for (pasted_text, names) in duplicate_pasted_texts.iter() {
+            warn!(%pasted_text, ?names, "Multiple emotes have the same pasted text");
+        }
+
         Self {
-            image: "state0.png".into(),
-            ..Default::default()
+            manifests,
+            duplicate_pasted_texts,
+            parent_profile_uuid: options.parent_profile_uuid,
+            nav_style,
+            downloaded_bytes: 0,
+            uuid_seed: options.uuid_seed.clone(),
+            categories,
+            dropped_categories,
+            failed_downloads: Vec::new(),
+        }
+    }
+
+    /// Lays out the emotes into pages with navigation between them, returning the pages and how
+    /// they're linked
+    fn layout_pages(
+        images: &[EmoteImage],
+        options: &ProfileOptions,
+    ) -> (Vec<(Uuid, ProfileManifest)>, NavStyle) {
+        let model = &options.model;
+        let name = &options.name;
+        let nav_layout = options.nav_layout;
+        let cells = grid_cells(model);
+        let plan = plan_layout(images.len(), options);
+
+        let placed = plan.emotes_per_page.iter().sum::<usize>();
+        if images.len() > placed {
+            let dropped = images[placed..]
+                .iter()
+                .map(|image| image.emote.name.as_str())
+                .collect::<Vec<_>>();
+            warn!(
+                count = dropped.len(),
+                names = ?dropped,
+                "Leaving out emotes that don't fit on a single page without navigation"
+            );
+        }
+
+        let mut manifests = Vec::new();
+        let mut images = images.iter();
+
+        let free_cells = cells
+            .iter()
+            .filter(|pos| !plan.reserved.contains(pos))
+            .copied()
+            .collect::<Vec<_>>();
+
+        for (page, page_len) in plan.emotes_per_page.iter().enumerate() {
+            let mut images = images.by_ref().take(*page_len);
+            let is_last_page = page + 1 == plan.emotes_per_page.len();
+            let free_cells = match page {
+                0 => free_cells
+                    .iter()
+                    .filter(|pos| !plan.skipped.contains(pos))
+                    .copied()
+                    .collect(),
+                _ => free_cells.clone(),
+            };
+            let slots = if is_last_page && options.center_last_page {
+                center_last_row(&free_cells, *page_len)
+            } else {
+                free_cells.iter().take(*page_len).copied().collect()
+            };
+
+            let manifest_actions = cells
+                .iter()
+                .map(|pos| {
+                    if !slots.contains(pos) {
+                        return None;
+                    }
+
+                    images.next().map(|image| {
+                        let mut action = image
+                            .emote
+                            .to_action(&options.action, Some(image.bytes.clone()));
+
+                        // A color from the overrides file takes precedence
+                        let tier = image
+                            .emote
+                            .tier
+                            .as_ref()
+                            .filter(|_| image.emote.title_color.is_none());
+                        let tier_color = tier.and_then(|tier| {
+                            options
+                                .tier_colors
+                                .iter()
+                                .find(|c| c.tier.eq_ignore_ascii_case(tier))
+                        });
+                        if let Some(tier_color) = tier_color {
+                            action.states[0].title_color = tier_color.color.clone();
+                        }
+                        action.states[0].image = image.format.state_image_name().into();
+
+                        match &image.dimmed_bytes {
+                            Some(dimmed) => action.with_dimmed_state(dimmed.clone()),
+                            None => action,
+                        }
+                    })
+                })
+                .collect();
+
+            let mut manifest = ProfileManifest {
+                actions: HashMap::new(),
+                device_model: model.clone(),
+                device_uuid: options.device_uuid.clone(),
+                name: name.clone(),
+                version: MANIFEST_VERSION.to_owned(),
+            };
+
+            manifest.set_actions(manifest_actions);
+
+            let manifest_uuid = if manifests.is_empty() {
+                options.root_profile_uuid
+            } else {
+                uuid_v5(&options.uuid_seed, manifests.len())
+            };
+
+            manifests.push((manifest_uuid, manifest));
+        }
+
+        let index_cells = index_cells(options);
+
+        let nav_style = plan.nav_style;
+        if options.nav_style == NavStyle::Index && manifests.len() > index_cells.len() {
+            warn!(
+                pages = manifests.len(),
+                keys = index_cells.len(),
+                "Too many pages to fit on an index page, linking pages in a chain instead"
+            );
+        }
+
+        // The index page goes first, with every page of emotes as a child of it
+        if nav_style == NavStyle::Index {
+            let mut index_manifest = ProfileManifest {
+                actions: HashMap::new(),
+                device_model: model.clone(),
+                device_uuid: options.device_uuid.clone(),
+                name: name.clone(),
+                version: MANIFEST_VERSION.to_owned(),
+            };
+
+            for (page, (uuid, _)) in manifests.iter_mut().enumerate() {
+                *uuid = uuid_v5(&options.uuid_seed, page + 1);
+                let action = Action::open_child(&format!("Page {}", page + 1), *uuid, None);
+                index_manifest.actions.insert(index_cells[page], action);
+            }
+
+            manifests.insert(0, (options.root_profile_uuid, index_manifest));
+        }
+
+        // Pages link back to their parent, including the root page if it's nested in another profile
+        let skip = if options.parent_profile_uuid.is_some() && !options.no_navigation {
+            0
+        } else {
+            1
+        };
+
+        for (index, (_, manifest)) in manifests.iter_mut().enumerate().skip(skip) {
+            manifest
+                .actions
+                .insert(nav_layout.back_position(model), Action::back_to_parent());
+
+            let home_position = nav_layout
+                .home_position(model)
+                .filter(|_| index > 0 && nav_style == NavStyle::Chain);
+            if let Some(position) = home_position {
+                let action = Action {
+                    name: "Switch Profile".into(),
+                    state: 0,
+                    states: vec![State {
+                        title: "Home".into(),
+                        ..State::default()
+                    }],
+                    settings: Settings::SwitchProfile {
+                        device_uuid: options.device_uuid.clone(),
+                        profile_uuid: options
+                            .parent_profile_uuid
+                            .unwrap_or(options.root_profile_uuid),
+                    },
+                    image: None,
+                    emote: None,
+                    dimmed_image: None,
+                };
+
+                manifest.actions.insert(position, action);
+            }
+        }
+
+        if nav_style == NavStyle::Chain {
+            let mut child_uuid: Option<Uuid> = None;
+            for (uuid, manifest) in manifests.iter_mut().rev() {
+                if let Some(child) = child_uuid {
+                    let image = include_bytes!("../images/forward.png").as_ref().into();
+                    let action = Action::open_child("Next", child, Some(image));
+
+                    manifest
+                        .actions
+                        .insert(nav_layout.next_position(model), action);
+                }
+
+                child_uuid = Some(*uuid);
+            }
+        }
+
+        if let Some(template) = &options.page_name_template {
+            // Page numbers match the keys on the index page, which isn't numbered itself
+            let first_page = if nav_style == NavStyle::Index { 0 } else { 1 };
+            for (index, (_, manifest)) in manifests.iter_mut().enumerate().skip(1) {
+                manifest.name = template
+                    .replace("{name}", name)
+                    .replace("{page}", &(index + first_page).to_string());
+            }
+        }
+
+        (manifests, nav_style)
+    }
+
+    /// Puts each category's emotes in its own folder, in the order that the categories first
+    /// appear (with [`DEFAULT_CATEGORY`] last), and adds a root page with a key for each folder
+    fn category_pages(
+        images: Vec<EmoteImage>,
+        options: &ProfileOptions,
+    ) -> (
+        Vec<(Uuid, ProfileManifest)>,
+        Vec<CategoryPages>,
+        Vec<String>,
+    ) {
+        let mut groups = Vec::<(String, Vec<EmoteImage>)>::new();
+        let mut uncategorized = Vec::new();
+        for image in images {
+            match image.emote.category.clone() {
+                Some(category) => match groups.iter_mut().find(|(name, _)| *name == category) {
+                    Some((_, group)) => group.push(image),
+                    None => groups.push((category, vec![image])),
+                },
+                None => uncategorized.push(image),
+            }
+        }
+        if !uncategorized.is_empty() {
+            match groups.iter_mut().find(|(name, _)| name == DEFAULT_CATEGORY) {
+                Some((_, group)) => group.extend(uncategorized),
+                None => groups.push((DEFAULT_CATEGORY.to_owned(), uncategorized)),
+            }
+        }
+
+        let index_cells = index_cells(options);
+        let dropped = groups
+            .iter()
+            .skip(index_cells.len())
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        if !dropped.is_empty() {
+            warn!(
+                categories = groups.len(),
+                keys = index_cells.len(),
+                ?dropped,
+                "Too many categories to fit on the root page, leaving out the last ones"
+            );
+        }
+
+        let mut root = ProfileManifest {
+            actions: HashMap::new(),
+            device_model: options.model.clone(),
+            device_uuid: options.device_uuid.clone(),
+            name: options.name.clone(),
+            version: MANIFEST_VERSION.to_owned(),
+        };
+        if options.parent_profile_uuid.is_some() {
+            let position = options.nav_layout.back_position(&options.model);
+            root.actions.insert(position, Action::back_to_parent());
+        }
+
+        let mut manifests = Vec::new();
+        let mut categories = Vec::new();
+        for ((category, images), position) in groups.into_iter().zip(index_cells) {
+            // Each folder is laid out like a profile nested in the root page
+            let uuid_seed = format!("{}/{}", options.uuid_seed, category);
+            let category_options = ProfileOptions {
+                root_profile_uuid: uuid_v5(&uuid_seed, 0),
+                parent_profile_uuid: Some(options.root_profile_uuid),
+                name: category.clone(),
+                uuid_seed,
+                extra_actions: Vec::new(),
+                start_position: None,
+                categorize: false,
+                no_navigation: false,
+                ..options.clone()
+            };
+            let (pages, nav_style) = Self::layout_pages(&images, &category_options);
+
+            let action = Action::open_child(&category, category_options.root_profile_uuid, None);
+            root.actions.insert(position, action);
+
+            categories.push(CategoryPages {
+                name: category,
+                first_page: manifests.len() + 1,
+                pages: pages.len(),
+                nav_style,
+                uuid_seed: category_options.uuid_seed,
+            });
+            manifests.extend(pages);
+        }
+
+        manifests.insert(0, (options.root_profile_uuid, root));
+        (manifests, categories, dropped)
+    }
+
+    /// Adds the keys for `--extra-action`, either replacing an emote's key on any page or using an
+    /// empty key of the first page
+    fn apply_extra_actions(manifests: &mut [(Uuid, ProfileManifest)], options: &ProfileOptions) {
+        let model = &options.model;
+        for extra in options.extra_actions.iter() {
+            match &extra.target {
+                ExtraActionTarget::Emote(name) => {
+                    let actions = manifests
+                        .iter_mut()
+                        .flat_map(|(_, manifest)| manifest.actions.values_mut())
+                        .filter(|action| {
+                            action
+                                .emote
+                                .as_ref()
+                                .is_some_and(|emote| emote.name.eq_ignore_ascii_case(name))
+                        })
+                        .collect::<Vec<_>>();
+                    if actions.is_empty() {
+                        warn!(%name, url = %extra.url, "Extra action doesn't match any emote");
+                    }
+                    for action in actions {
+                        action.name = "Website".into();
+                        action.settings = extra.settings();
+                    }
+                }
+                ExtraActionTarget::Position(position) => {
+                    let (width, height) = model.size();
+                    let first_page = manifests.first_mut().map(|(_, manifest)| manifest);
+                    match first_page {
+                        _ if position.x >= width || position.y >= height => {
+                            warn!(%position, "Extra action is outside of the device's keys")
+                        }
+                        Some(manifest) if !manifest.actions.contains_key(position) => {
+                            let action = Action {
+                                name: "Website".into(),
+                                state: 0,
+                                states: vec![State::default()],
+                                settings: extra.settings(),
+                                image: None,
+                                emote: None,
+                                dimmed_image: None,
+                            };
+                            manifest.actions.insert(*position, action);
+                        }
+                        Some(_) => warn!(%position, "Extra action's key is already used"),
+                        None => warn!(%position, "Extra action has no page to go on"),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Index of the page that contains the folder key for the given page, if any
+    pub fn parent_page(&self, page: usize) -> Option<usize> {
+        let category = self
+            .categories
+            .iter()
+            .find(|c| (c.first_page..c.first_page + c.pages).contains(&page));
+        if let Some(category) = category {
+            return match (page - category.first_page, category.nav_style) {
+                (0, _) => Some(0),
+                (_, NavStyle::Chain) => Some(page - 1),
+                (_, NavStyle::Index) => Some(category.first_page),
+            };
+        }
+
+        match (page, self.nav_style) {
+            (0, _) => None,
+            (_, NavStyle::Chain) => Some(page - 1),
+            (_, NavStyle::Index) => Some(0),
+        }
+    }
+}
+
+/// How emotes are ordered once their images are downloaded
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EmoteSort {
+    /// Keep the order from the memberships page (after `--prioritize` and `--deprioritize`)
+    Original,
+    /// Largest images first, by pixel dimensions and then file size
+    BySize,
+}
+
+impl FromStr for EmoteSort {
+    type Err = color_eyre::eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_ref() {
+            "original" => Ok(EmoteSort::Original),
+            "by-size" => Ok(EmoteSort::BySize),
+            other => bail!("Unknown sort order {}", other),
+        }
+    }
+}
+
+/// How pages are linked together
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NavStyle {
+    /// Each page has a Next key that opens the following page, nested one level deeper
+    Chain,
+    /// The first page is an index with a key for each page of emotes
+    Index,
+}
+
+impl FromStr for NavStyle {
+    type Err = color_eyre::eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_ref() {
+            "chain" => Ok(NavStyle::Chain),
+            "index" => Ok(NavStyle::Index),
+            other => bail!("Unknown navigation style {}", other),
+        }
+    }
+}
+
+/// Determines which keys are reserved for navigating between pages
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NavLayout {
+    /// The leftmost column is reserved, with Back and Next keys in its corners
+    Minimal,
+    /// The bottom row is reserved, with Back, Home, and Next keys
+    Row,
+}
+
+impl FromStr for NavLayout {
+    type Err = color_eyre::eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_ref() {
+            "minimal" => Ok(NavLayout::Minimal),
+            "row" => Ok(NavLayout::Row),
+            other => bail!("Unknown navigation layout {}", other),
+        }
+    }
+}
+
+impl NavLayout {
+    pub fn is_reserved(&self, pos: &Position, model: &DeviceModel) -> bool {
+        let (_width, height) = model.size();
+        match self {
+            Self::Minimal => pos.x == 0,
+            Self::Row => pos.y == height - 1,
         }
     }
-}
 
-impl Default for State {
-    fn default() -> Self {
-        Self {
-            f_family: "".into(),
-            f_size: "12".into(),
-            f_style: "".into(),
-            f_underline: "off".into(),
-            image: "".into(),
-            title: "".into(),
-            title_alignment: "bottom".into(),
-            title_color: "#fbfcff".into(),
-            title_show: "".into(),
+    pub fn back_position(&self, model: &DeviceModel) -> Position {
+        let (_width, height) = model.size();
+        match self {
+            Self::Minimal => Position::new(0, 0),
+            Self::Row => Position::new(0, height - 1),
+        }
+    }
+
+    pub fn home_position(&self, model: &DeviceModel) -> Option<Position> {
+        let (width, height) = model.size();
+        match self {
+            Self::Minimal => None,
+            Self::Row => Some(Position::new(width / 2, height - 1)),
+        }
+    }
+
+    pub fn next_position(&self, model: &DeviceModel) -> Position {
+        let (width, height) = model.size();
+        match self {
+            Self::Minimal => Position::new(0, height - 1),
+            Self::Row => Position::new(width - 1, height - 1),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ProfileManifest {
+    pub actions: HashMap<Position, Action>,
+    pub device_model: DeviceModel,
+    #[serde(rename = "DeviceUUID")]
+    pub device_uuid: String, // e.g., `@(1)[4057/128/DL16K1A70561]`
+    pub name: String,
+    pub version: String, // `1.0`
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DeviceModel {
+    Standard,
+    XL,
+    Mini,
+    /// 8 keys, plus two touch buttons and an info strip that aren't used
+    Neo,
+}
+
+impl FromStr for DeviceModel {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s.to_lowercase().as_ref() {
+            "standard" => Ok(DeviceModel::Standard),
+            "xl" => Ok(DeviceModel::XL),
+            "mini" => Ok(DeviceModel::Mini),
+            "neo" => Ok(DeviceModel::Neo),
+            other => Err(Error::InvalidDeviceModel(other.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for DeviceModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Standard => "standard",
+            Self::XL => "xl",
+            Self::Mini => "mini",
+            Self::Neo => "neo",
+        })
+    }
+}
+
+impl DeviceModel {
+    pub fn id(&self) -> &'static str {
+        match self {
+            Self::Standard => "20GBA9901",
+            Self::XL => "20GAT9901",
+            Self::Mini => "unknown", // TODO: Find correct value
+            Self::Neo => "20GBJ9901",
+        }
+    }
+
+    /// Reverse of [`DeviceModel::id`], for known model IDs
+    pub fn from_model_id(id: &str) -> Option<Self> {
+        match id {
+            "20GBA9901" => Some(Self::Standard),
+            "20GAT9901" => Some(Self::XL),
+            "20GBJ9901" => Some(Self::Neo),
+            _ => None, // The ID for the Mini isn't known yet
+        }
+    }
+
+    /// Size of a key image in pixels
+    pub fn key_size(&self) -> u32 {
+        match self {
+            Self::Standard => 72,
+            Self::XL => 96,
+            Self::Mini => 80,
+            Self::Neo => 96,
+        }
+    }
+
+    pub fn size(&self) -> (u8, u8) {
+        match self {
+            Self::Standard => (5, 3),
+            Self::XL => (8, 4),
+            Self::Mini => (3, 2),
+            Self::Neo => (4, 2),
+        }
+    }
+}
+impl Serialize for DeviceModel {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.id())
+    }
+}
+
+impl ProfileManifest {
+    pub fn set_actions(&mut self, actions: Vec<Option<Action>>) {
+        let (width, _height) = self.device_model.size();
+
+        for (index, action) in actions.into_iter().enumerate() {
+            let index = index as u8;
+            let pos = Position::new(index % width, index / width);
+
+            if let Some(action) = action {
+                self.actions.insert(pos, action);
+            } else {
+                self.actions.remove(&pos);
+            }
+        }
+    }
+}
+
+#[derive(Eq, PartialEq, Hash, Debug, Clone, Copy)]
+pub struct Position {
+    pub x: u8,
+    pub y: u8,
+}
+
+impl Position {
+    pub fn new(x: u8, y: u8) -> Self {
+        Self { x, y }
+    }
+}
+
+impl FromStr for Position {
+    type Err = color_eyre::eyre::Error;
+
+    /// Parses a position in the form `x,y`, where `0,0` is the top left key
+    fn from_str(s: &str) -> Result<Self> {
+        let position = s.split_once(',').and_then(|(x, y)| {
+            Some(Position::new(
+                x.trim().parse().ok()?,
+                y.trim().parse().ok()?,
+            ))
+        });
+        match position {
+            Some(position) => Ok(position),
+            None => bail!("Expected a position in the form X,Y, got {:?}", s),
+        }
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(f, "{},{}", self.x, self.y)
+    }
+}
+
+impl Serialize for Position {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Action {
+    pub state: u8,
+    pub states: Vec<State>,
+    pub name: String,
+    #[serde(flatten)]
+    pub settings: Settings,
+    #[serde(skip_serializing)]
+    pub image: Option<Bytes>,
+    /// The emote this action was generated from, if any
+    #[serde(skip_serializing)]
+    pub emote: Option<Emote>,
+    /// Image for the second state, which the key switches to and from each time it's pressed
+    #[serde(skip_serializing)]
+    pub dimmed_image: Option<Bytes>,
+}
+
+impl Action {
+    /// Adds a second state using the given image. The Stream Deck application toggles between the
+    /// states on every press, rather than showing the second one only while the key is held.
+    pub fn with_dimmed_state(mut self, image: Bytes) -> Self {
+        let mut state = self.states.first().cloned().unwrap_or_default();
+        state.image = "state1.png".into();
+        self.states.push(state);
+        self.dimmed_image = Some(image);
+        self
+    }
+
+    /// Key that goes back to the parent profile
+    pub fn back_to_parent() -> Self {
+        Action {
+            name: "Open Folder".into(),
+            state: 0,
+            states: vec![State {
+                title: "Back".into(),
+                ..State::new_image()
+            }],
+            settings: Settings::BackToParent {},
+            image: Some(include_bytes!("../images/back.png").as_ref().into()),
+            emote: None,
+            dimmed_image: None,
+        }
+    }
+
+    /// Key that opens a child profile (folder)
+    pub fn open_child(title: &str, profile_uuid: Uuid, image: Option<Bytes>) -> Self {
+        Action {
+            name: "Create Folder".into(),
+            state: 0,
+            states: vec![State {
+                title: title.into(),
+                ..State::new_image()
+            }],
+            settings: Settings::OpenChild { profile_uuid },
+            image,
+            emote: None,
+            dimmed_image: None,
+        }
+    }
+}
+
+/// `UUID`s of the kinds of actions that are generated, as opposed to ones added by the user.
+/// Website keys can be either, see [`is_generated_website`].
+pub const GENERATED_ACTION_UUIDS: &[&str] = &[
+    "com.elgato.streamdeck.profile.backtoparent",
+    "com.elgato.streamdeck.profile.openchild",
+    "com.elgato.streamdeck.profile.rotate",
+    "com.elgato.streamdeck.system.text",
+];
+
+/// `UUID` of the "Website" action, used for `--extra-action` keys
+pub const WEBSITE_ACTION_UUID: &str = "com.elgato.streamdeck.system.website";
+
+/// Whether an existing action (as JSON) at `position` was generated by this tool, rather than added
+/// or edited by the user
+pub fn is_generated_action(
+    action: &serde_json::Value,
+    position: &str,
+    extra_actions: &[ExtraAction],
+) -> bool {
+    match action.get("UUID").and_then(|uuid| uuid.as_str()) {
+        Some(WEBSITE_ACTION_UUID) => is_generated_website(action, position, extra_actions),
+        Some(uuid) => GENERATED_ACTION_UUIDS.contains(&uuid),
+        None => false,
+    }
+}
+
+/// Whether an existing website key (as JSON) at `position` came from one of the extra actions,
+/// because it opens one of their URLs or is at the position of one. Other website keys were added
+/// by the user.
+pub fn is_generated_website(
+    action: &serde_json::Value,
+    position: &str,
+    extra_actions: &[ExtraAction],
+) -> bool {
+    let url = action
+        .pointer("/Settings/path")
+        .and_then(|path| path.as_str());
+    extra_actions.iter().any(|extra| {
+        url == Some(extra.url.as_str())
+            || matches!(&extra.target, ExtraActionTarget::Position(pos) if pos.to_string() == position)
+    })
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "UUID", content = "Settings", rename_all = "PascalCase")]
+pub enum Settings {
+    #[serde(rename = "com.elgato.streamdeck.profile.backtoparent")]
+    BackToParent {},
+    #[serde(rename = "com.elgato.streamdeck.profile.openchild")]
+    OpenChild {
+        #[serde(rename = "ProfileUUID", serialize_with = "uuid_uppercase")]
+        profile_uuid: Uuid,
+    },
+    #[serde(rename = "com.elgato.streamdeck.profile.rotate")]
+    SwitchProfile {
+        #[serde(rename = "DeviceUUID")]
+        device_uuid: String,
+        #[serde(rename = "ProfileUUID", serialize_with = "uuid_uppercase")]
+        profile_uuid: Uuid,
+    },
+    #[serde(rename = "com.elgato.streamdeck.system.text", rename_all = "camelCase")]
+    Text {
+        is_sending_enter: bool,
+        pasted_text: String,
+    },
+    /// The "Website" action, which opens `path` in the default browser
+    #[serde(
+        rename = "com.elgato.streamdeck.system.website",
+        rename_all = "camelCase"
+    )]
+    OpenUrl { open_in_browser: bool, path: String },
+}
+
+fn uuid_uppercase<S>(uuid: &Uuid, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(&uuid.to_string().to_uppercase())
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct State {
+    pub f_family: String,
+    pub f_size: String,
+    pub f_style: String,
+    pub f_underline: String,
+    pub image: String,
+    pub title: String,
+    pub title_alignment: String,
+    pub title_color: String,
+    /// Left empty, which the Stream Deck application treats as the default of showing the title
+    /// (the labels in `docs/profile-page1.png` were generated this way). Keys without a label
+    /// have an empty `title` instead.
+    pub title_show: String,
+}
+
+impl State {
+    fn new_image() -> Self {
+        Self {
+            image: "state0.png".into(),
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            f_family: "".into(),
+            f_size: "12".into(),
+            f_style: "".into(),
+            f_underline: "off".into(),
+            image: "".into(),
+            title: "".into(),
+            title_alignment: "bottom".into(),
+            title_color: "#fbfcff".into(),
+            title_show: "".into(),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::fetch::StubFetcher;
+    use crate::processing::tests::{fixture_gif, fixture_png};
+
+    #[test]
+    fn serialize_profile() -> Result<()> {
+        let mut actions = HashMap::new();
+
+        actions.insert(
+            Position::new(0, 0),
+            Action {
+                name: "Open Folder".into(),
+                state: 0,
+                states: vec![State::default()],
+                settings: Settings::BackToParent {},
+                image: None,
+                emote: None,
+                dimmed_image: None,
+            },
+        );
+
+        actions.insert(
+            Position::new(1, 0),
+            Action {
+                name: "Text".into(),
+                state: 0,
+                states: vec![State::new_image()],
+                image: None,
+                emote: None,
+                dimmed_image: None,
+                settings: Settings::Text {
+                    is_sending_enter: false,
+                    pasted_text: ":_pomuSmall9cm:".into(),
+                },
+            },
+        );
+
+        let profile_uuid = Uuid::parse_str("AC20BCF3-0A7C-4243-BB74-5C0DC5681BA5")?;
+
+        actions.insert(
+            Position::new(4, 2),
+            Action {
+                name: "Create Folder".into(),
+                state: 0,
+                states: vec![State::default()],
+                settings: Settings::OpenChild { profile_uuid },
+                image: None,
+                emote: None,
+                dimmed_image: None,
+            },
+        );
+
+        let profile = ProfileManifest {
+            actions,
+            device_model: DeviceModel::Standard,
+            device_uuid: "@(1)[4057/128/DL16K1A71331]".into(),
+            name: "Emotes".into(),
+            version: "1.0".into(),
+        };
+
+        let json = serde_json::to_value(&profile)?;
+
+        let expected = serde_json::json!({
+          "Actions": {
+            "0,0": {
+              "State": 0,
+              "States": [
+                {
+                  "FFamily": "",
+                  "FSize": "12",
+                  "FStyle": "",
+                  "FUnderline": "off",
+                  "Image": "",
+                  "Title": "",
+                  "TitleAlignment": "bottom",
+                  "TitleColor": "#fbfcff",
+                  "TitleShow": ""
+                }
+              ],
+              "Name": "Open Folder",
+              "UUID": "com.elgato.streamdeck.profile.backtoparent",
+              "Settings": {}
+            },
+            "1,0": {
+              "State": 0,
+              "States": [
+                {
+                  "FFamily": "",
+                  "FSize": "12",
+                  "FStyle": "",
+                  "FUnderline": "off",
+                  "Image": "state0.png",
+                  "Title": "",
+                  "TitleAlignment": "bottom",
+                  "TitleColor": "#fbfcff",
+                  "TitleShow": ""
+                }
+              ],
+              "Name": "Text",
+              "UUID": "com.elgato.streamdeck.system.text",
+              "Settings": {
+                "isSendingEnter": false,
+                "pastedText": ":_pomuSmall9cm:"
+              }
+            },
+            "4,2": {
+              "State": 0,
+              "States": [
+                {
+                  "FFamily": "",
+                  "FSize": "12",
+                  "FStyle": "",
+                  "FUnderline": "off",
+                  "Image": "",
+                  "Title": "",
+                  "TitleAlignment": "bottom",
+                  "TitleColor": "#fbfcff",
+                  "TitleShow": ""
+                }
+              ],
+              "Name": "Create Folder",
+              "UUID": "com.elgato.streamdeck.profile.openchild",
+              "Settings": {
+                "ProfileUUID": "AC20BCF3-0A7C-4243-BB74-5C0DC5681BA5"
+              }
+            }
+          },
+          "DeviceModel": "20GBA9901",
+          "DeviceUUID": "@(1)[4057/128/DL16K1A71331]",
+          "Name": "Emotes",
+          "Version": "1.0"
+        });
+
+        assert_eq!(json, expected);
+
+        Ok(())
+    }
+
+    /// Emotes named `emote0`, `emote1`, etc., with placeholder images
+    pub fn emote_images(count: usize) -> Vec<EmoteImage> {
+        let emotes = (0..count)
+            .map(|i| Emote {
+                url: format!("http://example.com/{}.png", i),
+                name: format!("emote{}", i),
+                ..Default::default()
+            })
+            .collect();
+        placeholder_images(emotes)
+    }
+
+    /// Gives each emote the same placeholder image, for tests that don't look at images
+    pub fn placeholder_images(emotes: Vec<Emote>) -> Vec<EmoteImage> {
+        emotes
+            .into_iter()
+            .map(|emote| EmoteImage {
+                emote,
+                bytes: Bytes::from_static(b"image"),
+                dimmed_bytes: None,
+                format: EncodedFormat::Png,
+            })
+            .collect()
+    }
+
+    /// Emotes with the given names, each with its own URL
+    fn named_emotes(names: &[&str]) -> Vec<Emote> {
+        names
+            .iter()
+            .map(|name| Emote {
+                url: format!("http://example.com/{}.png", name),
+                name: (*name).to_owned(),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    /// Loads the emotes from a stub fetcher that serves the image paired with each emote's name.
+    /// Emotes without an image fail to download.
+    async fn profiles_from(
+        emotes: &[Emote],
+        stub: &[(&str, Bytes)],
+        options: &ProfileOptions,
+    ) -> Result<ProfilesWithImages> {
+        let fetcher = StubFetcher::new(stub.iter().filter_map(|(name, bytes)| {
+            let emote = emotes.iter().find(|emote| emote.name == *name)?;
+            Some((emote.url.as_str(), bytes.clone()))
+        }));
+        ProfilesWithImages::new(&fetcher, emotes.to_vec(), options).await
+    }
+
+    /// Names of the emotes on the first page, sorted
+    fn emote_names(profiles: &ProfilesWithImages) -> Vec<String> {
+        let mut names = profiles.manifests[0]
+            .1
+            .actions
+            .values()
+            .filter_map(|action| Some(action.emote.as_ref()?.name.clone()))
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// Positions of the emotes on the given page, in reading order
+    fn emote_positions(profiles: &ProfilesWithImages, page: usize) -> Vec<Position> {
+        let mut positions = profiles.manifests[page]
+            .1
+            .actions
+            .iter()
+            .filter(|(_, action)| action.emote.is_some())
+            .map(|(pos, _)| *pos)
+            .collect::<Vec<_>>();
+        positions.sort_by_key(|pos| (pos.y, pos.x));
+        positions
+    }
+
+    pub fn options(model: DeviceModel, nav_layout: NavLayout) -> ProfileOptions {
+        ProfileOptions {
+            root_profile_uuid: uuid_v5("Emotes", 0),
+            parent_profile_uuid: None,
+            model,
+            device_uuid: "".into(),
+            name: "Emotes".into(),
+            uuid_seed: "Emotes".into(),
+            action: ActionOptions::default(),
+            nav_layout,
+            nav_style: NavStyle::Chain,
+            image: ImageOptions::default(),
+            tier_colors: Vec::new(),
+            tier_badges: None,
+            sort: EmoteSort::Original,
+            page_name_template: None,
+            compact: false,
+            dedupe_by_image: false,
+            extra_actions: Vec::new(),
+            save_originals: None,
+            min_source_resolution: None,
+            center_last_page: false,
+            start_position: None,
+            categorize: false,
+            no_navigation: false,
+            no_animated: false,
+            skip_failed_downloads: false,
+        }
+    }
+
+    #[test]
+    fn index_nav_style() {
+        let mut options = options(DeviceModel::Standard, NavLayout::Row);
+        options.nav_style = NavStyle::Index;
+
+        // 10 emotes per page with the bottom row reserved
+        let profiles = ProfilesWithImages::from_images(emote_images(35), &options);
+        assert_eq!(profiles.nav_style, NavStyle::Index);
+        assert_eq!(profiles.manifests.len(), 5);
+
+        let linked_uuids = |manifest: &ProfileManifest| {
+            manifest
+                .actions
+                .values()
+                .filter_map(|action| match action.settings {
+                    Settings::OpenChild { profile_uuid } => Some(profile_uuid),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let (root_uuid, root) = &profiles.manifests[0];
+        assert_eq!(*root_uuid, options.root_profile_uuid);
+        assert!(root.actions.values().all(|action| action.emote.is_none()));
+
+        let mut linked = linked_uuids(root);
+        linked.sort();
+        let mut pages = profiles.manifests[1..]
+            .iter()
+            .map(|(uuid, _)| *uuid)
+            .collect::<Vec<_>>();
+        pages.sort();
+        assert_eq!(linked, pages);
+
+        for (page, (_, manifest)) in profiles.manifests.iter().enumerate().skip(1) {
+            assert!(linked_uuids(manifest).is_empty());
+            assert!(matches!(
+                manifest.actions[&Position::new(0, 2)].settings,
+                Settings::BackToParent {}
+            ));
+            assert_eq!(profiles.parent_page(page), Some(0));
+        }
+        assert_eq!(profiles.parent_page(0), None);
+
+        // Falls back to a chain if there are more pages than keys on the index page
+        let profiles = ProfilesWithImages::from_images(emote_images(200), &options);
+        assert_eq!(profiles.nav_style, NavStyle::Chain);
+        assert_eq!(profiles.parent_page(3), Some(2));
+    }
+
+    #[test]
+    fn prefix_suggestions() {
+        let names = ["pomuHi", "pomuLove", "pomu9cm", "wave"];
+        assert_eq!(suggest_prefix("rrat", names), Some("pomu".to_owned()));
+        assert_eq!(suggest_prefix("pomu", names), None);
+        assert_eq!(suggest_prefix("POMU", names), None);
+        assert_eq!(suggest_prefix("", names), None);
+
+        // Names without a family name are normal for YouTube
+        assert_eq!(
+            suggest_prefix("pomu", ["small9cm", "verycool", "ANGRY"]),
+            None
+        );
+        assert_eq!(suggest_prefix("pomu", ["fooBar", "bazQux", "eee"]), None);
+    }
+
+    #[tokio::test]
+    async fn failed_download_names_emote() {
+        let emotes = named_emotes(&["ok", "broken"]);
+        let stub = [("ok", fixture_png(8, 8))];
+
+        let options = options(DeviceModel::Standard, NavLayout::Minimal);
+        let error = match profiles_from(&emotes, &stub, &options).await {
+            Ok(_) => panic!("expected the download of the broken emote to fail"),
+            Err(e) => e,
+        };
+        assert_eq!(
+            error.to_string(),
+            "Failed to load emote broken from URL http://example.com/broken.png"
+        );
+        assert!(error.downcast_ref::<Error>().is_some(), "{:?}", error);
+    }
+
+    #[tokio::test]
+    async fn sort_by_size() -> Result<()> {
+        let sizes = [(16, 16), (64, 32), (8, 8), (30, 64), (48, 48)];
+        let names = sizes
+            .iter()
+            .map(|(w, h)| format!("{}x{}", w, h))
+            .collect::<Vec<_>>();
+        let names = names.iter().map(String::as_str).collect::<Vec<_>>();
+        let stub = names
+            .iter()
+            .zip(&sizes)
+            .map(|(name, (w, h))| (*name, fixture_png(*w, *h)))
+            .collect::<Vec<_>>();
+
+        let mut options = options(DeviceModel::Standard, NavLayout::Minimal);
+        options.sort = EmoteSort::BySize;
+        let profiles = profiles_from(&named_emotes(&names), &stub, &options).await?;
+
+        let (_, manifest) = &profiles.manifests[0];
+        let names = (1..5)
+            .chain(6..7)
+            .map(|x| Position::new(x % 5, x / 5))
+            .filter_map(|pos| Some(manifest.actions.get(&pos)?.emote.as_ref()?.name.as_str()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(names, vec!["48x48", "64x32", "30x64", "16x16", "8x8"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn tier_badges() -> Result<()> {
+        let mut emotes = named_emotes(&["member", "plain", "super"]);
+        emotes[0].tier = Some("Member".into());
+        emotes[2].tier = Some("Super".into());
+        let fixture = fixture_png(72, 72);
+        let stub = emotes
+            .iter()
+            .map(|emote| (emote.name.as_str(), fixture.clone()))
+            .collect::<Vec<_>>();
+
+        let mut options = options(DeviceModel::Standard, NavLayout::Minimal);
+        options.tier_badges = Some(BadgeStyle::default());
+        options.tier_colors = vec!["super=#FF0000".parse()?];
+        let profiles = profiles_from(&emotes, &stub, &options).await?;
+
+        let (_, manifest) = &profiles.manifests[0];
+        let pixel = |name: &str, x: u32, y: u32| {
+            let action = manifest
+                .actions
+                .values()
+                .find(|action| action.emote.as_ref().is_some_and(|e| e.name == name))
+                .unwrap();
+            let image = image::load_from_memory(action.image.as_ref().unwrap()).unwrap();
+            image.to_rgba8().get_pixel(x, y).0
+        };
+        let original = image::load_from_memory(&fixture)?.to_rgba8();
+
+        // A 25% badge in the top right corner, in the default color of the first tier or the
+        // tier's color
+        assert_eq!(pixel("member", 60, 12), [0x2e, 0x9e, 0x44, 255]);
+        assert_eq!(pixel("super", 60, 12), [255, 0, 0, 255]);
+        assert_eq!(pixel("member", 12, 12), original.get_pixel(12, 12).0);
+        assert_eq!(pixel("member", 60, 60), original.get_pixel(60, 60).0);
+
+        // Emotes without a known tier don't get a badge
+        assert_eq!(pixel("plain", 60, 12), original.get_pixel(60, 12).0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn animated_images() -> Result<()> {
+        let emotes = named_emotes(&["animated", "static"]);
+        let stub = [
+            ("animated", fixture_gif(32, 32, 2)),
+            ("static", fixture_png(32, 32)),
+        ];
+
+        let image_names = |profiles: &ProfilesWithImages| {
+            let (_, manifest) = &profiles.manifests[0];
+            [Position::new(1, 0), Position::new(2, 0)]
+                .iter()
+                .map(|pos| manifest.actions[pos].states[0].image.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let mut options = options(DeviceModel::Standard, NavLayout::Minimal);
+        options.image.animated = true;
+        let profiles = profiles_from(&emotes, &stub, &options).await?;
+        assert_eq!(image_names(&profiles), vec!["state0.gif", "state0.png"]);
+
+        options.image.animated = false;
+        let profiles = profiles_from(&emotes, &stub, &options).await?;
+        assert_eq!(image_names(&profiles), vec!["state0.png", "state0.png"]);
+
+        // The animation stays a GIF, while the opaque static image becomes a JPEG
+        options.image.animated = true;
+        options.image.format = crate::processing::KeyImageFormat::Auto;
+        let profiles = profiles_from(&emotes, &stub, &options).await?;
+        assert_eq!(image_names(&profiles), vec!["state0.gif", "state0.jpg"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn dedupe_by_image() -> Result<()> {
+        let emotes = named_emotes(&["first", "copy", "other"]);
+        let stub = [
+            ("first", fixture_png(16, 16)),
+            ("copy", fixture_png(16, 16)),
+            ("other", fixture_png(16, 8)),
+        ];
+
+        let mut options = options(DeviceModel::Standard, NavLayout::Minimal);
+        let profiles = profiles_from(&emotes, &stub, &options).await?;
+        assert_eq!(emote_names(&profiles), vec!["copy", "first", "other"]);
+
+        options.dedupe_by_image = true;
+        let profiles = profiles_from(&emotes, &stub, &options).await?;
+        assert_eq!(emote_names(&profiles), vec!["first", "other"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn no_animated() -> Result<()> {
+        let emotes = named_emotes(&["animated", "static", "single_frame"]);
+        let stub = [
+            ("animated", fixture_gif(16, 16, 3)),
+            ("static", fixture_png(16, 16)),
+            ("single_frame", fixture_gif(16, 16, 1)),
+        ];
+
+        let mut options = options(DeviceModel::Standard, NavLayout::Minimal);
+        let profiles = profiles_from(&emotes, &stub, &options).await?;
+        assert_eq!(
+            emote_names(&profiles),
+            vec!["animated", "single_frame", "static"]
+        );
+
+        options.no_animated = true;
+        let profiles = profiles_from(&emotes, &stub, &options).await?;
+        assert_eq!(emote_names(&profiles), vec!["single_frame", "static"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn min_source_resolution() -> Result<()> {
+        let emotes = named_emotes(&["tiny", "wide", "large"]);
+        let stub = [
+            ("tiny", fixture_png(32, 32)),
+            ("wide", fixture_png(256, 48)),
+            ("large", fixture_png(128, 128)),
+        ];
+
+        let mut options = options(DeviceModel::Standard, NavLayout::Minimal);
+        options.min_source_resolution = Some(64);
+        let profiles = profiles_from(&emotes, &stub, &options).await?;
+
+        assert_eq!(emote_names(&profiles), vec!["large"]);
+        assert!(profiles.downloaded_bytes > 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn save_originals() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let emotes = named_emotes(&["small9cm", "a/b", "a:b"]);
+        let original = fixture_png(128, 128);
+        let stub = emotes
+            .iter()
+            .map(|emote| (emote.name.as_str(), original.clone()))
+            .collect::<Vec<_>>();
+
+        let mut options = options(DeviceModel::Standard, NavLayout::Minimal);
+        options.image.resize = 72;
+        options.save_originals = Some(dir.path().join("originals"));
+        let profiles = profiles_from(&emotes, &stub, &options).await?;
+
+        let mut files = fs::read_dir(dir.path().join("originals"))?
+            .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+            .collect::<Result<Vec<_>>>()?;
+        files.sort();
+        assert_eq!(files, vec!["a_b.png", "a_b_2.png", "small9cm.png"]);
+        for file in files {
+            assert_eq!(fs::read(dir.path().join("originals").join(file))?, original);
+        }
+
+        // The profile still gets the resized images
+        for action in profiles.manifests[0].1.actions.values() {
+            if let Some(image) = &action.image {
+                assert_eq!(image_dimensions(image), Some((72, 72)));
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn layout_plans() {
+        let plan = |count, model, nav_style| {
+            let mut options = options(model, NavLayout::Minimal);
+            options.nav_style = nav_style;
+            plan_layout(count, &options)
+        };
+
+        let standard = plan(30, DeviceModel::Standard, NavStyle::Chain);
+        assert_eq!(standard.emotes_per_page, vec![12, 12, 6]);
+        assert_eq!(standard.reserved.len(), 3);
+        assert_eq!(standard.pages(), 3);
+
+        let xl = plan(56, DeviceModel::XL, NavStyle::Index);
+        assert_eq!(xl.emotes_per_page, vec![28, 28]);
+        assert_eq!((xl.nav_style, xl.pages()), (NavStyle::Index, 3));
+
+        // One page doesn't need an index
+        let single = plan(4, DeviceModel::Mini, NavStyle::Index);
+        assert_eq!(single.emotes_per_page, vec![4]);
+        assert_eq!(single.nav_style, NavStyle::Chain);
+
+        // 8 pages don't fit on the Mini's 6 keys
+        let mini = plan(30, DeviceModel::Mini, NavStyle::Index);
+        assert_eq!(mini.emotes_per_page.len(), 8);
+        assert_eq!(mini.emotes_per_page.last(), Some(&2));
+        assert_eq!(mini.nav_style, NavStyle::Chain);
+
+        assert_eq!(plan(0, DeviceModel::Standard, NavStyle::Chain).pages(), 0);
+
+        // The plan matches the generated pages
+        let options = options(DeviceModel::Standard, NavLayout::Row);
+        let profiles = ProfilesWithImages::from_images(emote_images(25), &options);
+        assert_eq!(
+            plan_layout(25, &options).emotes_per_page,
+            profiles
+                .manifests
+                .iter()
+                .map(|(_, m)| m.actions.values().filter(|a| a.emote.is_some()).count())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn extra_actions() -> Result<()> {
+        let website: ExtraAction = "small9cm=https://example.com/?a=b".parse()?;
+        assert_eq!(website.target, ExtraActionTarget::Emote("small9cm".into()));
+        assert_eq!(website.url, "https://example.com/?a=b");
+        assert_eq!(
+            "0,1=https://example.com".parse::<ExtraAction>()?.target,
+            ExtraActionTarget::Position(Position::new(0, 1))
+        );
+        assert!("https://example.com".parse::<ExtraAction>().is_err());
+
+        let json = serde_json::to_value(website.settings())?;
+        let expected = serde_json::json!({
+            "UUID": "com.elgato.streamdeck.system.website",
+            "Settings": { "openInBrowser": true, "path": "https://example.com/?a=b" }
+        });
+        assert_eq!(json, expected);
+
+        let mut options = options(DeviceModel::Standard, NavLayout::Minimal);
+        options.extra_actions = vec![
+            "EMOTE1=https://example.com/1".parse()?,
+            "0,1=https://example.com/channel".parse()?,
+        ];
+        let profiles = ProfilesWithImages::from_images(emote_images(3), &options);
+        let actions = &profiles.manifests[0].1.actions;
+        assert!(matches!(
+            &actions[&Position::new(2, 0)].settings,
+            Settings::OpenUrl { path, .. } if path == "https://example.com/1"
+        ));
+        assert!(matches!(
+            &actions[&Position::new(0, 1)].settings,
+            Settings::OpenUrl { path, .. } if path == "https://example.com/channel"
+        ));
+        assert!(matches!(
+            &actions[&Position::new(1, 0)].settings,
+            Settings::Text { .. }
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn compact_single_page() {
+        let mut options = options(DeviceModel::Standard, NavLayout::Minimal);
+        options.compact = true;
+
+        let profiles = ProfilesWithImages::from_images(emote_images(15), &options);
+        assert_eq!(profiles.manifests.len(), 1);
+        let (_, manifest) = &profiles.manifests[0];
+        assert_eq!(manifest.actions.len(), 15);
+        assert!(manifest
+            .actions
+            .values()
+            .all(|action| action.emote.is_some()));
+
+        // Pages are laid out as usual once there are too many emotes for one page
+        let profiles = ProfilesWithImages::from_images(emote_images(16), &options);
+        assert_eq!(profiles.manifests.len(), 2);
+        assert!(profiles.manifests[0].1.actions[&Position::new(0, 2)]
+            .emote
+            .is_none());
+
+        // Nested profiles keep their Back key
+        options.parent_profile_uuid = Some(uuid_v5("Parent", 0));
+        let profiles = ProfilesWithImages::from_images(emote_images(14), &options);
+        assert_eq!(profiles.manifests.len(), 1);
+        let back = &profiles.manifests[0].1.actions[&Position::new(0, 0)];
+        assert!(matches!(back.settings, Settings::BackToParent {}));
+    }
+
+    #[test]
+    fn no_navigation() {
+        let mut options = options(DeviceModel::Standard, NavLayout::Minimal);
+        options.no_navigation = true;
+
+        let profiles = ProfilesWithImages::from_images(emote_images(30), &options);
+        assert_eq!(profiles.manifests.len(), 1);
+        let (_, manifest) = &profiles.manifests[0];
+        assert_eq!(manifest.actions.len(), 15);
+        assert!(manifest
+            .actions
+            .values()
+            .all(|action| matches!(action.settings, Settings::Text { .. })));
+        assert!(manifest.actions.values().any(|action| action
+            .emote
+            .as_ref()
+            .is_some_and(|emote| emote.name == "emote14")));
+
+        // Not even a Back key when nested in another profile
+        options.parent_profile_uuid = Some(uuid_v5("Parent", 0));
+        let profiles = ProfilesWithImages::from_images(emote_images(30), &options);
+        assert_eq!(profiles.manifests[0].1.actions.len(), 15);
+        assert_eq!(plan_layout(30, &options).emotes_per_page, vec![15]);
+    }
+
+    #[test]
+    fn start_position() -> Result<()> {
+        let mut options = options(DeviceModel::Standard, NavLayout::Minimal);
+        options.start_position = Some("2,1".parse()?);
+        let profiles = ProfilesWithImages::from_images(emote_images(19), &options);
+
+        // The keys before the start position are left empty, and later pages are filled as usual
+        let first_page = emote_positions(&profiles, 0);
+        assert_eq!(first_page[0], Position::new(2, 1));
+        assert!(first_page.iter().all(|pos| (pos.y, pos.x) >= (1, 2)));
+        for x in 0..5 {
+            assert!(!profiles.manifests[0]
+                .1
+                .actions
+                .contains_key(&Position::new(x, 0)));
+        }
+        assert_eq!(emote_positions(&profiles, 1).len(), 12);
+        assert_eq!(first_page.len(), 7);
+        assert_eq!(profiles.manifests.len(), 2);
+
+        let plan = plan_layout(19, &options);
+        assert_eq!(plan.emotes_per_page, vec![7, 12]);
+        assert_eq!(plan.skipped.len(), 7);
+
+        assert!("2".parse::<Position>().is_err());
+        assert!("a,b".parse::<Position>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn center_last_page() {
+        let mut options = options(DeviceModel::Standard, NavLayout::Minimal);
+        options.center_last_page = true;
+
+        // 12 emotes on the first page, leaving 3 for the second
+        let profiles = ProfilesWithImages::from_images(emote_images(15), &options);
+        assert_eq!(profiles.manifests.len(), 2);
+        assert_eq!(
+            emote_positions(&profiles, 1),
+            vec![
+                Position::new(1, 0),
+                Position::new(2, 0),
+                Position::new(3, 0)
+            ]
+        );
+
+        // Full rows are filled as usual, and earlier pages aren't affected
+        let profiles = ProfilesWithImages::from_images(emote_images(19), &options);
+        assert_eq!(emote_positions(&profiles, 0).len(), 12);
+        assert_eq!(
+            emote_positions(&profiles, 1),
+            vec![
+                Position::new(1, 0),
+                Position::new(2, 0),
+                Position::new(3, 0),
+                Position::new(4, 0),
+                Position::new(1, 1),
+                Position::new(2, 1),
+                Position::new(3, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn page_name_template() {
+        let mut options = options(DeviceModel::Standard, NavLayout::Minimal);
+        options.page_name_template = Some("{name} {page}".into());
+
+        let profiles = ProfilesWithImages::from_images(emote_images(30), &options);
+        let names = profiles
+            .manifests
+            .iter()
+            .map(|(_, manifest)| manifest.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["Emotes", "Emotes 2", "Emotes 3"]);
+
+        options.nav_style = NavStyle::Index;
+        let profiles = ProfilesWithImages::from_images(emote_images(30), &options);
+        let names = profiles
+            .manifests
+            .iter()
+            .map(|(_, manifest)| manifest.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["Emotes", "Emotes 1", "Emotes 2", "Emotes 3"]);
+
+        options.page_name_template = None;
+        let profiles = ProfilesWithImages::from_images(emote_images(30), &options);
+        assert!(profiles.manifests.iter().all(|(_, m)| m.name == "Emotes"));
+    }
+
+    #[test]
+    fn sanitize_names() {
+        assert_eq!(sanitize_name("small9cm"), "small9cm");
+        assert_eq!(sanitize_name("a/b\\c:d"), "a_b_c_d");
+        assert_eq!(sanitize_name("what?*<>|\"now\""), "what______now_");
+        assert_eq!(sanitize_name("  two \t words\n"), "two words");
+        assert_eq!(sanitize_name("trailing..."), "trailing");
+        assert_eq!(sanitize_name(".."), "_");
+        assert_eq!(sanitize_name(" "), "_");
+        assert_eq!(sanitize_name("ぽむ"), "ぽむ");
+    }
+
+    #[test]
+    fn label_sizes() -> Result<()> {
+        let size: LabelSize = "16".parse()?;
+        assert_eq!(size, LabelSize("16".into()));
+        assert_eq!(" 6 ".parse::<LabelSize>()?, LabelSize("6".into()));
+
+        for invalid in ["0", "41", "300", "-1", "12.5", "large", ""] {
+            let error = invalid.parse::<LabelSize>().unwrap_err().to_string();
+            assert!(error.contains("from 6 to 40"), "{}", error);
         }
+
+        let mut options = options(DeviceModel::Standard, NavLayout::Minimal);
+        options.action.include_label = true;
+        options.action.label_size = Some(size);
+        let profiles = ProfilesWithImages::from_images(emote_images(2), &options);
+        let action = &profiles.manifests[0].1.actions[&Position::new(1, 0)];
+        assert_eq!(action.states[0].f_size, "16");
+
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn fit_labels() {
+        assert_eq!(fit_label("small9cm", 8, false), "small9cm");
+        assert_eq!(fit_label("small9cm", 20, false), "small9cm");
+        assert_eq!(fit_label("verycool", 5, false), "very…");
+        assert_eq!(fit_label("ぽむぽむぽむ", 4, false), "ぽむぽ…");
+        assert_eq!(fit_label("pomu", 1, false), "…");
+
+        assert_eq!(fit_label("Pomu small 9cm", 9, true), "Pomu\nsmall 9cm");
+        assert_eq!(fit_label("extraordinarily big", 8, true), "extraor…\nbig");
+        assert_eq!(fit_label("hic", 8, true), "hic");
+
+        let mut images = emote_images(1);
+        images[0].emote.name = "extraordinary".into();
+        let mut options = options(DeviceModel::Standard, NavLayout::Minimal);
+        options.action.include_label = true;
+        options.action.label_max_chars = Some(6);
+        let profiles = ProfilesWithImages::from_images(images, &options);
+        let action = &profiles.manifests[0].1.actions[&Position::new(1, 0)];
+        assert_eq!(action.states[0].title, "extra…");
+    }
 
     #[test]
-    fn serialize_profile() -> Result<()> {
-        let mut actions = HashMap::new();
+    fn tier_colors() -> Result<()> {
+        let mut images = emote_images(3);
+        images[0].emote.tier = Some("Member".into());
+        images[1].emote.tier = Some("Super Member".into());
+        images[2].emote.tier = Some("Unmapped".into());
+
+        let mut options = options(DeviceModel::Standard, NavLayout::Minimal);
+        options.action.include_label = true;
+        options.tier_colors = vec!["member=#FF0000".parse()?, "Super Member=#00ff00".parse()?];
+
+        let profiles = ProfilesWithImages::from_images(images, &options);
+        let colors = profiles.manifests[0]
+            .1
+            .actions
+            .values()
+            .filter_map(|action| Some((action.emote.as_ref()?.name.as_str(), action)))
+            .map(|(name, action)| (name, action.states[0].title_color.as_str()))
+            .collect::<HashMap<_, _>>();
+
+        assert_eq!(colors["emote0"], "#ff0000");
+        assert_eq!(colors["emote1"], "#00ff00");
+        assert_eq!(colors["emote2"], State::default().title_color);
+
+        assert!("Member=red".parse::<TierColor>().is_err());
+        assert!("Member=#12345".parse::<TierColor>().is_err());
+        assert!("=#123456".parse::<TierColor>().is_err());
 
-        actions.insert(
-            Position::new(0, 0),
-            Action {
-                name: "Open Folder".into(),
-                state: 0,
-                states: vec![State::default()],
-                settings: Settings::BackToParent {},
-                image: None,
-            },
-        );
+        Ok(())
+    }
 
-        actions.insert(
-            Position::new(1, 0),
-            Action {
-                name: "Text".into(),
-                state: 0,
-                states: vec![State::new_image()],
-                image: None,
-                settings: Settings::Text {
-                    is_sending_enter: false,
-                    pasted_text: ":_pomuSmall9cm:".into(),
-                },
-            },
+    #[tokio::test]
+    async fn new_with_stub_fetcher() -> Result<()> {
+        let emotes = (0..30)
+            .map(|i| Emote {
+                url: format!("http://example.com/{}.png", i),
+                name: format!("emote{}", i),
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+
+        // Each image has a different height, to tell them apart once they're resized
+        let urls = emotes.iter().map(|e| e.url.clone()).collect::<Vec<_>>();
+        let images = (0..urls.len())
+            .map(|i| fixture_png(72, i as u32 + 1))
+            .collect::<Vec<_>>();
+        let fetcher = StubFetcher::new(
+            urls.iter()
+                .zip(&images)
+                .map(|(url, image)| (url.as_str(), image.clone())),
         );
 
-        let profile_uuid = Uuid::parse_str("AC20BCF3-0A7C-4243-BB74-5C0DC5681BA5")?;
+        let options = options(DeviceModel::Standard, NavLayout::Minimal);
+        let profiles = ProfilesWithImages::new(&fetcher, emotes, &options).await?;
+
+        let mut requested = fetcher.requested();
+        requested.sort();
+        let mut expected = urls.clone();
+        expected.sort();
+        assert_eq!(requested, expected);
+
+        let total = images.iter().map(|image| image.len() as u64).sum::<u64>();
+        assert_eq!(profiles.downloaded_bytes, total);
+
+        // 12 emotes per page on the standard model, with the leftmost column reserved
+        assert_eq!(profiles.manifests.len(), 3);
+        let uuids = profiles
+            .manifests
+            .iter()
+            .map(|(uuid, _)| *uuid)
+            .collect::<Vec<_>>();
+        assert_eq!(uuids[0], options.root_profile_uuid);
+        assert_eq!(uuids[1], uuid_v5("Emotes", 1));
+        assert_eq!(uuids[2], uuid_v5("Emotes", 2));
+
+        for (page, (_, manifest)) in profiles.manifests.iter().enumerate() {
+            for (pos, action) in manifest.actions.iter() {
+                if let Some(emote) = &action.emote {
+                    let index = page * 12 + (pos.y as usize) * 4 + (pos.x as usize - 1);
+                    assert_eq!(emote.url, urls[index]);
+                    let image = action.image.as_deref().unwrap();
+                    assert_eq!(image_dimensions(image), Some((72, index as u32 + 1)));
+                }
+            }
+        }
 
-        actions.insert(
-            Position::new(4, 2),
-            Action {
-                name: "Create Folder".into(),
-                state: 0,
-                states: vec![State::default()],
-                settings: Settings::OpenChild { profile_uuid },
-                image: None,
-            },
-        );
+        let (_, last) = &profiles.manifests[2];
+        assert_eq!(last.actions.len(), 6 + 1);
+        assert!(matches!(
+            last.actions[&Position::new(0, 0)].settings,
+            Settings::BackToParent {}
+        ));
+        assert!(!last.actions.contains_key(&Position::new(0, 2)));
 
-        let profile = ProfileManifest {
-            actions,
-            device_model: DeviceModel::Standard,
-            device_uuid: "@(1)[4057/128/DL16K1A71331]".into(),
-            name: "Emotes".into(),
-            version: "1.0".into(),
+        Ok(())
+    }
+
+    #[test]
+    fn row_nav_layout_xl() {
+        let options = options(DeviceModel::XL, NavLayout::Row);
+        let profiles = ProfilesWithImages::from_images(emote_images(40), &options);
+
+        // 8x4 grid with the bottom row reserved fits 24 emotes per page
+        assert_eq!(profiles.manifests.len(), 2);
+
+        let text_count = |manifest: &ProfileManifest| {
+            manifest
+                .actions
+                .values()
+                .filter(|action| matches!(action.settings, Settings::Text { .. }))
+                .count()
         };
 
-        let json = serde_json::to_value(&profile)?;
+        let (root_uuid, root) = &profiles.manifests[0];
+        let (child_uuid, child) = &profiles.manifests[1];
+        assert_eq!(text_count(root), 24);
+        assert_eq!(text_count(child), 16);
 
-        let expected = serde_json::json!({
-          "Actions": {
-            "0,0": {
-              "State": 0,
-              "States": [
-                {
-                  "FFamily": "",
-                  "FSize": "12",
-                  "FStyle": "",
-                  "FUnderline": "off",
-                  "Image": "",
-                  "Title": "",
-                  "TitleAlignment": "bottom",
-                  "TitleColor": "#fbfcff",
-                  "TitleShow": ""
-                }
-              ],
-              "Name": "Open Folder",
-              "UUID": "com.elgato.streamdeck.profile.backtoparent",
-              "Settings": {}
-            },
-            "1,0": {
-              "State": 0,
-              "States": [
-                {
-                  "FFamily": "",
-                  "FSize": "12",
-                  "FStyle": "",
-                  "FUnderline": "off",
-                  "Image": "state0.png",
-                  "Title": "",
-                  "TitleAlignment": "bottom",
-                  "TitleColor": "#fbfcff",
-                  "TitleShow": ""
-                }
-              ],
-              "Name": "Text",
-              "UUID": "com.elgato.streamdeck.system.text",
-              "Settings": {
-                "isSendingEnter": false,
-                "pastedText": ":_pomuSmall9cm:"
-              }
-            },
-            "4,2": {
-              "State": 0,
-              "States": [
-                {
-                  "FFamily": "",
-                  "FSize": "12",
-                  "FStyle": "",
-                  "FUnderline": "off",
-                  "Image": "",
-                  "Title": "",
-                  "TitleAlignment": "bottom",
-                  "TitleColor": "#fbfcff",
-                  "TitleShow": ""
+        for (_, manifest) in profiles.manifests.iter() {
+            for x in 0..8 {
+                if let Some(action) = manifest.actions.get(&Position::new(x, 3)) {
+                    assert!(!matches!(action.settings, Settings::Text { .. }));
                 }
-              ],
-              "Name": "Create Folder",
-              "UUID": "com.elgato.streamdeck.profile.openchild",
-              "Settings": {
-                "ProfileUUID": "AC20BCF3-0A7C-4243-BB74-5C0DC5681BA5"
-              }
             }
-          },
-          "DeviceModel": "20GBA9901",
-          "DeviceUUID": "@(1)[4057/128/DL16K1A71331]",
-          "Name": "Emotes",
-          "Version": "1.0"
-        });
+        }
 
-        assert_eq!(json, expected);
+        assert_eq!(root.actions.len(), 25);
+        assert!(matches!(
+            root.actions[&Position::new(7, 3)].settings,
+            Settings::OpenChild { profile_uuid } if profile_uuid == *child_uuid
+        ));
+
+        assert_eq!(child.actions.len(), 18);
+        assert!(matches!(
+            child.actions[&Position::new(0, 3)].settings,
+            Settings::BackToParent {}
+        ));
+        assert!(matches!(
+            child.actions[&Position::new(4, 3)].settings,
+            Settings::SwitchProfile { profile_uuid, .. } if profile_uuid == *root_uuid
+        ));
+    }
+
+    #[test]
+    fn root_nested_in_parent() -> Result<()> {
+        let parent_uuid = Uuid::parse_str("AC20BCF3-0A7C-4243-BB74-5C0DC5681BA5")?;
+        let options = ProfileOptions {
+            parent_profile_uuid: Some(parent_uuid),
+            ..options(DeviceModel::Standard, NavLayout::Row)
+        };
+
+        let profiles = ProfilesWithImages::from_images(emote_images(20), &options);
+        assert_eq!(profiles.parent_profile_uuid, Some(parent_uuid));
+
+        let (_, root) = &profiles.manifests[0];
+        assert!(matches!(
+            root.actions[&Position::new(0, 2)].settings,
+            Settings::BackToParent {}
+        ));
+        assert!(!root.actions.contains_key(&Position::new(2, 2)));
+
+        let (_, child) = &profiles.manifests[1];
+        assert!(matches!(
+            child.actions[&Position::new(2, 2)].settings,
+            Settings::SwitchProfile { profile_uuid, .. } if profile_uuid == parent_uuid
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn minimal_nav_layout_standard() {
+        let options = options(DeviceModel::Standard, NavLayout::Minimal);
+        let profiles = ProfilesWithImages::from_images(emote_images(13), &options);
+
+        assert_eq!(profiles.manifests.len(), 2);
+
+        let (_, root) = &profiles.manifests[0];
+        assert!((0..3).all(|y| !matches!(
+            root.actions.get(&Position::new(0, y)).map(|a| &a.settings),
+            Some(Settings::Text { .. })
+        )));
+        assert!(matches!(
+            root.actions[&Position::new(0, 2)].settings,
+            Settings::OpenChild { .. }
+        ));
+
+        let (_, child) = &profiles.manifests[1];
+        assert!(matches!(
+            child.actions[&Position::new(0, 0)].settings,
+            Settings::BackToParent {}
+        ));
+        assert!(matches!(
+            child.actions[&Position::new(1, 0)].settings,
+            Settings::Text { .. }
+        ));
+    }
+
+    #[test]
+    fn grid_dimensions() {
+        // (columns, rows) of the physical devices
+        assert_eq!(DeviceModel::Standard.size(), (5, 3));
+        assert_eq!(DeviceModel::XL.size(), (8, 4));
+        assert_eq!(DeviceModel::Mini.size(), (3, 2));
+    }
+
+    #[test]
+    fn navigation_on_corners() {
+        for model in [DeviceModel::Standard, DeviceModel::XL, DeviceModel::Mini].iter() {
+            let (width, height) = model.size();
+            let (right, bottom) = (width - 1, height - 1);
+
+            let nav = NavLayout::Minimal;
+            assert_eq!(nav.back_position(model), Position::new(0, 0));
+            assert_eq!(nav.next_position(model), Position::new(0, bottom));
+
+            let nav = NavLayout::Row;
+            assert_eq!(nav.back_position(model), Position::new(0, bottom));
+            assert_eq!(nav.next_position(model), Position::new(right, bottom));
+
+            let options = options(model.clone(), NavLayout::Minimal);
+            let profiles = ProfilesWithImages::from_images(emote_images(50), &options);
+            for (_, manifest) in profiles.manifests.iter() {
+                assert!(manifest
+                    .actions
+                    .keys()
+                    .all(|pos| pos.x < width && pos.y < height));
+            }
+        }
+    }
+
+    #[test]
+    fn neo_model() -> Result<()> {
+        let model = "neo".parse::<DeviceModel>()?;
+        assert_eq!(model, DeviceModel::Neo);
+        assert_eq!(model.to_string(), "neo");
+        assert_eq!(serde_json::to_value(&model)?, "20GBJ9901");
+
+        // 6 emotes per page with the left column reserved
+        let profiles = ProfilesWithImages::from_images(
+            emote_images(20),
+            &options(DeviceModel::Neo, NavLayout::Minimal),
+        );
+        assert_eq!(profiles.manifests.len(), 4);
+        let (_, last) = &profiles.manifests[3];
+        assert_eq!(
+            last.actions.values().filter(|a| a.emote.is_some()).count(),
+            2
+        );
+        assert!(last.actions.keys().all(|pos| pos.x < 4 && pos.y < 2));
 
         Ok(())
     }
 
+    #[test]
+    fn parse_device_model() {
+        assert_eq!("XL".parse::<DeviceModel>().ok(), Some(DeviceModel::XL));
+        assert!(matches!(
+            "plus".parse::<DeviceModel>(),
+            Err(Error::InvalidDeviceModel(model)) if model == "plus"
+        ));
+    }
+
+    #[test]
+    fn model_from_id() {
+        for model in [DeviceModel::Standard, DeviceModel::XL, DeviceModel::Neo].iter() {
+            assert_eq!(DeviceModel::from_model_id(model.id()).as_ref(), Some(model));
+        }
+
+        assert_eq!(DeviceModel::from_model_id(DeviceModel::Mini.id()), None);
+        assert_eq!(DeviceModel::from_model_id(""), None);
+    }
+
     #[test]
     fn emote_to_action_with_prefix() -> Result<()> {
         let emote = Emote {
             url: "http://example.com/image.png".into(),
             name: "small9cm".into(),
+            ..Default::default()
         };
 
-        let action = emote.to_action("pomu", true, None);
+        let action = emote.to_action(
+            &ActionOptions {
+                prefix: "pomu".into(),
+                name_case: NameCase::CapitalizeFirst,
+                include_label: true,
+                ..Default::default()
+            },
+            None,
+        );
 
         assert_eq!(action.states[0].title, "small9cm");
 
@@ -527,14 +2942,190 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn normalize_prefix_forms() -> Result<()> {
+        let emote = Emote {
+            url: "http://example.com/image.png".into(),
+            name: "small9cm".into(),
+            ..Default::default()
+        };
+
+        for form in &["pomu", "_pomu", ":_pomu", ":_pomu:", " _pomu "] {
+            let prefix = normalize_prefix(form);
+            assert_eq!(prefix, "pomu", "input {:?}", form);
+
+            match emote
+                .to_action(
+                    &ActionOptions {
+                        prefix: prefix.clone(),
+                        name_case: NameCase::CapitalizeFirst,
+                        ..Default::default()
+                    },
+                    None,
+                )
+                .settings
+            {
+                Settings::Text { pasted_text, .. } if pasted_text == ":_pomuSmall9cm:" => {}
+                other => bail!("Unexpected settings for input {:?}: {:?}", form, other),
+            }
+        }
+
+        assert_eq!(normalize_prefix(""), "");
+        assert_eq!(normalize_prefix("_"), "");
+
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_pasted_texts_after_capitalization() {
+        let emotes = ["small9cm", "Small9cm", "yay"].iter().map(|name| Emote {
+            url: "http://example.com/image.png".into(),
+            name: (*name).into(),
+            ..Default::default()
+        });
+
+        let actions = emotes
+            .map(|emote| {
+                emote.to_action(
+                    &ActionOptions {
+                        prefix: "pomu".into(),
+                        name_case: NameCase::CapitalizeFirst,
+                        ..Default::default()
+                    },
+                    None,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            duplicate_pasted_texts(&actions),
+            vec![(
+                ":_pomuSmall9cm:".to_owned(),
+                vec!["small9cm".to_owned(), "Small9cm".to_owned()]
+            )]
+        );
+
+        assert!(duplicate_pasted_texts(&actions[1..]).is_empty());
+    }
+
+    #[test]
+    fn name_case_modes() {
+        let cases = [
+            (NameCase::AsIs, "small9Cm"),
+            (NameCase::CapitalizeFirst, "Small9Cm"),
+            (NameCase::Lowercase, "small9cm"),
+            (NameCase::Uppercase, "SMALL9CM"),
+        ];
+
+        for (name_case, expected) in cases.iter() {
+            assert_eq!(name_case.apply("small9Cm"), *expected, "{:?}", name_case);
+        }
+
+        assert_eq!(NameCase::CapitalizeFirst.apply(""), "");
+        assert_eq!(NameCase::default_for_prefix(""), NameCase::AsIs);
+        assert_eq!(
+            NameCase::default_for_prefix("pomu"),
+            NameCase::CapitalizeFirst
+        );
+    }
+
+    #[test]
+    fn labels_are_shown() -> Result<()> {
+        let emote = Emote {
+            url: "http://example.com/image.png".into(),
+            name: "small9cm".into(),
+            ..Default::default()
+        };
+
+        let labeled = serde_json::to_value(emote.to_action(
+            &ActionOptions {
+                prefix: "pomu".into(),
+                include_label: true,
+                ..Default::default()
+            },
+            None,
+        ))?;
+        assert_eq!(labeled["States"][0]["Title"], "small9cm");
+        assert_eq!(labeled["States"][0]["TitleShow"], "");
+
+        let unlabeled = serde_json::to_value(emote.to_action(
+            &ActionOptions {
+                prefix: "pomu".into(),
+                ..Default::default()
+            },
+            None,
+        ))?;
+        assert_eq!(unlabeled["States"][0]["Title"], "");
+        assert_eq!(unlabeled["States"][0]["TitleShow"], "");
+
+        Ok(())
+    }
+
+    #[test]
+    fn emote_to_action_underscore() {
+        let emote = Emote {
+            url: "http://example.com/image.png".into(),
+            name: "small9cm".into(),
+            ..Default::default()
+        };
+
+        let pasted_text = |prefix: &str, underscore: bool| match emote
+            .to_action(
+                &ActionOptions {
+                    prefix: prefix.into(),
+                    underscore,
+                    name_case: NameCase::default_for_prefix(prefix),
+                    ..Default::default()
+                },
+                None,
+            )
+            .settings
+        {
+            Settings::Text { pasted_text, .. } => pasted_text,
+            other => panic!("Unexpected settings {:?}", other),
+        };
+
+        assert_eq!(pasted_text("pomu", true), ":_pomuSmall9cm:");
+        assert_eq!(pasted_text("pomu", false), ":pomuSmall9cm:");
+        assert_eq!(
+            pasted_text(&normalize_prefix("_pomu"), false),
+            ":pomuSmall9cm:"
+        );
+        assert_eq!(pasted_text("", true), ":_small9cm:");
+        assert_eq!(pasted_text("", false), ":small9cm:");
+    }
+
+    #[test]
+    fn text_template() {
+        let emote = Emote {
+            url: "http://example.com/image.png".into(),
+            name: "small9cm".into(),
+            ..Default::default()
+        };
+        let code = |text_template: Option<&str>| {
+            emote.code(&ActionOptions {
+                prefix: "pomu".into(),
+                name_case: NameCase::CapitalizeFirst,
+                text_template: text_template.map(String::from),
+                ..Default::default()
+            })
+        };
+
+        assert_eq!(code(None), ":_pomuSmall9cm:");
+        assert_eq!(code(Some("{Name} ")), "Small9cm ");
+        assert_eq!(code(Some("{prefix}:{name}")), "pomu:Small9cm");
+        assert_eq!(code(Some("[{name}")), "[Small9cm");
+    }
+
     #[test]
     fn emote_to_action_no_prefix() -> Result<()> {
         let emote = Emote {
             url: "http://example.com/image.png".into(),
             name: "hic1".into(),
+            ..Default::default()
         };
 
-        let action = emote.to_action("", false, None);
+        let action = emote.to_action(&ActionOptions::default(), None);
 
         assert_eq!(action.states[0].title, "");
 
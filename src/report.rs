@@ -0,0 +1,270 @@
+use crate::profile::{DeviceModel, LayoutPlan, NavStyle};
+use color_eyre::eyre::{bail, Result, WrapErr};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Version of the JSON printed with `--format json`. It's increased when fields are removed or
+/// change meaning, but fields can be added without changing it.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// How informational output (`--print-codes`, `--plan-only`, and `--suggest-model`) is printed
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OutputFormat {
+    /// Lines meant for people to read, which may change between versions
+    #[default]
+    Text,
+    /// A single JSON object with a `schema_version`, see [`SCHEMA_VERSION`]
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = color_eyre::eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_ref() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            other => bail!("Unknown output format {}", other),
+        }
+    }
+}
+
+/// Prints the report as pretty JSON on stdout
+pub fn print_json(report: &impl Serialize) -> Result<()> {
+    let json = serde_json::to_string_pretty(report).context("Failed to serialize output")?;
+    println!("{}", json);
+    Ok(())
+}
+
+/// Output of `--print-codes`
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct CodesReport {
+    pub schema_version: u32,
+    pub emotes: Vec<EmoteCode>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct EmoteCode {
+    pub name: String,
+    /// Text pasted by the emote's key, e.g. `:_pomuSmall9cm:`
+    pub code: String,
+}
+
+impl CodesReport {
+    pub fn new(emotes: Vec<EmoteCode>) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            emotes,
+        }
+    }
+}
+
+/// Output of `--plan-only`, with a plan for each device
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct PlanReport {
+    pub schema_version: u32,
+    pub devices: Vec<DevicePlan>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct DevicePlan {
+    /// Model name as given to `--model`, e.g. `xl`
+    pub model: String,
+    pub emotes: usize,
+    /// Total number of pages, including the index page if there is one
+    pub pages: usize,
+    /// Number of emotes on each page of emotes, in order
+    pub emotes_per_page: Vec<usize>,
+    /// Number of keys on each page of emotes that are used for navigation
+    pub reserved_keys: usize,
+    pub index_page: bool,
+}
+
+impl PlanReport {
+    pub fn new(devices: Vec<DevicePlan>) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            devices,
+        }
+    }
+}
+
+impl DevicePlan {
+    pub fn new(model: &DeviceModel, emotes: usize, plan: &LayoutPlan) -> Self {
+        Self {
+            model: model.to_string(),
+            emotes,
+            pages: plan.pages(),
+            emotes_per_page: plan.emotes_per_page.clone(),
+            reserved_keys: plan.reserved.len(),
+            index_page: plan.nav_style == NavStyle::Index,
+        }
+    }
+}
+
+/// Output of `--suggest-model`, with the models that need the fewest pages first
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct SuggestionsReport {
+    pub schema_version: u32,
+    pub emotes: usize,
+    pub models: Vec<ModelSuggestion>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct ModelSuggestion {
+    pub model: String,
+    /// Number of keys on one screen of the device
+    pub keys: usize,
+    pub pages: usize,
+    /// Whether all of the emotes fit on one screen with `--compact`
+    pub fits_on_one_screen: bool,
+}
+
+impl SuggestionsReport {
+    pub fn new(emotes: usize, models: Vec<ModelSuggestion>) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            emotes,
+            models,
+        }
+    }
+}
+
+/// Written by `--failure-report`, with the emotes whose images couldn't be downloaded, and read back
+/// by `--retry-failed`
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct FailureReport {
+    pub schema_version: u32,
+    pub failed: Vec<FailedEmote>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct FailedEmote {
+    pub name: String,
+    pub url: String,
+    pub error: String,
+}
+
+impl FailureReport {
+    pub fn new(failed: Vec<FailedEmote>) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            failed,
+        }
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        let string = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read failure report {:?}", path))?;
+        let report = serde_json::from_str::<Self>(&string)
+            .with_context(|| format!("Invalid failure report {:?}", path))?;
+        if report.schema_version != SCHEMA_VERSION {
+            bail!(
+                "Failure report {:?} has schema version {}, but only {} is supported",
+                path,
+                report.schema_version,
+                SCHEMA_VERSION
+            );
+        }
+        Ok(report)
+    }
+
+    /// Writes the report as pretty JSON, replacing the file if it exists
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self).context("Failed to serialize output")?;
+        fs::write(path, json).with_context(|| format!("Failed to write failure report {:?}", path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::Position;
+    use serde::de::DeserializeOwned;
+    use serde_json::{json, Value};
+    use std::fmt::Debug;
+
+    /// Checks that the report serializes to `expected`, and deserializes back to itself
+    fn assert_round_trip<T>(report: &T, expected: Value)
+    where
+        T: Serialize + DeserializeOwned + Debug + PartialEq,
+    {
+        let value = serde_json::to_value(report).unwrap();
+        assert_eq!(value, expected);
+        assert_eq!(&serde_json::from_value::<T>(value).unwrap(), report);
+    }
+
+    #[test]
+    fn json_reports() {
+        let codes = CodesReport::new(vec![EmoteCode {
+            name: "small9cm".into(),
+            code: ":_pomuSmall9cm:".into(),
+        }]);
+        assert_round_trip(
+            &codes,
+            json!({
+                "schema_version": 1,
+                "emotes": [{ "name": "small9cm", "code": ":_pomuSmall9cm:" }]
+            }),
+        );
+
+        let plan = LayoutPlan {
+            emotes_per_page: vec![12, 6],
+            reserved: (0..3).map(|y| Position::new(0, y)).collect(),
+            skipped: Vec::new(),
+            nav_style: NavStyle::Chain,
+        };
+        let plans = PlanReport::new(vec![DevicePlan::new(&DeviceModel::Standard, 18, &plan)]);
+        assert_round_trip(
+            &plans,
+            json!({
+                "schema_version": 1,
+                "devices": [{
+                    "model": "standard",
+                    "emotes": 18,
+                    "pages": 2,
+                    "emotes_per_page": [12, 6],
+                    "reserved_keys": 3,
+                    "index_page": false
+                }]
+            }),
+        );
+
+        let suggestions = SuggestionsReport::new(
+            30,
+            vec![ModelSuggestion {
+                model: "xl".into(),
+                keys: 32,
+                pages: 2,
+                fits_on_one_screen: true,
+            }],
+        );
+        assert_round_trip(
+            &suggestions,
+            json!({
+                "schema_version": 1,
+                "emotes": 30,
+                "models": [{ "model": "xl", "keys": 32, "pages": 2, "fits_on_one_screen": true }]
+            }),
+        );
+
+        let failures = FailureReport::new(vec![FailedEmote {
+            name: "small9cm".into(),
+            url: "https://example.com/small9cm.png".into(),
+            error: "Failed to download".into(),
+        }]);
+        assert_round_trip(
+            &failures,
+            json!({
+                "schema_version": 1,
+                "failed": [{
+                    "name": "small9cm",
+                    "url": "https://example.com/small9cm.png",
+                    "error": "Failed to download"
+                }]
+            }),
+        );
+    }
+}
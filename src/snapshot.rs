@@ -0,0 +1,92 @@
+use crate::profile::Emote;
+use color_eyre::eyre::{Result, WrapErr};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+/// An emote as recorded in a snapshot file, which is a JSON array of these. The same file can also
+/// be used with `--emotes-json`.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct SnapshotEmote {
+    pub name: String,
+    pub url: String,
+}
+
+pub fn read_snapshot(path: &Path) -> Result<Vec<SnapshotEmote>> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read snapshot file {:?}", path))?;
+
+    serde_json::from_str(&contents)
+        .with_context(|| format!("Failed to parse snapshot file {:?}", path))
+}
+
+/// Saves the names and URLs of the emotes, to compare against with `--since` in a later run
+pub fn write_snapshot(path: &Path, emotes: &[Emote]) -> Result<()> {
+    let snapshot = emotes
+        .iter()
+        .map(|emote| SnapshotEmote {
+            name: emote.name.clone(),
+            url: emote.url.clone(),
+        })
+        .collect::<Vec<_>>();
+
+    let json = serde_json::to_string_pretty(&snapshot)?;
+    fs::write(path, json).with_context(|| format!("Failed to write snapshot file {:?}", path))
+}
+
+/// Names of the emotes that aren't in the snapshot (case-insensitive), in their original order
+pub fn new_emote_names(snapshot: &[SnapshotEmote], emotes: &[Emote]) -> Vec<String> {
+    let known = snapshot
+        .iter()
+        .map(|emote| emote.name.to_lowercase())
+        .collect::<HashSet<_>>();
+
+    emotes
+        .iter()
+        .filter(|emote| !known.contains(&emote.name.to_lowercase()))
+        .map(|emote| emote.name.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn emotes(names: &[&str]) -> Vec<Emote> {
+        names
+            .iter()
+            .map(|name| Emote {
+                url: format!("http://example.com/{}.png", name),
+                name: (*name).to_owned(),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn new_emotes_since_snapshot() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("snapshot.json");
+
+        write_snapshot(&path, &emotes(&["small9cm", "hic"]))?;
+        let snapshot = read_snapshot(&path)?;
+        assert_eq!(
+            snapshot[0],
+            SnapshotEmote {
+                name: "small9cm".into(),
+                url: "http://example.com/small9cm.png".into(),
+            }
+        );
+
+        let current = emotes(&["wave", "Small9cm", "hic", "hype"]);
+        assert_eq!(new_emote_names(&snapshot, &current), vec!["wave", "hype"]);
+        assert!(new_emote_names(&snapshot, &current[1..3]).is_empty());
+
+        // Snapshots can be read as an emotes file too
+        let emotes = crate::emotes_json::read_emotes_json(&path)?;
+        assert_eq!(emotes.len(), 2);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,153 @@
+use color_eyre::eyre::{bail, Result, WrapErr};
+use fs_extra::dir::CopyOptions;
+use std::collections::BTreeSet;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// Copies everything in `from` into `to`, if `from` exists
+pub fn copy_contents(from: &Path, to: &Path) -> Result<()> {
+    if from.exists() {
+        let copy_options = CopyOptions {
+            content_only: true,
+            ..Default::default()
+        };
+        fs_extra::dir::copy(from, to, &copy_options)
+            .with_context(|| format!("Failed to copy {:?} to {:?}", from, to))?;
+    }
+    Ok(())
+}
+
+/// Copy of the output directory for `--stage-dir`. Profiles are written here first, then the
+/// top-level directories that changed replace the ones in the output directory, so that the Stream
+/// Deck application never sees a partly written profile. The stage is removed when this is
+/// dropped, whether or not it was synced, so that a failed run doesn't leave it behind.
+#[derive(Debug)]
+pub struct StageDir {
+    pub path: PathBuf,
+    /// Names of the entries in the output directory when it was copied
+    copied: BTreeSet<OsString>,
+}
+
+impl StageDir {
+    /// Copies `root_path` into `path`, which must be empty or not exist yet
+    pub fn create(root_path: &Path, path: &Path) -> Result<Self> {
+        let is_empty = match fs::read_dir(path) {
+            Ok(mut entries) => entries.next().is_none(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => true,
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read stage directory {:?}", path))
+            }
+        };
+        if !is_empty {
+            bail!("Stage directory {:?} must be empty", path);
+        }
+
+        fs::create_dir_all(path)
+            .with_context(|| format!("Failed to create stage directory {:?}", path))?;
+        copy_contents(root_path, path)?;
+
+        Ok(Self {
+            path: path.to_owned(),
+            copied: entry_names(path)?,
+        })
+    }
+
+    /// Replaces each top-level entry of `root_path` that has a written file in it (or that was
+    /// removed from the stage, e.g. by `--replace`) with the staged one
+    pub fn sync(self, root_path: &Path, written_files: &[PathBuf]) -> Result<()> {
+        let staged = entry_names(&self.path)?;
+        let written = written_files
+            .iter()
+            .filter_map(|file| file.strip_prefix(&self.path).ok()?.iter().next())
+            .map(OsString::from);
+        let removed = self.copied.difference(&staged).cloned();
+        let changed = written.chain(removed).collect::<BTreeSet<_>>();
+
+        fs::create_dir_all(root_path)
+            .with_context(|| format!("Failed to create directory {:?}", root_path))?;
+        for name in &changed {
+            replace(&self.path.join(name), &root_path.join(name))?;
+        }
+        info!(
+            from = ?self.path,
+            to = ?root_path,
+            count = changed.len(),
+            "Moved staged profiles into place"
+        );
+        Ok(())
+    }
+}
+
+impl Drop for StageDir {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.path) {
+            warn!(path = ?self.path, error = %e, "Failed to remove stage directory");
+        }
+    }
+}
+
+fn entry_names(dir: &Path) -> Result<BTreeSet<OsString>> {
+    let entries =
+        fs::read_dir(dir).with_context(|| format!("Failed to read directory {:?}", dir))?;
+    entries
+        .map(|entry| Ok(entry?.file_name()))
+        .collect::<std::io::Result<_>>()
+        .with_context(|| format!("Failed to read directory {:?}", dir))
+}
+
+/// Swaps `live` for `staged` (or removes `live` if there's nothing staged). The old version is
+/// renamed out of the way first, so that it can be restored if the staged one can't be moved.
+fn replace(staged: &Path, live: &Path) -> Result<()> {
+    let mut backup_name = OsString::from(".");
+    backup_name.push(live.file_name().unwrap_or_default());
+    backup_name.push(".old");
+    let backup = live.with_file_name(backup_name);
+
+    let had_live = live.exists();
+    if had_live {
+        fs::rename(live, &backup)
+            .with_context(|| format!("Failed to move {:?} out of the way", live))?;
+    }
+
+    if staged.exists() {
+        if let Err(e) = move_path(staged, live) {
+            if had_live {
+                let _ = fs::rename(&backup, live);
+            }
+            return Err(e);
+        }
+    }
+
+    if had_live {
+        remove_path(&backup)?;
+    }
+    Ok(())
+}
+
+/// Renames `from` to `to`, or copies it if renaming isn't possible (e.g., because the stage
+/// directory is on another filesystem)
+fn move_path(from: &Path, to: &Path) -> Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+
+    if from.is_dir() {
+        fs::create_dir_all(to).with_context(|| format!("Failed to create directory {:?}", to))?;
+        copy_contents(from, to)
+    } else {
+        fs::copy(from, to)
+            .map(drop)
+            .with_context(|| format!("Failed to copy {:?} to {:?}", from, to))
+    }
+}
+
+fn remove_path(path: &Path) -> Result<()> {
+    let result = if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    };
+    result.with_context(|| format!("Failed to remove {:?}", path))
+}
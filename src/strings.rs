@@ -0,0 +1,122 @@
+//! Helpers for emote names and labels, which can be in any script and contain emoji. They work on
+//! `char`s rather than bytes, and don't split a character from the combining marks that follow it.
+
+/// Uppercases the first character, e.g. `small9cm` to `Small9cm` or `élan` to `Élan`. Characters
+/// without an uppercase form, such as in Japanese names, are left as-is.
+pub fn capitalize_first(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Strips `prefix` from the start of `s`, ignoring case, and returns the rest
+pub fn strip_prefix_ci<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    let mut rest = s.char_indices();
+    for expected in prefix.chars() {
+        let (_, actual) = rest.next()?;
+        if !actual.to_lowercase().eq(expected.to_lowercase()) {
+            return None;
+        }
+    }
+
+    Some(rest.next().map_or("", |(index, _)| &s[index..]))
+}
+
+/// Shortens `s` to at most `max_chars` characters. If that would cut between a character and a
+/// combining mark (such as an accent, an emoji skin tone, or the rest of a joined emoji), the whole
+/// character is left out instead, so the result can be shorter.
+pub fn truncate_chars(s: &str, max_chars: usize) -> &str {
+    let mut end = match s.char_indices().nth(max_chars) {
+        Some((index, _)) => index,
+        None => return s,
+    };
+
+    while end > 0 {
+        let next = s[end..].chars().next();
+        if !next.is_some_and(is_combining) && !s[..end].ends_with(ZERO_WIDTH_JOINER) {
+            break;
+        }
+        end = s[..end].char_indices().last().map_or(0, |(index, _)| index);
+    }
+
+    &s[..end]
+}
+
+/// Joins emoji into a single one, e.g. in 👨‍👩‍👧
+const ZERO_WIDTH_JOINER: char = '\u{200d}';
+
+/// Whether the character modifies the one before it, instead of standing on its own
+fn is_combining(c: char) -> bool {
+    matches!(
+        c,
+        '\u{0300}'..='\u{036f}' // Combining diacritical marks
+            | '\u{1ab0}'..='\u{1aff}'
+            | '\u{1dc0}'..='\u{1dff}'
+            | '\u{20d0}'..='\u{20ff}' // Combining marks for symbols, such as the keycap in 1️⃣
+            | '\u{fe00}'..='\u{fe0f}' // Variation selectors, such as emoji presentation
+            | '\u{fe20}'..='\u{fe2f}'
+            | '\u{3099}'..='\u{309a}' // Japanese voiced sound marks
+            | '\u{1f3fb}'..='\u{1f3ff}' // Emoji skin tones
+            | '\u{e0020}'..='\u{e007f}' // Tags, used in flags such as 🏴󠁧󠁢󠁳󠁣󠁴󠁿
+            | ZERO_WIDTH_JOINER
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capitalize() {
+        assert_eq!(capitalize_first("small9cm"), "Small9cm");
+        assert_eq!(capitalize_first("Small9cm"), "Small9cm");
+        assert_eq!(capitalize_first("élan"), "Élan");
+        assert_eq!(capitalize_first("ñandú"), "Ñandú");
+        assert_eq!(capitalize_first("привет"), "Привет");
+        assert_eq!(capitalize_first("ぽむ"), "ぽむ");
+        assert_eq!(capitalize_first("😀yay"), "😀yay");
+        assert_eq!(capitalize_first("9cm"), "9cm");
+        assert_eq!(capitalize_first(" a"), " a");
+        assert_eq!(capitalize_first(""), "");
+    }
+
+    #[test]
+    fn strip_prefix_ignoring_case() {
+        assert_eq!(strip_prefix_ci("pomuSmall9cm", "POMU"), Some("Small9cm"));
+        assert_eq!(strip_prefix_ci("ÉlanYay", "élan"), Some("Yay"));
+        assert_eq!(strip_prefix_ci("ПриветHi", "привет"), Some("Hi"));
+        assert_eq!(strip_prefix_ci("ぽむHi", "ぽむ"), Some("Hi"));
+        assert_eq!(strip_prefix_ci("😀yay", "😀"), Some("yay"));
+        assert_eq!(strip_prefix_ci("pomu", "pomu"), Some(""));
+        assert_eq!(strip_prefix_ci("anything", ""), Some("anything"));
+        assert_eq!(strip_prefix_ci("pom", "pomu"), None);
+        assert_eq!(strip_prefix_ci("ぽむ", "ぽ "), None);
+        assert_eq!(strip_prefix_ci("élan", "e"), None);
+    }
+
+    #[test]
+    fn truncate() {
+        assert_eq!(truncate_chars("small9cm", 5), "small");
+        assert_eq!(truncate_chars("small9cm", 8), "small9cm");
+        assert_eq!(truncate_chars("small9cm", 20), "small9cm");
+        assert_eq!(truncate_chars("small9cm", 0), "");
+        assert_eq!(truncate_chars("ぽむぽむぽむ", 4), "ぽむぽむ");
+        assert_eq!(truncate_chars("привет", 3), "при");
+        assert_eq!(truncate_chars("😀😃😄", 2), "😀😃");
+
+        // Combining accents stay with their letter
+        assert_eq!(truncate_chars("cafe\u{301}s", 4), "caf");
+        assert_eq!(truncate_chars("cafe\u{301}s", 5), "cafe\u{301}");
+        assert_eq!(truncate_chars("\u{304b}\u{3099}\u{304b}", 1), "");
+
+        // Emoji with a skin tone, a variation selector, or joined by zero-width joiners aren't split
+        assert_eq!(truncate_chars("hi👋🏽", 3), "hi");
+        assert_eq!(truncate_chars("a\u{2764}\u{fe0f}", 2), "a");
+        let family = "👨\u{200d}👩\u{200d}👧";
+        assert_eq!(truncate_chars(family, 1), "");
+        assert_eq!(truncate_chars(family, 3), "");
+        assert_eq!(truncate_chars(&format!("{}x", family), 5), family);
+    }
+}
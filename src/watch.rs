@@ -0,0 +1,176 @@
+use color_eyre::eyre::{bail, Result, WrapErr};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+use tracing::{debug, info, warn};
+
+/// How long to wait after a change before regenerating, so that an editor saving a file in several
+/// steps (or a browser saving a page and its assets) only triggers one run
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Watches the input files for `--watch`. The directories containing them are watched rather than
+/// the files themselves, since editors often save by replacing the file.
+pub struct FileWatcher {
+    paths: Vec<PathBuf>,
+    events: UnboundedReceiver<notify::Result<Event>>,
+    debouncer: Debouncer,
+    // Stops watching when dropped
+    _watcher: RecommendedWatcher,
+}
+
+impl FileWatcher {
+    pub fn new(paths: &[PathBuf]) -> Result<Self> {
+        let paths = paths
+            .iter()
+            .map(|path| {
+                std::fs::canonicalize(path)
+                    .with_context(|| format!("Failed to watch file {:?}", path))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let (sender, events) = unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            // The receiver is only dropped along with the watcher
+            let _ = sender.send(event);
+        })
+        .context("Failed to start file watcher")?;
+
+        let mut dirs = paths
+            .iter()
+            .filter_map(|path| path.parent())
+            .collect::<Vec<_>>();
+        dirs.sort();
+        dirs.dedup();
+        for dir in dirs {
+            watcher
+                .watch(dir, RecursiveMode::NonRecursive)
+                .with_context(|| format!("Failed to watch directory {:?}", dir))?;
+        }
+
+        info!(paths = ?paths, "Watching for changes");
+        Ok(Self {
+            paths,
+            events,
+            debouncer: Debouncer::new(DEBOUNCE),
+            _watcher: watcher,
+        })
+    }
+
+    /// Waits until one of the files has changed, and then hasn't changed again for a moment
+    pub async fn changed(&mut self) -> Result<()> {
+        loop {
+            let event = match self.debouncer.deadline() {
+                Some(deadline) => {
+                    let deadline = tokio::time::Instant::from_std(deadline);
+                    match tokio::time::timeout_at(deadline, self.events.recv()).await {
+                        Ok(event) => event,
+                        Err(_) if self.debouncer.fire(Instant::now()) => return Ok(()),
+                        Err(_) => continue,
+                    }
+                }
+                None => self.events.recv().await,
+            };
+
+            match event {
+                Some(Ok(event)) if is_relevant(&event, &self.paths) => {
+                    debug!(?event, "Input file changed");
+                    self.debouncer.event(Instant::now());
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => warn!(error = %e, "Error from file watcher"),
+                None => bail!("File watcher stopped unexpectedly"),
+            }
+        }
+    }
+}
+
+/// Whether the event changed the contents of one of the watched files
+fn is_relevant(event: &Event, paths: &[PathBuf]) -> bool {
+    let is_change = matches!(
+        event.kind,
+        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Any
+    );
+    is_change && event.paths.iter().any(|path| paths.contains(path))
+}
+
+/// Delays a trigger until there haven't been any events for `delay`
+#[derive(Debug)]
+struct Debouncer {
+    delay: Duration,
+    deadline: Option<Instant>,
+}
+
+impl Debouncer {
+    fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            deadline: None,
+        }
+    }
+
+    /// Records an event, restarting the delay
+    fn event(&mut self, now: Instant) {
+        self.deadline = Some(now + self.delay);
+    }
+
+    /// When the trigger will fire, if there have been any events since it last fired
+    fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Whether the delay has passed since the last event. Fires once per group of events.
+    fn fire(&mut self, now: Instant) -> bool {
+        match self.deadline {
+            Some(deadline) if deadline <= now => {
+                self.deadline = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{AccessKind, CreateKind, DataChange, ModifyKind};
+
+    #[test]
+    fn debounce_file_changes() {
+        let html = PathBuf::from("/pages/memberships.html");
+        let paths = vec![html.clone()];
+        let modify = Event::new(EventKind::Modify(ModifyKind::Data(DataChange::Content)));
+
+        assert!(is_relevant(&modify.clone().add_path(html.clone()), &paths));
+        assert!(is_relevant(
+            &Event::new(EventKind::Create(CreateKind::File)).add_path(html.clone()),
+            &paths
+        ));
+        assert!(!is_relevant(
+            &modify.clone().add_path("/pages/other.html".into()),
+            &paths
+        ));
+        assert!(!is_relevant(
+            &Event::new(EventKind::Access(AccessKind::Any)).add_path(html),
+            &paths
+        ));
+
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        let mut debouncer = Debouncer::new(Duration::from_millis(500));
+        assert_eq!(debouncer.deadline(), None);
+        assert!(!debouncer.fire(ms(0)));
+
+        // Each change restarts the delay
+        debouncer.event(ms(0));
+        debouncer.event(ms(300));
+        assert_eq!(debouncer.deadline(), Some(ms(800)));
+        assert!(!debouncer.fire(ms(600)));
+
+        // It fires once, after the last change
+        assert!(debouncer.fire(ms(800)));
+        assert!(!debouncer.fire(ms(1000)));
+        assert_eq!(debouncer.deadline(), None);
+    }
+}
@@ -1,23 +1,159 @@
+use crate::error::{Error, NoEmotesReason};
 use crate::profile::Emote;
-use color_eyre::eyre::{bail, ContextCompat, Result, WrapErr};
+use color_eyre::eyre::bail;
 use serde_json::Value;
+use std::str::FromStr;
+use tracing::warn;
 
-pub fn parse_emotes(html: &str) -> Result<Vec<Emote>> {
-    const START: &'static str = "ytInitialData = ";
+/// Finds the text of the `ytInitialData` JSON object embedded in the HTML of a YouTube page,
+/// without parsing it. The end of the object is found by matching braces, so it doesn't matter
+/// what follows it, falling back to the first `;</script>` if the braces don't match.
+pub fn find_initial_data(html: &str) -> Result<&str, Error> {
+    const START: &str = "ytInitialData";
 
-    let start_index = html.find(START).wrap_err("failed to find ytInitialData")? + START.len();
-    let (json_str, _) = html[start_index..]
+    // Other scripts can mention it too (e.g., `if (window.ytInitialData)`), so only an assignment
+    // counts, such as `ytInitialData = {` or `window["ytInitialData"]={`
+    let rest = html
+        .match_indices(START)
+        .find_map(|(index, _)| assigned_value(&html[index + START.len()..]))
+        .ok_or_else(|| Error::html_parse("failed to find ytInitialData"))?;
+
+    if let Some(len) = balanced_object_len(rest) {
+        return Ok(&rest[..len]);
+    }
+
+    let (json_str, _) = rest
         .split_once(";</script>")
-        .wrap_err("failed to find ending semicolon")?;
+        .ok_or_else(|| Error::html_parse("failed to find ending semicolon"))?;
+
+    Ok(json_str)
+}
+
+/// The rest of the string after the `=` (and any whitespace), if it starts by assigning to the
+/// name before it, optionally with the closing quote and bracket of `window["name"]`
+fn assigned_value(s: &str) -> Option<&str> {
+    let s = s.strip_prefix(|c| c == '"' || c == '\'').unwrap_or(s);
+    let s = s.strip_prefix(']').unwrap_or(s);
+    let s = s.trim_start().strip_prefix('=')?;
+    if s.starts_with('=') {
+        return None; // A comparison, not an assignment
+    }
+    Some(s.trim_start())
+}
+
+/// Length of the JSON object at the start of the string, up to and including its closing brace.
+/// Braces inside strings are skipped. Returns `None` if the string doesn't start with an object or
+/// it isn't closed.
+fn balanced_object_len(s: &str) -> Option<usize> {
+    if !s.starts_with('{') {
+        return None;
+    }
+
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (index, c) in s.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+
+        match c {
+            '"' => in_string = true,
+            '{' | '[' => depth += 1,
+            '}' | ']' => {
+                depth = depth.checked_sub(1)?;
+                if depth == 0 {
+                    return Some(index + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Extracts the `ytInitialData` JSON object embedded in the HTML of a YouTube page
+pub fn parse_initial_data(html: &str) -> Result<Value, Error> {
+    let json_str = find_initial_data(html)?;
+    serde_json::from_str::<Value>(json_str).map_err(|e| Error::HtmlParseFailed {
+        message: "failed to parse ytInitialData JSON",
+        source: Some(e),
+    })
+}
+
+/// Finds the name of the channel in `ytInitialData`, if present
+pub fn parse_channel_name(json: &Value) -> Option<String> {
+    const POINTERS: &[&str] = &[
+        "/metadata/channelMetadataRenderer/title",
+        "/header/c4TabbedHeaderRenderer/title",
+        "/microformat/microformatDataRenderer/title",
+    ];
 
-    let json =
-        serde_json::from_str::<Value>(json_str).wrap_err("failed to parse ytInitialData JSON")?;
+    POINTERS
+        .iter()
+        .filter_map(|pointer| json.pointer(pointer)?.as_str())
+        .map(|title| title.trim())
+        .find(|title| !title.is_empty())
+        .map(|title| title.to_owned())
+}
+
+/// Which of an emote's thumbnails to download
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ThumbnailSelect {
+    First,
+    /// The one with the most pixels according to its `width` and `height`, or the last one if the
+    /// sizes aren't listed
+    Largest,
+}
+
+impl FromStr for ThumbnailSelect {
+    type Err = color_eyre::eyre::Error;
+
+    fn from_str(s: &str) -> color_eyre::eyre::Result<Self> {
+        match s.to_lowercase().as_ref() {
+            "first" => Ok(ThumbnailSelect::First),
+            "largest" => Ok(ThumbnailSelect::Largest),
+            other => bail!("Unknown thumbnail selection {}", other),
+        }
+    }
+}
+
+impl ThumbnailSelect {
+    fn select<'a>(&self, thumbnails: &'a [Value]) -> Option<&'a Value> {
+        match self {
+            Self::First => thumbnails.first(),
+            Self::Largest => {
+                let pixels = |thumbnail: &Value| {
+                    let width = thumbnail.get("width")?.as_u64()?;
+                    let height = thumbnail.get("height")?.as_u64()?;
+                    Some(width * height)
+                };
+
+                thumbnails
+                    .iter()
+                    .filter_map(|thumbnail| Some((pixels(thumbnail)?, thumbnail)))
+                    .max_by_key(|(pixels, _)| *pixels)
+                    .map(|(_, thumbnail)| thumbnail)
+                    .or_else(|| thumbnails.last())
+            }
+        }
+    }
+}
 
+pub fn parse_emotes(json: &Value, thumbnail_select: ThumbnailSelect) -> Result<Vec<Emote>, Error> {
     let tabs = json
         .pointer("/contents/twoColumnBrowseResultsRenderer/tabs")
-        .wrap_err("failed to find tab data in ytInitialData")?
+        .ok_or_else(|| Error::html_parse("failed to find tab data in ytInitialData"))?
         .as_array()
-        .wrap_err("failed to parse tabs as array")?;
+        .ok_or_else(|| Error::html_parse("failed to parse tabs as array"))?;
 
     let emotes = tabs
         .iter()
@@ -27,45 +163,550 @@ pub fn parse_emotes(html: &str) -> Result<Vec<Emote>> {
                 .into_iter()
                 .flat_map(|value| value.as_array().into_iter().flatten())
         })
-        .flat_map(|value| {
-            value
-                .pointer("/sponsorshipsExpandablePerksRenderer/expandableItems")
-                .into_iter()
-                .flat_map(|value| value.as_array().into_iter().flatten())
-        })
-        .flat_map(|value| {
-            value
-                .pointer("/sponsorshipsPerkRenderer/images")
+        .filter_map(|value| value.get("sponsorshipsExpandablePerksRenderer"))
+        .flat_map(|perks| {
+            let tier = perks.get("title").and_then(text);
+            perks
+                .get("expandableItems")
                 .into_iter()
                 .flat_map(|value| value.as_array().into_iter().flatten())
+                .flat_map(|value| {
+                    value
+                        .pointer("/sponsorshipsPerkRenderer/images")
+                        .into_iter()
+                        .flat_map(|value| value.as_array().into_iter().flatten())
+                })
+                .map(move |value| (tier.clone(), value))
         })
-        .map(|value| {
+        .map(|(tier, value)| {
             let name = value
                 .pointer("/accessibility/accessibilityData/label")
-                .wrap_err("failed to find label")?
+                .ok_or_else(|| Error::html_parse("failed to find label"))?
                 .as_str()
-                .wrap_err("failed to parse label as string")?
+                .ok_or_else(|| Error::html_parse("failed to parse label as string"))?
+                .trim()
                 .to_owned();
 
-            let full_url = value
-                .pointer("/thumbnails/0/url")
-                .wrap_err("failed to find url")?
+            let thumbnail = value
+                .get("thumbnails")
+                .and_then(|thumbnails| thumbnails.as_array())
+                .and_then(|thumbnails| thumbnail_select.select(thumbnails));
+            let full_url = thumbnail
+                .and_then(|thumbnail| thumbnail.get("url"))
+                .ok_or_else(|| Error::html_parse("failed to find url"))?
                 .as_str()
-                .wrap_err("failed to parse url as string")?;
+                .ok_or_else(|| Error::html_parse("failed to parse url as string"))?;
 
-            let url = if let Some((first, _)) = full_url.split_once('=') {
-                first.to_owned()
-            } else {
-                full_url.to_owned()
-            };
+            let url = strip_size_suffix(full_url).to_owned();
 
-            Ok(Emote { name, url })
+            let id = value
+                .get("emojiId")
+                .and_then(|id| id.as_str())
+                .map(str::trim)
+                .filter(|id| !id.is_empty())
+                .map(str::to_owned);
+
+            Ok(Emote {
+                name,
+                url,
+                id,
+                tier,
+                ..Default::default()
+            })
+        })
+        .collect::<Result<Vec<Emote>, Error>>()?;
+
+    // Some malformed entries have an empty label, which would give a broken code like `:_pomu:`
+    let emotes = emotes
+        .into_iter()
+        .filter(|emote| {
+            if emote.name.is_empty() {
+                warn!(url = %emote.url, "Skipping emote without a name");
+            }
+            !emote.name.is_empty()
         })
-        .collect::<Result<Vec<Emote>>>()?;
+        .collect::<Vec<_>>();
+
+    if !emotes.is_empty() {
+        return Ok(emotes);
+    }
+
+    let reason = if contains_key(json, "signInEndpoint") {
+        NoEmotesReason::LoggedOut
+    } else if !contains_key(json, "sponsorshipsExpandablePerksRenderer") {
+        NoEmotesReason::NoPerks
+    } else {
+        NoEmotesReason::NotInPerks
+    };
 
-    if emotes.is_empty() {
-        bail!("failed to find emotes in JSON")
+    Err(Error::NoEmotesFound { reason })
+}
+
+/// Removes Google's image sizing options (e.g., the `=s48-c-k-nd` in
+/// `https://yt3.ggpht.com/abc=s48-c-k-nd`) so that the full size image is downloaded. URLs that
+/// don't end with sizing options, such as ones with query parameters, are returned unchanged.
+fn strip_size_suffix(url: &str) -> &str {
+    let (base, options) = match url.rsplit_once('=') {
+        Some(split) => split,
+        None => return url,
+    };
+
+    if base.contains('?') {
+        return url;
+    }
+
+    let is_size = |option: &str| {
+        let mut chars = option.chars();
+        matches!(chars.next(), Some('s' | 'w' | 'h')) && chars.as_str().parse::<u32>().is_ok()
+    };
+
+    let mut options = options.split('-');
+    let is_sizing = options.next().is_some_and(is_size)
+        && options
+            .all(|option| !option.is_empty() && option.chars().all(|c| c.is_ascii_alphanumeric()));
+
+    if is_sizing {
+        base
     } else {
-        Ok(emotes)
+        url
+    }
+}
+
+/// Reads a YouTube text object, which is either `{ "simpleText": "..." }` or
+/// `{ "runs": [{ "text": "..." }, ...] }`
+fn text(value: &Value) -> Option<String> {
+    let text = match value.get("simpleText") {
+        Some(text) => text.as_str()?.to_owned(),
+        None => value
+            .get("runs")?
+            .as_array()?
+            .iter()
+            .filter_map(|run| run.get("text")?.as_str())
+            .collect(),
+    };
+
+    let text = text.trim();
+    if text.is_empty() {
+        None
+    } else {
+        Some(text.to_owned())
+    }
+}
+
+/// Checks whether the tab contents include a continuation, meaning that YouTube would load more
+/// content (possibly more perks) on demand, which isn't part of the saved HTML
+pub fn has_continuation(json: &Value) -> bool {
+    const KEYS: &[&str] = &[
+        "continuationItemRenderer",
+        "nextContinuationData",
+        "continuations",
+    ];
+
+    json.pointer("/contents/twoColumnBrowseResultsRenderer/tabs")
+        .map(|tabs| KEYS.iter().any(|key| contains_key(tabs, key)))
+        .unwrap_or(false)
+}
+
+/// Recursively checks whether any object in the JSON has the given key
+fn contains_key(json: &Value, key: &str) -> bool {
+    match json {
+        Value::Object(map) => map.contains_key(key) || map.values().any(|v| contains_key(v, key)),
+        Value::Array(values) => values.iter().any(|v| contains_key(v, key)),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::{ActionOptions, NameCase};
+    use color_eyre::eyre::Result;
+    use serde_json::json;
+
+    fn html(json: &Value) -> String {
+        format!(
+            "<html><script>var ytInitialData = {};</script></html>",
+            json
+        )
+    }
+
+    fn error_message(json: &Value) -> String {
+        match parse_emotes(json, ThumbnailSelect::Largest) {
+            Ok(emotes) => panic!("expected error, got {:?}", emotes),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn logged_out_page() -> Result<()> {
+        let page = html(&json!({
+            "contents": {
+                "twoColumnBrowseResultsRenderer": {
+                    "tabs": [{ "tabRenderer": { "content": { "sectionListRenderer": {
+                        "contents": [{ "itemSectionRenderer": { "contents": [] } }]
+                    } } } }]
+                }
+            },
+            "topbar": {
+                "desktopTopbarRenderer": {
+                    "topbarButtons": [{
+                        "buttonRenderer": {
+                            "text": { "runs": [{ "text": "Sign in" }] },
+                            "navigationEndpoint": { "signInEndpoint": { "idamTag": "65620" } }
+                        }
+                    }]
+                }
+            }
+        }));
+
+        let json = parse_initial_data(&page)?;
+        assert!(error_message(&json).contains("logged out"));
+        assert!(matches!(
+            parse_emotes(&json, ThumbnailSelect::Largest),
+            Err(Error::NoEmotesFound {
+                reason: NoEmotesReason::LoggedOut
+            })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_html() {
+        let error = parse_initial_data("<html></html>").unwrap_err();
+        assert!(matches!(error, Error::HtmlParseFailed { source: None, .. }));
+        assert_eq!(error.to_string(), "failed to find ytInitialData");
+
+        let error = parse_initial_data("<script>ytInitialData = {;</script>").unwrap_err();
+        assert!(matches!(
+            error,
+            Error::HtmlParseFailed {
+                source: Some(_),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn initial_data_endings() -> Result<()> {
+        let json = r#"{"a":{"b":"};</script>"},"c":["{",  "\\\"}"]}"#;
+        let expected = serde_json::from_str::<Value>(json)?;
+
+        for html in [
+            format!("<script>var ytInitialData = {};</script>", json),
+            format!("<script>var ytInitialData = {} ;\n</script>", json),
+            format!("<script>var ytInitialData={}\n</script>", json),
+            format!(r#"<script>window["ytInitialData"] = {};"#, json),
+        ] {
+            assert_eq!(find_initial_data(&html)?, json, "{}", html);
+            assert_eq!(parse_initial_data(&html)?, expected);
+        }
+
+        // Mentions of it before the assignment are skipped
+        let html = format!(
+            "<script>if (window.ytInitialData) {{ x = {{}}; }}</script>\
+            <script>if (window['ytInitialData'] == null) {{}}</script>\
+            <script>var ytInitialData = {};</script>",
+            json
+        );
+        assert_eq!(find_initial_data(&html)?, json);
+        assert!(find_initial_data("<script>window.ytInitialData;</script>").is_err());
+
+        // Unbalanced braces fall back to the end of the script
+        let html = "<script>var ytInitialData = {\"a\": 1;</script>";
+        assert_eq!(find_initial_data(html)?, "{\"a\": 1");
+
+        Ok(())
+    }
+
+    #[test]
+    fn no_membership_perks() {
+        let json = json!({
+            "contents": { "twoColumnBrowseResultsRenderer": { "tabs": [] } }
+        });
+        assert!(error_message(&json).contains("doesn't contain any membership perks"));
+
+        let json = json!({
+            "contents": { "twoColumnBrowseResultsRenderer": { "tabs": [{ "tabRenderer": {
+                "content": { "sectionListRenderer": { "contents": [{
+                    "sponsorshipsExpandablePerksRenderer": { "expandableItems": [] }
+                }] } }
+            } }] } }
+        });
+        assert_eq!(error_message(&json), "failed to find emotes in JSON");
+    }
+
+    #[test]
+    fn emote_tiers() -> Result<()> {
+        let perks = |title: Value, label: &str| {
+            json!({ "sponsorshipsExpandablePerksRenderer": {
+                "title": title,
+                "expandableItems": [{ "sponsorshipsPerkRenderer": { "images": [{
+                    "thumbnails": [{ "url": format!("https://yt3.ggpht.com/{}=s48", label) }],
+                    "accessibility": { "accessibilityData": { "label": label } }
+                }] } }]
+            } })
+        };
+
+        let json = json!({
+            "contents": { "twoColumnBrowseResultsRenderer": { "tabs": [{ "tabRenderer": {
+                "content": { "sectionListRenderer": { "contents": [
+                    perks(json!({ "simpleText": "Member" }), "small9cm"),
+                    perks(json!({ "runs": [{ "text": "Super " }, { "text": "Member" }] }), "pog"),
+                    perks(json!({ "simpleText": " " }), "untiered"),
+                ] } }
+            } }] } }
+        });
+
+        let tiers = parse_emotes(&json, ThumbnailSelect::Largest)?
+            .into_iter()
+            .map(|emote| (emote.name, emote.tier))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            tiers,
+            vec![
+                ("small9cm".to_owned(), Some("Member".to_owned())),
+                ("pog".to_owned(), Some("Super Member".to_owned())),
+                ("untiered".to_owned(), None),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn emote_ids() -> Result<()> {
+        let json = json!({
+            "contents": { "twoColumnBrowseResultsRenderer": { "tabs": [{ "tabRenderer": {
+                "content": { "sectionListRenderer": { "contents": [{
+                    "sponsorshipsExpandablePerksRenderer": {
+                        "title": { "simpleText": "Member" },
+                        "expandableItems": [{ "sponsorshipsPerkRenderer": { "images": [
+                            {
+                                "emojiId": "UCJ46YTYBQVXsfsp8-HryoUA/3bkUYpGHL4WMNUTB4DfM6fUA",
+                                "thumbnails": [{ "url": "https://yt3.ggpht.com/a=s48" }],
+                                "accessibility": { "accessibilityData": { "label": "small9cm" } }
+                            },
+                            {
+                                "thumbnails": [{ "url": "https://yt3.ggpht.com/b=s48" }],
+                                "accessibility": { "accessibilityData": { "label": "pog" } }
+                            },
+                            {
+                                "emojiId": " ",
+                                "thumbnails": [{ "url": "https://yt3.ggpht.com/c=s48" }],
+                                "accessibility": { "accessibilityData": { "label": "blank" } }
+                            },
+                        ] } }]
+                    }
+                }] } }
+            } }] } }
+        });
+
+        let emotes = parse_emotes(&json, ThumbnailSelect::Largest)?;
+        let ids = emotes
+            .iter()
+            .map(|emote| (emote.name.as_str(), emote.id.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ids,
+            vec![
+                (
+                    "small9cm",
+                    Some("UCJ46YTYBQVXsfsp8-HryoUA/3bkUYpGHL4WMNUTB4DfM6fUA")
+                ),
+                ("pog", None),
+                ("blank", None),
+            ]
+        );
+        assert!(emotes
+            .iter()
+            .all(|emote| emote.tier.as_deref() == Some("Member")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn empty_emote_names() -> Result<()> {
+        let image = |label: &str| {
+            json!({
+                "thumbnails": [{ "url": "https://yt3.ggpht.com/abc=s48" }],
+                "accessibility": { "accessibilityData": { "label": label } }
+            })
+        };
+        let json = |images: Vec<Value>| {
+            json!({
+                "contents": { "twoColumnBrowseResultsRenderer": { "tabs": [{ "tabRenderer": {
+                    "content": { "sectionListRenderer": { "contents": [{
+                        "sponsorshipsExpandablePerksRenderer": { "expandableItems": [{
+                            "sponsorshipsPerkRenderer": { "images": images }
+                        }] }
+                    }] } }
+                } }] } }
+            })
+        };
+
+        let emotes = parse_emotes(
+            &json(vec![
+                image(""),
+                image(" small9cm "),
+                image(" \t "),
+                image("pog"),
+            ]),
+            ThumbnailSelect::Largest,
+        )?;
+        let names = emotes.iter().map(|e| e.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["small9cm", "pog"]);
+        assert_eq!(
+            emotes[0].code(&ActionOptions {
+                prefix: "pomu".into(),
+                name_case: NameCase::CapitalizeFirst,
+                ..Default::default()
+            }),
+            ":_pomuSmall9cm:"
+        );
+
+        // A page where no emote has a name is treated like one without emotes
+        let error = parse_emotes(&json(vec![image(" ")]), ThumbnailSelect::Largest).unwrap_err();
+        assert!(matches!(error, Error::NoEmotesFound { .. }), "{}", error);
+
+        // Capitalizing names that don't start with an ASCII letter doesn't panic
+        for name in ["", " ", "ぽむ"] {
+            assert_eq!(NameCase::CapitalizeFirst.apply(name), name);
+        }
+        assert_eq!(NameCase::CapitalizeFirst.apply("éa"), "Éa");
+
+        Ok(())
+    }
+
+    #[test]
+    fn thumbnail_selection() -> Result<()> {
+        let json = |thumbnails: Value| {
+            json!({
+                "contents": { "twoColumnBrowseResultsRenderer": { "tabs": [{ "tabRenderer": {
+                    "content": { "sectionListRenderer": { "contents": [{
+                        "sponsorshipsExpandablePerksRenderer": { "expandableItems": [{
+                            "sponsorshipsPerkRenderer": { "images": [{
+                                "thumbnails": thumbnails,
+                                "accessibility": { "accessibilityData": { "label": "small9cm" } }
+                            }] }
+                        }] }
+                    }] } }
+                } }] } }
+            })
+        };
+        let url = |json: &Value, select: ThumbnailSelect| -> Result<String> {
+            Ok(parse_emotes(json, select)?.remove(0).url)
+        };
+
+        let sized = json(json!([
+            { "url": "https://yt3.ggpht.com/a=s48", "width": 48, "height": 48 },
+            { "url": "https://yt3.ggpht.com/b=s384", "width": 384, "height": 384 },
+            { "url": "https://yt3.ggpht.com/c=s96", "width": 96, "height": 96 },
+        ]));
+        assert_eq!(
+            url(&sized, ThumbnailSelect::Largest)?,
+            "https://yt3.ggpht.com/b"
+        );
+        assert_eq!(
+            url(&sized, ThumbnailSelect::First)?,
+            "https://yt3.ggpht.com/a"
+        );
+
+        let without_sizes = json(json!([
+            { "url": "https://yt3.ggpht.com/a=s48" },
+            { "url": "https://yt3.ggpht.com/b=s96" },
+        ]));
+        assert_eq!(
+            url(&without_sizes, ThumbnailSelect::Largest)?,
+            "https://yt3.ggpht.com/b"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn size_suffix() {
+        assert_eq!(
+            strip_size_suffix("https://yt3.ggpht.com/abc123=s48"),
+            "https://yt3.ggpht.com/abc123"
+        );
+        assert_eq!(
+            strip_size_suffix("https://yt3.ggpht.com/abc123=w48-h48-c-k-nd"),
+            "https://yt3.ggpht.com/abc123"
+        );
+        assert_eq!(
+            strip_size_suffix("https://yt3.ggpht.com/abc=def==s48-c-k-nd"),
+            "https://yt3.ggpht.com/abc=def="
+        );
+        assert_eq!(
+            strip_size_suffix("https://yt3.ggpht.com/abc=def=="),
+            "https://yt3.ggpht.com/abc=def=="
+        );
+        assert_eq!(
+            strip_size_suffix("https://example.com/emote.png?size=s48&sig=abc"),
+            "https://example.com/emote.png?size=s48&sig=abc"
+        );
+        assert_eq!(
+            strip_size_suffix("https://example.com/emote.png?size=s48"),
+            "https://example.com/emote.png?size=s48"
+        );
+        assert_eq!(
+            strip_size_suffix("https://example.com/emote.png"),
+            "https://example.com/emote.png"
+        );
+    }
+
+    #[test]
+    fn continuation_marker() {
+        let section = |contents: Value| {
+            json!({
+                "contents": { "twoColumnBrowseResultsRenderer": { "tabs": [{ "tabRenderer": {
+                    "content": { "sectionListRenderer": { "contents": contents } }
+                } }] } }
+            })
+        };
+
+        let json = section(json!([
+            { "sponsorshipsExpandablePerksRenderer": { "expandableItems": [] } },
+            { "continuationItemRenderer": {
+                "continuationEndpoint": { "continuationCommand": { "token": "abc" } }
+            } }
+        ]));
+        assert!(has_continuation(&json));
+
+        let json = section(json!([
+            { "sponsorshipsExpandablePerksRenderer": { "expandableItems": [] } }
+        ]));
+        assert!(!has_continuation(&json));
+
+        let json = json!({ "onResponseReceivedActions": [{ "continuationItemRenderer": {} }] });
+        assert!(!has_continuation(&json));
+    }
+
+    #[test]
+    fn channel_name() -> Result<()> {
+        let page = html(&json!({
+            "metadata": {
+                "channelMetadataRenderer": { "title": "Pomu Rainpuff Ch. NIJISANJI EN" }
+            },
+            "header": {
+                "c4TabbedHeaderRenderer": { "title": "Header title" }
+            }
+        }));
+
+        let json = parse_initial_data(&page)?;
+        assert_eq!(
+            parse_channel_name(&json).as_deref(),
+            Some("Pomu Rainpuff Ch. NIJISANJI EN")
+        );
+
+        let json = json!({ "header": { "c4TabbedHeaderRenderer": { "title": "Header title" } } });
+        assert_eq!(parse_channel_name(&json).as_deref(), Some("Header title"));
+
+        let json = json!({ "metadata": { "channelMetadataRenderer": { "title": " " } } });
+        assert_eq!(parse_channel_name(&json), None);
+
+        Ok(())
     }
 }